@@ -0,0 +1,184 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Compare the span list links kept by each node (`next` & `prev`)
+//! against rbtree parent links, for the neighbor lookups the list is used for.
+//!
+//! Both are measured on the same balanced tree of spans stored by index (as `RangeTree` does):
+//! - stepping through every span in order.
+//! - finding the span containing a value, then both of its neighbors.
+//!
+//! Run with: `cargo bench --bench node_links`.
+
+use std::hint::black_box;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+const NULL: u32 = u32::MAX;
+
+struct Node {
+    range: [u64; 2],
+    left: u32,
+    right: u32,
+    parent: u32,
+    next: u32,
+    prev: u32,
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    root: u32,
+}
+
+impl Tree {
+    // Spans of 4 values separated by a single taken value.
+    fn new(len: usize) -> Tree {
+        let mut nodes: Vec<Node> = (0..len).map(|i| {
+            let value = i as u64 * 5;
+            Node {
+                range: [value, value + 3],
+                left: NULL,
+                right: NULL,
+                parent: NULL,
+                next: if i + 1 < len { i as u32 + 1 } else { NULL },
+                prev: if i > 0 { i as u32 - 1 } else { NULL },
+            }
+        }).collect();
+        let root = Tree::link_balanced(&mut nodes, 0, len, NULL);
+        Tree { nodes, root }
+    }
+
+    fn link_balanced(nodes: &mut [Node], start: usize, end: usize, parent: u32) -> u32 {
+        if start == end {
+            return NULL;
+        }
+        let mid = (start + end) / 2;
+        nodes[mid].parent = parent;
+        nodes[mid].left = Tree::link_balanced(nodes, start, mid, mid as u32);
+        nodes[mid].right = Tree::link_balanced(nodes, mid + 1, end, mid as u32);
+        mid as u32
+    }
+
+    fn find(&self, value: u64) -> u32 {
+        let mut node = self.root;
+        while node != NULL {
+            let n = &self.nodes[node as usize];
+            if value < n.range[0] {
+                node = n.left;
+            } else if value > n.range[1] {
+                node = n.right;
+            } else {
+                break;
+            }
+        }
+        node
+    }
+
+    fn first(&self) -> u32 {
+        let mut node = self.root;
+        while self.nodes[node as usize].left != NULL {
+            node = self.nodes[node as usize].left;
+        }
+        node
+    }
+
+    fn next_by_parent(&self, mut node: u32) -> u32 {
+        let n = &self.nodes[node as usize];
+        if n.right != NULL {
+            node = n.right;
+            while self.nodes[node as usize].left != NULL {
+                node = self.nodes[node as usize].left;
+            }
+            return node;
+        }
+        let mut parent = n.parent;
+        while parent != NULL && self.nodes[parent as usize].right == node {
+            node = parent;
+            parent = self.nodes[node as usize].parent;
+        }
+        parent
+    }
+
+    fn prev_by_parent(&self, mut node: u32) -> u32 {
+        let n = &self.nodes[node as usize];
+        if n.left != NULL {
+            node = n.left;
+            while self.nodes[node as usize].right != NULL {
+                node = self.nodes[node as usize].right;
+            }
+            return node;
+        }
+        let mut parent = n.parent;
+        while parent != NULL && self.nodes[parent as usize].left == node {
+            node = parent;
+            parent = self.nodes[node as usize].parent;
+        }
+        parent
+    }
+}
+
+fn bench<F: FnMut() -> u64>(name: &str, ops: usize, mut f: F) {
+    // Repeat until the time is long enough to be stable, reporting the fastest run.
+    let mut best = Duration::MAX;
+    let time_start = Instant::now();
+    while time_start.elapsed() < Duration::from_millis(500) {
+        let t = Instant::now();
+        black_box(f());
+        best = best.min(t.elapsed());
+    }
+    println!("{:<32} {:>8.2} ns/op", name, best.as_nanos() as f64 / ops as f64);
+}
+
+fn main() {
+    for &len in &[1_000, 100_000, 1_000_000] {
+        let tree = Tree::new(len);
+        println!("{} spans:", len);
+
+        bench("  step (list links)", len, || {
+            let mut sum = 0;
+            let mut node = tree.first();
+            while node != NULL {
+                sum += tree.nodes[node as usize].range[0];
+                node = tree.nodes[node as usize].next;
+            }
+            sum
+        });
+        bench("  step (parent links)", len, || {
+            let mut sum = 0;
+            let mut node = tree.first();
+            while node != NULL {
+                sum += tree.nodes[node as usize].range[0];
+                node = tree.next_by_parent(node);
+            }
+            sum
+        });
+
+        // Values spread over all spans in a fixed pseudo-random order.
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let values: Vec<u64> = (0..10_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % len as u64) * 5 + 1
+        }).collect();
+
+        bench("  find & neighbors (list links)", values.len(), || {
+            let mut sum = 0;
+            for &value in &values {
+                let node = &tree.nodes[tree.find(value) as usize];
+                sum += u64::from(node.prev) + u64::from(node.next);
+            }
+            sum
+        });
+        bench("  find & neighbors (parent links)", values.len(), || {
+            let mut sum = 0;
+            for &value in &values {
+                let node = tree.find(value);
+                sum += u64::from(tree.prev_by_parent(node)) + u64::from(tree.next_by_parent(node));
+            }
+            sum
+        });
+    }
+}
@@ -0,0 +1,80 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Decode fuzzer input into operations, checking `RangeTree::validate` after each.
+
+use arbitrary::{
+    Arbitrary,
+    Unstructured,
+};
+use rangetree::{
+    Count,
+    RangeTree,
+    Step,
+};
+
+#[derive(Arbitrary, Debug)]
+enum Op<T> {
+    TryTake(T),
+    Retake(T),
+    TryRelease(T),
+    TakeAny,
+    TakeAnyMax,
+    TakeAnyFrom(T),
+    ReleaseAny,
+    TakeN(u8),
+    ReleaseMany(Vec<T>),
+    Invert,
+    GrowMax(T),
+    TryShrinkMax(T),
+    Clear(bool),
+}
+
+pub fn run<T>(data: &[u8])
+    where T: Ord + Step + Count + Copy + for<'a> Arbitrary<'a> + ::std::fmt::Debug
+{
+    let mut u = Unstructured::new(data);
+    let (bounds, full): ([T; 2], bool) = match u.arbitrary() {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    if bounds[0] > bounds[1] {
+        return;
+    }
+    let mut r: RangeTree<T> = RangeTree::new(bounds, full);
+    // decoding doesn't fail once the input runs out (zeroed values are used), so check the length.
+    while !u.is_empty() {
+        let op: Op<T> = match u.arbitrary() {
+            Ok(op) => op,
+            Err(_) => return,
+        };
+        let bounds = r.bounds();
+        let in_bounds = |v: &T| *v >= bounds[0] && *v <= bounds[1];
+        match op {
+            Op::TryTake(v) => { let _ = r.try_take(v); }
+            Op::Retake(v) => {
+                if in_bounds(&v) {
+                    r.retake(v);
+                }
+            }
+            Op::TryRelease(v) => { let _ = r.try_release(v); }
+            Op::TakeAny => { r.take_any(); }
+            Op::TakeAnyMax => { r.take_any_max(); }
+            Op::TakeAnyFrom(v) => { r.take_any_from(v); }
+            Op::ReleaseAny => { r.release_any(); }
+            Op::TakeN(n) => { r.take_n(n as usize); }
+            Op::ReleaseMany(values) => {
+                // only values which are taken, duplicates are ignored.
+                let values: Vec<T> = values.into_iter().filter(|v| in_bounds(v) && !r.has(*v)).collect();
+                r.release_many(&values);
+            }
+            Op::Invert => r.invert(),
+            Op::GrowMax(v) => { let _ = r.grow_max(v); }
+            Op::TryShrinkMax(v) => { let _ = r.try_shrink_max(v); }
+            Op::Clear(full) => r.clear(full),
+        }
+        if let Err(err) = r.validate() {
+            panic!("{:?} after {:?}", err, r);
+        }
+    }
+}
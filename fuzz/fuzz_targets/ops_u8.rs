@@ -0,0 +1,17 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Operation sequences on a `RangeTree<u8>`, small enough that values often reach the type limits.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate arbitrary;
+extern crate rangetree;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    common::run::<u8>(data);
+});
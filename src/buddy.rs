@@ -0,0 +1,127 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Power-of-two block allocation, see `BuddyAllocator`.
+
+use types::{
+    NodeId,
+    RType,
+};
+use {
+    Backend,
+    RangeMap,
+    RangeTree,
+    RangeTreeError,
+    count_offset_between as offset,
+};
+use rb;
+
+/// Allocator of blocks of `2^order` values, each aligned to its size
+/// (relative to the minimum of the range).
+///
+/// Released blocks are merged with their untaken neighbors by the tree,
+/// so there is no need to split & join buddies explicitly.
+pub struct BuddyAllocator<TOrd: RType> {
+    tree: RangeTree<TOrd>,
+    // the order of allocated blocks, adjacent blocks of the same order share a range
+    // (blocks are aligned, so the start of a block is found from the order).
+    blocks: RangeMap<TOrd, u32>,
+}
+
+impl<TOrd: RType> BuddyAllocator<TOrd> {
+    /// Create an allocator for blocks in the [minimum, maximum] range (inclusive).
+    pub fn new(
+        range: [TOrd; 2],
+    ) -> BuddyAllocator<TOrd> {
+        BuddyAllocator {
+            tree: RangeTree::new(range, false),
+            blocks: RangeMap::new(),
+        }
+    }
+
+    /// Return the tree of allocated (taken) values.
+    pub fn tree(
+        &self,
+    ) -> &RangeTree<TOrd> {
+        &self.tree
+    }
+
+    /// Allocate the lowest free block of `2^order` values,
+    /// returning its first value or `None` when there is no free block of this size.
+    pub fn alloc(
+        &mut self,
+        order: u32,
+    ) -> Option<TOrd> {
+        if order >= 128 {
+            return None;
+        }
+        let size: u128 = 1 << order;
+        let value_base = self.tree.range[0];
+        // spans smaller than the block can be skipped.
+        let mut node = if self.tree.backend == Backend::RbTree {
+            rb::get_first_with_count(&self.tree.nodes, self.tree.root, size)
+        } else {
+            self.tree.list.first
+        };
+        while !node.is_null() {
+            let range = self.tree.nodes[node].range;
+            let offset_max = offset(value_base, range[1]);
+            // round up to the block size, no block starts after an overflow.
+            let offset_min = match offset(value_base, range[0]).checked_add(size - 1) {
+                Some(offset_min) => offset_min & !(size - 1),
+                None => break,
+            };
+            if offset_min <= offset_max && offset_max - offset_min >= size - 1 {
+                let value = TOrd::count_offset(value_base, offset_min);
+                let block = [value, TOrd::count_offset(value, size - 1)];
+                self.alloc_impl(block, order, node);
+                return Some(value);
+            }
+            node = self.tree.nodes[node].next;
+        }
+        None
+    }
+
+    fn alloc_impl(
+        &mut self,
+        block: [TOrd; 2],
+        order: u32,
+        node: NodeId,
+    ) {
+        self.tree.take_range_impl(block, node);
+        self.blocks.insert_range_impl(block, order);
+    }
+
+    /// Return the order of the allocated block starting at `value`.
+    pub fn order(
+        &self,
+        value: TOrd,
+    ) -> Option<u32> {
+        let order = *self.blocks.get(value)?;
+        if offset(self.tree.range[0], value) & ((1 << order) - 1) != 0 {
+            return None;
+        }
+        Some(order)
+    }
+
+    /// Release the block starting at `value`, returning its order.
+    ///
+    /// Returns `RangeTreeError::NotTaken` when `value` isn't the start of an allocated block.
+    pub fn free(
+        &mut self,
+        value: TOrd,
+    ) -> Result<u32, RangeTreeError> {
+        if !self.tree.in_bounds(&value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        let order = self.order(value).ok_or(RangeTreeError::NotTaken)?;
+        let block = [value, TOrd::count_offset(value, (1 << order) - 1)];
+        self.blocks.remove_range_impl(block);
+        if order == 0 {
+            self.tree.release_impl(value);
+        } else {
+            self.tree.release_range_impl(block);
+        }
+        Ok(order)
+    }
+}
@@ -0,0 +1,142 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Construction of a `RangeTree` from options known at compile time.
+
+use core::cmp;
+
+use types::RType;
+use span::Span;
+use {
+    Backend,
+    RangeTree,
+    RangeTreeError,
+    NODE_CHUNK_SIZE,
+};
+
+/// Options for creating a `RangeTree`,
+/// see `RangeTree::builder`.
+///
+/// ```
+/// use rangetree::{Backend, RangeTree};
+///
+/// let ids: RangeTree<u32> = RangeTree::builder()
+///     .bounds([1, 1000])
+///     .chunk_size(64)
+///     .backend(Backend::List)
+///     .build()
+///     .unwrap();
+/// assert_eq!(ids.ranges_untaken_as_vec(), [[1, 1000]]);
+/// ```
+///
+/// The builder can be defined as a constant,
+/// so reserved ranges can be written as a `const` table:
+///
+/// ```
+/// use rangetree::{RangeTreeBuilder, span::Span};
+///
+/// const RESERVED: &[Span<u16>] = &[[0, 0], [0xfff0, 0xffff]];
+/// const IDS: RangeTreeBuilder<u16> = RangeTreeBuilder::new([0, 0xffff]).reserve_ranges(RESERVED);
+///
+/// let ids = IDS.build().unwrap();
+/// assert_eq!(ids.ranges_untaken_as_vec(), [[1, 0xffef]]);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RangeTreeBuilder<'a, TOrd: 'a + RType> {
+    range: Span<TOrd>,
+    full: bool,
+    reserved: &'a [Span<TOrd>],
+    chunk_size: usize,
+    backend: Backend,
+}
+
+impl<'a, TOrd: RType> RangeTreeBuilder<'a, TOrd> {
+    /// Start building a tree with the [minimum, maximum] values (inclusive), all untaken.
+    pub const fn new(
+        range: Span<TOrd>,
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            range,
+            full: false,
+            reserved: &[],
+            chunk_size: NODE_CHUNK_SIZE,
+            backend: Backend::RbTree,
+        }
+    }
+
+    /// The [minimum, maximum] values (inclusive).
+    pub const fn bounds(
+        self,
+        range: Span<TOrd>,
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            range,
+            ..self
+        }
+    }
+
+    /// When true, the tree is created with all values *taken*
+    /// (reserved ranges are ignored).
+    pub const fn full(
+        self,
+        full: bool,
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            full,
+            ..self
+        }
+    }
+
+    /// Ranges which are taken when the tree is built,
+    /// these must be sorted, non-overlapping and within the bounds.
+    pub const fn reserve_ranges(
+        self,
+        reserved: &'a [Span<TOrd>],
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            reserved,
+            ..self
+        }
+    }
+
+    /// The number of nodes (untaken ranges) allocated up-front
+    /// and the minimum number added when the pool grows (1024 by default).
+    ///
+    /// Smaller chunks use less memory for trees with few ranges,
+    /// larger chunks allocate less often for fragmented trees.
+    pub const fn chunk_size(
+        self,
+        chunk_size: usize,
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            chunk_size,
+            ..self
+        }
+    }
+
+    /// How spans are looked up, see `RangeTree::set_backend`.
+    pub const fn backend(
+        self,
+        backend: Backend,
+    ) -> RangeTreeBuilder<'a, TOrd> {
+        RangeTreeBuilder {
+            backend,
+            ..self
+        }
+    }
+
+    /// Create the tree, validating the reserved ranges
+    /// (see `RangeTree::from_ranges_taken` for errors).
+    pub fn build(
+        &self,
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> {
+        let chunk_size = cmp::max(self.chunk_size, 1);
+        let mut r = if self.full {
+            RangeTree::from_ranges_untaken_impl(self.range, None, chunk_size)?
+        } else {
+            RangeTree::from_ranges_taken_impl(self.range, self.reserved, chunk_size)?
+        };
+        r.set_backend(self.backend);
+        Ok(r)
+    }
+}
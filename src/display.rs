@@ -0,0 +1,198 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Formatting a `RangeTree` for logs (see `RangeTree::display`) and debugging.
+
+use core::cmp;
+use core::fmt;
+use core::fmt::Write;
+
+use types::RType;
+use {
+    RangeTree,
+    count_offset_between,
+};
+
+/// Options for `RangeTree::display`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayOptions {
+    /// The maximum number of untaken spans to write,
+    /// the remaining spans are replaced by an ellipsis.
+    pub spans_max: usize,
+    /// Write a second line with the counts and occupancy.
+    pub summary: bool,
+}
+
+impl Default for DisplayOptions {
+    /// Options used by the alternate `{:#}` format.
+    fn default() -> DisplayOptions {
+        DisplayOptions {
+            spans_max: 16,
+            summary: true,
+        }
+    }
+}
+
+/// Formats a tree using `DisplayOptions`, see `RangeTree::display`.
+pub struct RangeTreeDisplay<'a, TOrd: 'a + RType> {
+    tree: &'a RangeTree<TOrd>,
+    options: DisplayOptions,
+}
+
+impl<'a, TOrd: RType> RangeTreeDisplay<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a RangeTree<TOrd>,
+        options: DisplayOptions,
+    ) -> RangeTreeDisplay<'a, TOrd> {
+        RangeTreeDisplay {
+            tree,
+            options,
+        }
+    }
+}
+
+fn fmt_tree<TOrd: RType + fmt::Display>(
+    tree: &RangeTree<TOrd>,
+    f: &mut fmt::Formatter,
+    spans_max: Option<usize>,
+    summary: bool,
+) -> fmt::Result {
+    let bounds = tree.bounds();
+    write!(f, "bounds [{}, {}], untaken [", bounds[0], bounds[1])?;
+    for (i, range) in tree.iter_untaken().enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+        if spans_max == Some(i) {
+            // Counting the remaining spans doesn't need to visit them.
+            write!(f, "... ({} more)", tree.count_ranges_untaken() - i)?;
+            break;
+        }
+        write!(f, "[{}, {}]", range[0], range[1])?;
+    }
+    f.write_str("]")?;
+    if summary {
+        write!(
+            f,
+            "\ntaken {}, untaken {}, spans {} ({:.1}% occupied)",
+            tree.count_taken(),
+            tree.count_untaken(),
+            tree.count_ranges_untaken(),
+            tree.occupancy() * 100.0,
+        )?;
+    }
+    Ok(())
+}
+
+impl<'a, TOrd: RType + fmt::Display> fmt::Display for RangeTreeDisplay<'a, TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        fmt_tree(self.tree, f, Some(self.options.spans_max), self.options.summary)
+    }
+}
+
+/// Formats a tree as a bar of characters followed by its spans, see `RangeTree::ascii_map`.
+pub struct AsciiMap<'a, TOrd: 'a + RType> {
+    tree: &'a RangeTree<TOrd>,
+    width: usize,
+}
+
+impl<'a, TOrd: RType> AsciiMap<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a RangeTree<TOrd>,
+        width: usize,
+    ) -> AsciiMap<'a, TOrd> {
+        AsciiMap {
+            tree,
+            width,
+        }
+    }
+
+    fn fmt_bar(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let bounds = self.tree.bounds();
+        let count = self.tree.capacity();
+        let width = cmp::min(self.width as u128, count);
+        // The first value of each cell (as an offset from the minimum bound),
+        // computed so the product can't overflow.
+        let cell_start = |i: u128| i * (count / width) + (i * (count % width)) / width;
+        // Untaken spans as offsets from the minimum bound.
+        let mut spans = self.tree.iter_untaken().map(|range| [
+            count_offset_between(bounds[0], range[0]),
+            count_offset_between(bounds[0], range[1]),
+        ]).peekable();
+        for i in 0..width {
+            let cell = [cell_start(i), cell_start(i + 1) - 1];
+            let mut untaken = 0;
+            while let Some(&span) = spans.peek() {
+                if span[0] > cell[1] {
+                    break;
+                }
+                untaken += cmp::min(span[1], cell[1]) - cmp::max(span[0], cell[0]) + 1;
+                if span[1] > cell[1] {
+                    // the span continues into the next cell.
+                    break;
+                }
+                spans.next();
+            }
+            f.write_char(if untaken == 0 {
+                '#'
+            } else if untaken == cell[1] - cell[0] + 1 {
+                '.'
+            } else {
+                '+'
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, TOrd: RType + fmt::Display> fmt::Display for AsciiMap<'a, TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        self.fmt_bar(f)?;
+        f.write_char('\n')?;
+        let options = DisplayOptions::default();
+        fmt_tree(self.tree, f, Some(options.spans_max), options.summary)
+    }
+}
+
+/// Writes the bounds and every untaken span on a single line,
+/// the alternate format `{:#}` limits the number of spans and adds a summary line
+/// (see `DisplayOptions::default`).
+impl<TOrd: RType + fmt::Display> fmt::Display for RangeTree<TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        if f.alternate() {
+            fmt::Display::fmt(&RangeTreeDisplay::new(self, DisplayOptions::default()), f)
+        } else {
+            fmt_tree(self, f, None, false)
+        }
+    }
+}
+
+/// Writes the bounds and untaken spans,
+/// the alternate format `{:#?}` also writes the depth of the internal tree.
+impl<TOrd: RType + fmt::Debug> fmt::Debug for RangeTree<TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut s = f.debug_struct("RangeTree");
+        s.field("bounds", &self.range);
+        s.field("untaken", &self.ranges_untaken_as_vec());
+        if alternate {
+            s.field("depth", &self.tree_depth());
+        }
+        s.finish()
+    }
+}
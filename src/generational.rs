@@ -0,0 +1,99 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Versioned ID allocation, see `GenerationalAllocator`.
+
+use types::RType;
+use {
+    RangeMap,
+    RangeTree,
+    RangeTreeError,
+};
+use span;
+
+/// An ID with the generation it was allocated in,
+/// see `GenerationalAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GenerationalId<TOrd: RType> {
+    pub id: TOrd,
+    pub generation: u32,
+}
+
+/// Allocator of IDs which are reused once released,
+/// where each release increments the generation of the ID so stale handles can be detected.
+///
+/// Generations are stored as ranges, so IDs which have never been released use no memory.
+pub struct GenerationalAllocator<TOrd: RType> {
+    tree: RangeTree<TOrd>,
+    // IDs without a range have generation zero.
+    generations: RangeMap<TOrd, u32>,
+}
+
+impl<TOrd: RType> GenerationalAllocator<TOrd> {
+    /// Create an allocator for IDs in the [minimum, maximum] range (inclusive).
+    pub fn new(
+        range: [TOrd; 2],
+    ) -> GenerationalAllocator<TOrd> {
+        GenerationalAllocator {
+            tree: RangeTree::new(range, false),
+            generations: RangeMap::new(),
+        }
+    }
+
+    /// Return the tree of allocated (taken) IDs.
+    pub fn tree(
+        &self,
+    ) -> &RangeTree<TOrd> {
+        &self.tree
+    }
+
+    /// Return the current generation of `id`, allocated or not.
+    pub fn generation(
+        &self,
+        id: TOrd,
+    ) -> u32 {
+        self.generations.get(id).cloned().unwrap_or(0)
+    }
+
+    /// Allocate the lowest free ID, returning `None` when all IDs are allocated.
+    pub fn alloc(
+        &mut self,
+    ) -> Option<GenerationalId<TOrd>> {
+        let id = self.tree.take_any()?;
+        Some(GenerationalId {
+            id,
+            generation: self.generation(id),
+        })
+    }
+
+    /// Check if `handle` is allocated and from the current generation of its ID,
+    /// IDs outside the allocator bounds are never valid.
+    pub fn is_valid(
+        &self,
+        handle: GenerationalId<TOrd>,
+    ) -> bool {
+        span::contains(&self.tree.bounds(), &handle.id) &&
+        !self.tree.has(handle.id) &&
+        self.generation(handle.id) == handle.generation
+    }
+
+    /// Release `handle`, incrementing the generation of its ID (wrapping on overflow).
+    ///
+    /// Returns `RangeTreeError::OutOfBounds` for IDs outside the allocator bounds,
+    /// `RangeTreeError::NotTaken` when the handle isn't valid,
+    /// including handles from a previous generation.
+    pub fn free(
+        &mut self,
+        handle: GenerationalId<TOrd>,
+    ) -> Result<(), RangeTreeError> {
+        if !span::contains(&self.tree.bounds(), &handle.id) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        if !self.is_valid(handle) {
+            return Err(RangeTreeError::NotTaken);
+        }
+        self.tree.try_release(handle.id)?;
+        self.generations.insert_range_impl([handle.id, handle.id], handle.generation.wrapping_add(1));
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Owned values released by the tree once dropped, see `RangeTree::take_handle`.
+
+use core::mem;
+use core::ops;
+use core::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+    MutexGuard,
+};
+
+use alloc::vec::Vec;
+
+use types::RType;
+
+/// Values of dropped handles, waiting to be released by the tree.
+pub struct ReleaseQueue<TOrd: RType> {
+    values: Mutex<Vec<TOrd>>,
+    // set when 'values' may be non-empty, so the tree can skip locking.
+    is_pending: AtomicBool,
+}
+
+impl<TOrd: RType> ReleaseQueue<TOrd> {
+    pub fn new() -> ReleaseQueue<TOrd> {
+        ReleaseQueue {
+            values: Mutex::new(vec![]),
+            is_pending: AtomicBool::new(false),
+        }
+    }
+
+    // A thread panicking while pushing can't leave the values in an invalid state,
+    // so poisoning is ignored (as `drain` can't panic).
+    fn lock(
+        &self,
+    ) -> MutexGuard<'_, Vec<TOrd>> {
+        match self.values.lock() {
+            Ok(values) => values,
+            Err(err) => err.into_inner(),
+        }
+    }
+
+    fn push(
+        &self,
+        value: TOrd,
+    ) {
+        self.lock().push(value);
+        self.is_pending.store(true, Ordering::Release);
+    }
+
+    /// Return the values of handles dropped since the last call.
+    pub fn drain(
+        &self,
+    ) -> Vec<TOrd> {
+        if !self.is_pending.swap(false, Ordering::Acquire) {
+            return vec![];
+        }
+        mem::take(&mut *self.lock())
+    }
+}
+
+/// A taken value which can outlive the borrow of its tree,
+/// released by the tree after the handle is dropped (unless `keep` is called).
+///
+/// Dropped handles queue their value, which remains taken
+/// until the tree's next `take_any`, `take_handle`, `release` or `flush`.
+/// Handles can be sent to (and dropped on) other threads.
+///
+/// See `RangeTree::take_handle` for handles which outlive their value being released.
+#[must_use = "the value is released when the handle is dropped"]
+pub struct OwnedValue<TOrd: RType> {
+    // `None` once kept.
+    value: Option<TOrd>,
+    queue: Arc<ReleaseQueue<TOrd>>,
+}
+
+impl<TOrd: RType> OwnedValue<TOrd> {
+    pub(crate) fn new(
+        value: TOrd,
+        queue: Arc<ReleaseQueue<TOrd>>,
+    ) -> OwnedValue<TOrd> {
+        OwnedValue {
+            value: Some(value),
+            queue,
+        }
+    }
+
+    /// Return the taken value.
+    pub fn value(
+        &self,
+    ) -> TOrd {
+        self.value.unwrap()
+    }
+
+    /// Keep the value taken, returning it.
+    pub fn keep(
+        mut self,
+    ) -> TOrd {
+        self.value.take().unwrap()
+    }
+}
+
+impl<TOrd: RType> ops::Deref for OwnedValue<TOrd> {
+    type Target = TOrd;
+
+    fn deref(
+        &self,
+    ) -> &TOrd {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<TOrd: RType> Drop for OwnedValue<TOrd> {
+    fn drop(
+        &mut self,
+    ) {
+        if let Some(value) = self.value.take() {
+            self.queue.push(value);
+        }
+    }
+}
@@ -1,15 +1,113 @@
 // Apache License, Version 2.0
 // (c) Campbell Barton, 2016
 
-/// `RangeTree` (1d) for integer values.
-///
+//! `RangeTree` (1d) for integer values.
+//!
+//! Only `core` and `alloc` are required, `std` is an optional (default) feature.
+//!
+//! Overflow policy: values at the type limits (`u32::MAX` for example) are handled as any other value.
+//! The successor or predecessor of a value is only computed when it's within the tree bounds,
+//! so no arithmetic overflows in debug or release builds.
+//! Counts are `u128`, which only saturate for a tree over every `u128` or `i128` value.
+
+#![no_std]
+
+#[cfg(any(feature = "std", test))]
+extern crate std;
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "rand")]
+extern crate rand_core;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 mod mempool_elem;
+mod vec_util;
+
+pub mod span;
+
+#[cfg(feature = "std")]
+mod shared;
+#[cfg(feature = "std")]
+pub use shared::{
+    ReservationGuard,
+    SharedRangeTree,
+};
+
+mod sub_allocator;
+pub use sub_allocator::SubAllocator;
+
+mod slice;
+pub use slice::RangeTreeSlice;
+
+mod range_map;
+pub use range_map::{
+    RangeMap,
+    RangeMapIter,
+};
+
+mod generational;
+pub use generational::{
+    GenerationalAllocator,
+    GenerationalId,
+};
+
+mod buddy;
+pub use buddy::BuddyAllocator;
+
+mod range_tree_2d;
+pub use range_tree_2d::RangeTree2d;
+
+mod transaction;
+pub use transaction::Transaction;
 
-use std::ptr;
+mod scoped;
+pub use scoped::ScopedValue;
 
-// disable for slow, full-list look-ups.
-const USE_BTREE: bool = true;
+#[cfg(feature = "handles")]
+mod handle;
+#[cfg(feature = "handles")]
+pub use handle::OwnedValue;
+
+#[cfg(feature = "std")]
+mod reservation;
+#[cfg(feature = "std")]
+pub use reservation::ReservationId;
+
+mod builder;
+pub use builder::RangeTreeBuilder;
+
+mod snapshot;
+pub use snapshot::DecodeError;
+
+mod stats;
+pub use stats::{
+    MemoryUsage,
+    RangeTreeStats,
+};
+
+mod display;
+pub use display::{
+    AsciiMap,
+    DisplayOptions,
+    RangeTreeDisplay,
+};
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+use core::fmt;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Default number of nodes to allocate, see `RangeTreeBuilder::chunk_size`.
+const NODE_CHUNK_SIZE: usize = 1024;
 
 // ----------------------------------------------------------------------------
 // Mini API, avoids using `num` crate.
@@ -17,6 +115,12 @@ const USE_BTREE: bool = true;
 // Exposes:
 // - zero()
 // - one()
+// - succ()
+// - pred()
+// - count_range()
+// - count_offset()
+// - min_value()
+// - max_value()
 
 /// Zero value (predefined as 0 for integer types).
 pub trait Zero: Sized {
@@ -28,6 +132,73 @@ pub trait One: Sized {
     fn one() -> Self;
 }
 
+/// Adjacent values, used to step between the ends of neighboring ranges.
+///
+/// Implemented for integer types, `char` (skipping the surrogate range)
+/// and the non-zero unsigned integer types.
+pub trait Step: Sized {
+    /// Return the next value (this must not be the maximum value).
+    fn succ(self) -> Self;
+    /// Return the previous value (this must not be the minimum value).
+    fn pred(self) -> Self;
+}
+
+// Steps which can't pass a bound, so never overflow when the bound is the type limit,
+// used where the result may be outside the tree (`Step` requires the result to exist),
+// see the overflow policy in the crate documentation.
+trait StepChecked: Step + Ord + Copy {
+    // Return the next value or `None` when 'self' is 'max' (or greater).
+    #[inline]
+    fn succ_checked(self, max: Self) -> Option<Self> {
+        if self < max { Some(self.succ()) } else { None }
+    }
+    // Return the previous value or `None` when 'self' is 'min' (or less).
+    #[inline]
+    fn pred_checked(self, min: Self) -> Option<Self> {
+        if self > min { Some(self.pred()) } else { None }
+    }
+    // Check if 'self' is the value immediately before 'other'.
+    #[inline]
+    fn precedes(self, other: Self) -> bool {
+        self.succ_checked(other) == Some(other)
+    }
+}
+
+impl<T: Step + Ord + Copy> StepChecked for T {}
+
+// Return the number of values after 'a' up to 'b' (which must not be less than 'a'),
+// unlike counting 'a..=b' this can't saturate (see `Count`).
+fn count_offset_between<TOrd: RType>(
+    a: TOrd,
+    b: TOrd,
+) -> u128 {
+    debug_assert!(a <= b);
+    if a == b {
+        0
+    } else {
+        TOrd::count_range(&[a.succ(), b])
+    }
+}
+
+/// Number of values in a [minimum, maximum] range (inclusive), used for counting.
+///
+/// Counts saturate at `u128::MAX`, so only a range over every `u128` or `i128` value
+/// (`2^128` values) is counted as one less than its size.
+pub trait Count: Sized {
+    /// True for integer types, whose values are written as numbers
+    /// by `RangeTreeStats::to_json` (other values are written as strings).
+    const IS_INTEGER: bool = false;
+    fn count_range(range: &[Self; 2]) -> u128;
+    /// Return `value` advanced by `count` (which must not step past the type maximum).
+    fn count_offset(value: Self, count: u128) -> Self;
+}
+
+/// The minimum and maximum values of a type, used for ranges over the full domain.
+pub trait Bounded: Sized {
+    fn min_value() -> Self;
+    fn max_value() -> Self;
+}
+
 macro_rules! zero_one_impl {
     ($($t:ty)*) => ($(
         impl Zero for $t {
@@ -38,10 +209,159 @@ macro_rules! zero_one_impl {
             #[inline]
             fn one() -> Self { 1 }
         }
+        impl Bounded for $t {
+            #[inline]
+            fn min_value() -> Self { <$t>::MIN }
+            #[inline]
+            fn max_value() -> Self { <$t>::MAX }
+        }
+        impl Step for $t {
+            #[inline]
+            fn succ(self) -> Self { self + 1 }
+            #[inline]
+            fn pred(self) -> Self { self - 1 }
+        }
+        impl Count for $t {
+            const IS_INTEGER: bool = true;
+            #[inline]
+            fn count_range(range: &[Self; 2]) -> u128 {
+                ((range[1] as i128 - range[0] as i128) + 1) as u128
+            }
+            #[inline]
+            fn count_offset(value: Self, count: u128) -> Self {
+                (value as i128 + count as i128) as Self
+            }
+        }
     )*)
 }
 zero_one_impl! { u8 u16 u32 u64 usize i8 i16 i32 i64 isize }
 
+// 128 bit types can't be widened, the difference is calculated with two's complement
+// wrapping arithmetic, which is exact when interpreted as unsigned.
+macro_rules! zero_one_impl_128 {
+    ($($t:ty)*) => ($(
+        impl Zero for $t {
+            #[inline]
+            fn zero() -> Self { 0 }
+        }
+        impl One for $t {
+            #[inline]
+            fn one() -> Self { 1 }
+        }
+        impl Bounded for $t {
+            #[inline]
+            fn min_value() -> Self { <$t>::MIN }
+            #[inline]
+            fn max_value() -> Self { <$t>::MAX }
+        }
+        impl Step for $t {
+            #[inline]
+            fn succ(self) -> Self { self + 1 }
+            #[inline]
+            fn pred(self) -> Self { self - 1 }
+        }
+        impl Count for $t {
+            const IS_INTEGER: bool = true;
+            #[inline]
+            fn count_range(range: &[Self; 2]) -> u128 {
+                (range[1] as u128).wrapping_sub(range[0] as u128).saturating_add(1)
+            }
+            #[inline]
+            fn count_offset(value: Self, count: u128) -> Self {
+                (value as u128).wrapping_add(count) as Self
+            }
+        }
+    )*)
+}
+zero_one_impl_128! { u128 i128 }
+
+macro_rules! non_zero_impl {
+    ($($t:ident($t_int:ty))*) => ($(
+        impl Step for ::core::num::$t {
+            #[inline]
+            fn succ(self) -> Self {
+                // Adding one to a value below the maximum is never zero.
+                ::core::num::$t::new(self.get() + 1).unwrap_or(self)
+            }
+            #[inline]
+            fn pred(self) -> Self {
+                debug_assert!(self.get() != 1);
+                ::core::num::$t::new(self.get() - 1).unwrap_or(self)
+            }
+        }
+        impl Bounded for ::core::num::$t {
+            #[inline]
+            fn min_value() -> Self { ::core::num::$t::MIN }
+            #[inline]
+            fn max_value() -> Self { ::core::num::$t::MAX }
+        }
+        impl Count for ::core::num::$t {
+            const IS_INTEGER: bool = true;
+            #[inline]
+            fn count_range(range: &[Self; 2]) -> u128 {
+                <$t_int>::count_range(&[range[0].get(), range[1].get()])
+            }
+            #[inline]
+            fn count_offset(value: Self, count: u128) -> Self {
+                ::core::num::$t::new(<$t_int>::count_offset(value.get(), count)).unwrap_or(value)
+            }
+        }
+    )*)
+}
+non_zero_impl! {
+    NonZeroU8(u8) NonZeroU16(u16) NonZeroU32(u32) NonZeroU64(u64) NonZeroU128(u128)
+    NonZeroUsize(usize)
+}
+
+// 'char' values are counted by their index, which excludes the surrogate range.
+const CHAR_SURROGATE_MIN: u32 = 0xd800;
+const CHAR_SURROGATE_LEN: u32 = 0x800;
+
+#[inline]
+fn char_index(
+    value: char,
+) -> u32 {
+    let value = value as u32;
+    if value < CHAR_SURROGATE_MIN { value } else { value - CHAR_SURROGATE_LEN }
+}
+
+#[inline]
+fn char_from_index(
+    index: u32,
+) -> char {
+    let value = if index < CHAR_SURROGATE_MIN { index } else { index + CHAR_SURROGATE_LEN };
+    ::core::char::from_u32(value).unwrap_or(::core::char::MAX)
+}
+
+impl Step for char {
+    #[inline]
+    fn succ(self) -> Self {
+        char_from_index(char_index(self) + 1)
+    }
+    #[inline]
+    fn pred(self) -> Self {
+        char_from_index(char_index(self) - 1)
+    }
+}
+
+impl Bounded for char {
+    #[inline]
+    fn min_value() -> Self { '\0' }
+    #[inline]
+    fn max_value() -> Self { ::core::char::MAX }
+}
+
+impl Count for char {
+    #[inline]
+    fn count_range(range: &[Self; 2]) -> u128 {
+        u32::count_range(&[char_index(range[0]), char_index(range[1])])
+    }
+    #[inline]
+    fn count_offset(value: Self, count: u128) -> Self {
+        char_from_index(u32::count_offset(char_index(value), count))
+    }
+}
+
 
 // ----------------------------------------------------------------------------
 // Generic Range Type Traits
@@ -51,88 +371,87 @@ zero_one_impl! { u8 u16 u32 u64 usize i8 i16 i32 i64 isize }
 // and also used by modules here
 mod types {
     use super::{
-        One,
-        Zero,
+        Count,
+        Step,
     };
     use mempool_elem;
-    use std::ptr;
-    use std::ops;
+    pub use mempool_elem::ElemId as NodeId;
 
     pub trait RType:
         Ord +
-        Zero +
-        One +
+        Step +
+        Count +
         Copy +
-        ops::Add<Output=Self> +
-        ops::Sub<Output=Self> +
-        ops::AddAssign +
-        ops::SubAssign +
-        ::std::fmt::Display +
         {}
     impl<TOrd> RType for TOrd where TOrd:
         Ord +
-        Zero +
-        One +
+        Step +
+        Count +
         Copy +
-        ops::Add<Output=TOrd> +
-        ops::Sub<Output=TOrd> +
-        ops::AddAssign +
-        ops::SubAssign +
-        ::std::fmt::Display +
         {}
 
-    pub struct Node<TOrd: RType> {
+    // Nodes link to each other by their index in the pool (`NodeId`),
+    // so there are no pointers between nodes.
+    pub struct Node<TOrd: RType, V = ()> {
         // next is also used for RangeTree.free chain.
         // when blocks are unused.
-        pub next: *mut Node<TOrd>,
-        pub prev: *mut Node<TOrd>,
+        //
+        // The list links are kept instead of rbtree parent links (or threading):
+        // neighbors are found in O(1) without the tree (needed by `Backend::List`),
+        // linking a node writes 4 links while parent links must also be updated by every rotation,
+        // and iterators & cursors step in both directions without walking up the tree.
+        // See `benches/node_links.rs`, stepping over 1,000 to 1,000,000 spans takes 2.4-3.3ns
+        // per span with list links vs 5.3-9.8ns with parent links,
+        // finding a span & its neighbors is dominated by the search, list links being 5-14% faster
+        // (at 100,000 spans results varied between 3% slower & 12% faster).
+        pub next: NodeId,
+        pub prev: NodeId,
 
         // range: min,max (inclusive)
         pub range: [TOrd; 2],
 
         // rbtree
-        pub left: *mut Node<TOrd>,
-        pub right: *mut Node<TOrd>,
+        pub left: NodeId,
+        pub right: NodeId,
         pub color: bool,
+        // Number of values in the ranges of this subtree (saturating), see `rb::count`
+        // and the number of values in the largest range of this subtree, see `rb::count_max`.
+        // Only kept up to date by `RangeTree` (`RangeMap` doesn't use them).
+        pub count: u128,
+        pub count_max: u128,
+
+        // associated value, unused (zero sized) by `RangeTree`, see `RangeMap`.
+        pub value: V,
     }
 
-    impl<TOrd: RType> mempool_elem::MemElemUtils for Node<TOrd> {
-        fn free_ptr_get(
+    impl<TOrd: RType, V> mempool_elem::MemElemUtils for Node<TOrd, V> {
+        fn free_id_get(
             &self,
-        ) -> *mut Node<TOrd> {
+        ) -> NodeId {
             self.next
         }
-        fn free_ptr_set(
+        fn free_id_set(
             &mut self,
-            ptr: *mut Node<TOrd>,
+            id: NodeId,
         ) {
-            self.next = ptr;
-            self.prev = self;  // tag as free'd
+            self.next = id;
+            self.prev = NodeId::NULL;
         }
     }
 
-    impl<TOrd: RType> Default for Node<TOrd> {
-        fn default() -> Node<TOrd> {
-            Node {
-                next: ptr::null_mut(),
-                prev: ptr::null_mut(),
-                range: [TOrd::zero(), TOrd::zero()],
-                left: ptr::null_mut(),
-                right: ptr::null_mut(),
-                // always overwritten when added to the tree
-                color: false,
-            }
-        }
-    }
+    pub type NodePool<TOrd, V = ()> = mempool_elem::MemPool<Node<TOrd, V>>;
 
-    pub struct List<TOrd: RType> {
-        pub first: *mut Node<TOrd>,
-        pub last: *mut Node<TOrd>,
+    pub struct List {
+        pub first: NodeId,
+        pub last: NodeId,
+        pub len: usize,
     }
 }
 
 use types::{
     Node,
+    NodeId,
+    NodePool,
     List,
     RType,
 };
@@ -140,192 +459,749 @@ use types::{
 /// Main range-tree structure.
 pub struct RangeTree<TOrd: RType> {
     range: [TOrd; 2],
-    list: List<TOrd>,
+    list: List,
+
+    // btree root (when the backend is `Backend::RbTree`).
+    root: NodeId,
+    backend: Backend,
+
+    nodes: NodePool<TOrd>,
+
+    // number of untaken values, see `RangeTree::count_untaken`.
+    count_untaken: u128,
+
+    // number of modifications, see `RangeTree::seq`.
+    seq: u64,
+    // operations since 'journal_seq' (when enabled).
+    journal: Option<Vec<RangeOp<TOrd>>>,
+    journal_seq: u64,
+    // see `RangeTree::set_undo`.
+    history: Option<History<TOrd>>,
+    // see `RangeTree::set_observer`.
+    observer: Option<Observer<TOrd>>,
+
+    // labels of taken values, see `RangeTree::take_labeled`.
+    #[cfg(feature = "diagnostics")]
+    labels: ::alloc::collections::BTreeMap<TOrd, &'static str>,
 
-    // btree root (USE_BTREE)
-    root: *mut Node<TOrd>,
+    // see `RangeTree::set_growth`.
+    growth: Option<Growth<TOrd>>,
 
-    node_pool: mempool_elem::MemPool<Node<TOrd>>,
+    // see `RangeTree::set_release_deferred`.
+    release_pending: Option<Vec<TOrd>>,
+    release_pending_limit: usize,
+
+    // values of dropped handles, see `RangeTree::take_handle`.
+    #[cfg(feature = "handles")]
+    release_queue: Option<::std::sync::Arc<handle::ReleaseQueue<TOrd>>>,
+
+    // see `RangeTree::reserve_range`.
+    #[cfg(feature = "std")]
+    reservations: reservation::Reservations<TOrd>,
+
+    // untaken count before each modification, see `RangeTree::set_rate_history`.
+    rate_history: Option<VecDeque<u128>>,
+    rate_history_limit: usize,
+
+    // highest taken count before a modification, see `RangeTree::stats`.
+    count_taken_max: u128,
 }
 
+/// Growth of the tree bounds when `RangeTree::take_any` runs out of values,
+/// see `RangeTree::set_growth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Growth<TOrd: RType> {
+    /// The number of values to add to the maximum bound.
+    pub increment: u128,
+    /// The maximum bound is never grown past this value.
+    pub limit: TOrd,
+}
 
-// ----------------------------------------------------------------------------
-// List API
+/// How spans are looked up, see `RangeTree::set_backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Scan the list of spans, avoiding the overhead of balancing a tree,
+    /// which can be faster for trees with few spans.
+    List,
+    /// Look up spans in a balanced tree (the default).
+    #[default]
+    RbTree,
+}
 
-impl<TOrd: RType> List<TOrd> {
+/// Error returned by fallible range-tree methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeTreeError {
+    /// The value is outside the tree bounds.
+    OutOfBounds,
+    /// The value is already taken.
+    AlreadyTaken,
+    /// The value isn't taken.
+    NotTaken,
+    /// The tree was modified since the cursor was created or resynchronized.
+    StaleCursor,
+    /// A range has its minimum greater than its maximum,
+    /// or a list of ranges isn't sorted or overlaps.
+    InvalidRange,
+    /// There are no untaken values (and the tree can't grow).
+    Exhausted,
+    /// The value is reserved, see `RangeTree::reserve_range`.
+    Reserved,
+    /// The reservation expired, or was already committed or cancelled.
+    Expired,
+    /// Memory couldn't be allocated, see `RangeTree::try_reserve`.
+    OutOfMemory,
+}
 
-    fn push_front(
-        &mut self,
-        node: *mut Node<TOrd>,
-    ) {
-        let node = unsafe { &mut *node };
-        debug_assert!(node.next.is_null() &&
-                      node.prev.is_null());
-        if !self.first.is_null() {
-            unsafe {
-                node.next = self.first;
-                (*self.first).prev = node;
-                node.prev = ptr::null_mut();
-            }
-        } else {
-            self.last = node;
-        }
-        self.first = node;
+/// Error returned by `RangeTree::transfer` and `RangeTree::transfer_range`,
+/// neither tree is modified when this is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferError {
+    /// The values can't be released from the source tree.
+    Source(RangeTreeError),
+    /// The values can't be taken from the destination tree.
+    Dest(RangeTreeError),
+}
+
+/// Error returned by `RangeTree::try_shrink_max` and `RangeTree::force_shrink_max`,
+/// the tree isn't modified when this is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShrinkError {
+    /// The new bound is outside the tree bounds.
+    OutOfBounds,
+    /// Values outside the new bounds are taken.
+    Taken,
+}
+
+impl fmt::Display for RangeTreeError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        f.write_str(match *self {
+            RangeTreeError::OutOfBounds => "value is outside the tree bounds",
+            RangeTreeError::AlreadyTaken => "value is already taken",
+            RangeTreeError::NotTaken => "value isn't taken",
+            RangeTreeError::StaleCursor => "tree was modified since the cursor was synchronized",
+            RangeTreeError::InvalidRange => "range is reversed, unsorted or overlapping",
+            RangeTreeError::Exhausted => "no untaken values remain",
+            RangeTreeError::Reserved => "value is reserved",
+            RangeTreeError::Expired => "reservation expired or was already used",
+            RangeTreeError::OutOfMemory => "memory couldn't be allocated",
+        })
     }
+}
 
-    fn push_back(
-        &mut self,
-        node: *mut Node<TOrd>,
-    ) {
-        let node = unsafe { &mut *node };
-        debug_assert!(node.next.is_null() &&
-                      node.prev.is_null());
-        if !self.first.is_null() {
-            unsafe {
-                node.prev = self.last;
-                (*self.last).next = node;
-                node.next = ptr::null_mut();
-            }
-        } else {
-            self.first = node;
+impl fmt::Display for TransferError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match *self {
+            TransferError::Source(ref err) => write!(f, "source tree: {}", err),
+            TransferError::Dest(ref err) => write!(f, "destination tree: {}", err),
         }
-        self.last = node;
     }
+}
 
-    fn push_after(
-        &mut self,
-        node_prev: *mut Node<TOrd>,
-        node_new: *mut Node<TOrd>,
-    ) {
-        let node_new = unsafe { &mut *node_new };
-        // node_new after node_prev
-
-        // empty list
-        if self.first.is_null() {
-            self.first = node_new;
-            self.last = node_new;
+impl fmt::Display for ShrinkError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        f.write_str(match *self {
+            ShrinkError::OutOfBounds => "new bound is outside the tree bounds",
+            ShrinkError::Taken => "values outside the new bounds are taken",
+        })
+    }
+}
 
-            debug_assert!(node_new.next.is_null() &&
-                          node_new.prev.is_null());
-        } else if node_prev.is_null() {
-            // insert at head of list
-            unsafe {
-                node_new.prev = ptr::null_mut();
-                node_new.next = self.first;
-                (*self.first).prev = node_new;
-                self.first = node_new;
-            }
-        } else {
-            // at end of list
-            let node_prev = unsafe { &mut *node_prev };
-            if self.last == node_prev {
-                self.last = node_new;
-            }
+#[cfg(feature = "std")]
+impl std::error::Error for RangeTreeError {}
 
-            unsafe {
-                node_new.next = node_prev.next;
-                node_new.prev = node_prev;
-                node_prev.next = node_new;
-                let node_new_next = node_new.next;
-                if !node_new_next.is_null() {
-                    (*node_new_next).prev = node_new;
-                }
-            }
+#[cfg(feature = "std")]
+impl std::error::Error for TransferError {
+    fn source(
+        &self,
+    ) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            TransferError::Source(ref err) | TransferError::Dest(ref err) => Some(err),
         }
     }
+}
 
-    fn push_before(
-        &mut self,
-        node_next: *mut Node<TOrd>,
-        node_new: *mut Node<TOrd>,
-    ) {
-        let node_new = unsafe { &mut *node_new };
-        // node_new before node_next
+#[cfg(feature = "std")]
+impl std::error::Error for ShrinkError {}
 
-        // empty list
-        if self.first.is_null() {
-            self.first = node_new;
-            self.last = node_new;
-            debug_assert!(node_new.next.is_null() &&
-                          node_new.prev.is_null());
-        } else if node_next.is_null() {
-            // insert at end of list
-            unsafe {
-                node_new.prev = self.last;
-                node_new.next = ptr::null_mut();
-                (*self.last).next = node_new;
-                self.last = node_new;
-            }
-        } else {
-            // at beginning of list
-            let node_next = unsafe { &mut *node_next };
-            if self.first == node_next {
-                self.first = node_new;
-            }
+/// Error returned by `RangeTree::take_exact_range_or_suggest`,
+/// the tree isn't modified when this is returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeConflict<TOrd> {
+    /// The [minimum, maximum] ranges (inclusive) of the requested range which can't be taken
+    /// (taken or outside the tree bounds), in ascending order.
+    /// Empty when the requested range is reversed (its minimum is greater than its maximum).
+    pub conflicts: Vec<[TOrd; 2]>,
+    /// The untaken range of the same length nearest the requested range
+    /// (the lowest when there are multiple), `None` when no span is large enough.
+    pub suggestion: Option<[TOrd; 2]>,
+}
 
-            unsafe {
-                node_new.next = node_next;
-                node_new.prev = node_next.prev;
-                node_next.prev = node_new;
-                let node_new_prev = node_new.prev;
-                if !node_new_prev.is_null() {
-                    (*node_new_prev).next = node_new;
-                }
-            }
+impl<TOrd> fmt::Display for RangeConflict<TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        if self.conflicts.is_empty() {
+            return write!(f, "range is reversed");
         }
+        write!(f, "range overlaps {} range(s) which can't be taken", self.conflicts.len())
     }
+}
 
-    fn remove(
-        &mut self,
-        node: *mut Node<TOrd>,
-    ) {
-        let node = unsafe { &mut *node };
+#[cfg(feature = "std")]
+impl<TOrd: fmt::Debug> std::error::Error for RangeConflict<TOrd> {}
+
+/// How `RangeTree::take_any_contiguous_fit` chooses between spans which are large enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// The lowest span.
+    First,
+    /// The smallest span (the lowest when there are multiple).
+    Best,
+    /// The largest span (the lowest when there are multiple).
+    Worst,
+}
+
+/// A single modification of the tree, as recorded by the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeOp<TOrd: RType> {
+    /// A value was taken.
+    Take(TOrd),
+    /// A value was released.
+    Release(TOrd),
+    /// A [minimum, maximum] range (inclusive) of untaken values was taken.
+    TakeRange([TOrd; 2]),
+    /// A [minimum, maximum] range (inclusive) of taken values was released.
+    ReleaseRange([TOrd; 2]),
+    /// The tree was cleared (`full` argument).
+    Clear(bool),
+    /// The maximum bound was increased to this value (adding untaken values).
+    GrowMax(TOrd),
+    /// The minimum bound was decreased to this value (adding untaken values).
+    GrowMin(TOrd),
+    /// The maximum bound was decreased to this value (removing values).
+    ShrinkMax(TOrd),
+    /// Taken and untaken values were swapped.
+    Invert,
+}
+
+/// A change to the untaken spans, see `RangeTree::set_observer`.
+///
+/// Splitting a span resizes it and adds the span after it,
+/// merging spans resizes the lower span and removes the upper span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanEvent<TOrd: RType> {
+    /// A span was added.
+    Added([TOrd; 2]),
+    /// A span was removed.
+    Removed([TOrd; 2]),
+    /// The minimum or maximum of a span was changed.
+    Resized {
+        old: [TOrd; 2],
+        new: [TOrd; 2],
+    },
+    /// All spans were removed (by `RangeTree::clear` or `RangeTree::invert`),
+    /// spans added afterwards are sent as `Added`.
+    Cleared,
+}
+
+type Observer<TOrd> = Box<dyn FnMut(SpanEvent<TOrd>) + Send + Sync>;
+
+// Calls to an `extern "C"` function are known not to unwind (a panic aborts instead),
+// so the observer can be called from paths which must not unwind, see `vec_util`.
+#[cfg(feature = "no-panic")]
+#[allow(improper_ctypes_definitions)]
+extern "C" fn observer_call<TOrd: RType>(
+    observer: &mut Observer<TOrd>,
+    event: SpanEvent<TOrd>,
+) {
+    observer(event);
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+fn observer_call<TOrd: RType>(
+    observer: &mut Observer<TOrd>,
+    event: SpanEvent<TOrd>,
+) {
+    observer(event);
+}
+
+// Operations which can be undone (most recent last) and redone, see `RangeTree::set_undo`.
+struct History<TOrd: RType> {
+    undo: Vec<RangeOp<TOrd>>,
+    redo: Vec<RangeOp<TOrd>>,
+}
+
+// Nodes from 'node' to 'node_back' (inclusive), both null when empty,
+// used to iterate over untaken ranges from either end.
+struct NodeSpan {
+    node: NodeId,
+    node_back: NodeId,
+}
+
+impl NodeSpan {
+    // Empty when either is null or 'node_back' is before 'node'.
+    fn new<TOrd: RType>(
+        nodes: &NodePool<TOrd>,
+        node: NodeId,
+        node_back: NodeId,
+    ) -> NodeSpan {
+        if node.is_null() || node_back.is_null() ||
+           nodes[node].range[0] > nodes[node_back].range[0]
         {
-            let node_next = node.next;
-            if !node_next.is_null() {
-                unsafe {
-                    (*node_next).prev = node.prev;
-                }
+            NodeSpan {
+                node: NodeId::NULL,
+                node_back: NodeId::NULL,
             }
-            let node_prev = node.prev;
-            if !node_prev.is_null() {
-                unsafe {
-                    (*node_prev).next = node.next;
-                }
+        } else {
+            NodeSpan {
+                node,
+                node_back,
             }
+        }
+    }
 
-            if self.last == node {
-                self.last = node.prev;
-            }
-            if self.first == node {
-                self.first = node.next;
-            }
+    fn next<TOrd: RType>(
+        &mut self,
+        nodes: &NodePool<TOrd>,
+    ) -> Option<[TOrd; 2]> {
+        if self.node.is_null() {
+            return None;
+        }
+        let node = &nodes[self.node];
+        if self.node == self.node_back {
+            self.node = NodeId::NULL;
+            self.node_back = NodeId::NULL;
+        } else {
+            self.node = node.next;
         }
+        Some(node.range)
     }
 
-    fn clear(
+    fn next_back<TOrd: RType>(
         &mut self,
-    ) {
-        self.first = ptr::null_mut();
-        self.last = ptr::null_mut();
+        nodes: &NodePool<TOrd>,
+    ) -> Option<[TOrd; 2]> {
+        if self.node_back.is_null() {
+            return None;
+        }
+        let node = &nodes[self.node_back];
+        if self.node == self.node_back {
+            self.node = NodeId::NULL;
+            self.node_back = NodeId::NULL;
+        } else {
+            self.node_back = node.prev;
+        }
+        Some(node.range)
     }
+}
 
+/// Iterator over untaken [minimum, maximum] ranges (inclusive),
+/// see `RangeTree::iter_untaken`.
+pub struct IterUntaken<'a, TOrd: 'a + RType> {
+    nodes: NodeSpan,
+    tree: &'a RangeTree<TOrd>,
 }
 
-// ----------------------------------------------------------------------------
-// BTree API
+impl<'a, TOrd: RType> Iterator for IterUntaken<'a, TOrd> {
+    type Item = [TOrd; 2];
 
-mod rb {
-    use std::{
-        ptr,
-    };
+    fn next(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        self.nodes.next(&self.tree.nodes)
+    }
+}
 
-    use types::{
-        Node,
-        RType,
-    };
+impl<'a, TOrd: RType> DoubleEndedIterator for IterUntaken<'a, TOrd> {
+    fn next_back(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        self.nodes.next_back(&self.tree.nodes)
+    }
+}
 
-    const RED: bool = false;
+/// Iterator over untaken [minimum, maximum] ranges (inclusive) which owns the tree,
+/// see `RangeTree::into_ranges_untaken`.
+pub struct IntoIterUntaken<TOrd: RType> {
+    nodes: NodeSpan,
+    tree: RangeTree<TOrd>,
+}
+
+impl<TOrd: RType> Iterator for IntoIterUntaken<TOrd> {
+    type Item = [TOrd; 2];
+
+    fn next(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        self.nodes.next(&self.tree.nodes)
+    }
+
+    fn size_hint(
+        &self,
+    ) -> (usize, Option<usize>) {
+        // an upper bound, the remaining nodes aren't counted.
+        (0, Some(self.tree.list.len))
+    }
+}
+
+impl<TOrd: RType> DoubleEndedIterator for IntoIterUntaken<TOrd> {
+    fn next_back(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        self.nodes.next_back(&self.tree.nodes)
+    }
+}
+
+/// Iterator over untaken ranges, merging ranges separated by ignorable taken gaps,
+/// see `RangeTree::iter_untaken_coalesced`.
+pub struct IterUntakenCoalesced<'a, TOrd: 'a + RType, F> {
+    iter: IterUntaken<'a, TOrd>,
+    range_next: Option<[TOrd; 2]>,
+    gap_fn: F,
+}
+
+impl<'a, TOrd: RType, F> Iterator for IterUntakenCoalesced<'a, TOrd, F> where
+    F: FnMut([TOrd; 2]) -> bool,
+{
+    type Item = [TOrd; 2];
+
+    fn next(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        let mut range = match self.range_next.take() {
+            Some(range) => range,
+            None => self.iter.next()?,
+        };
+        for range_next in &mut self.iter {
+            let gap = [range[1].succ(), range_next[0].pred()];
+            if (self.gap_fn)(gap) {
+                range[1] = range_next[1];
+            } else {
+                self.range_next = Some(range_next);
+                break;
+            }
+        }
+        Some(range)
+    }
+}
+
+/// Iterator over taken [minimum, maximum] ranges (inclusive),
+/// see `RangeTree::iter_taken`.
+pub struct IterTaken<'a, TOrd: 'a + RType> {
+    // start of the next taken range, `None` when finished.
+    value: Option<TOrd>,
+    value_max: TOrd,
+    node: NodeId,
+    tree: &'a RangeTree<TOrd>,
+}
+
+impl<'a, TOrd: RType> Iterator for IterTaken<'a, TOrd> {
+    type Item = [TOrd; 2];
+
+    fn next(
+        &mut self,
+    ) -> Option<[TOrd; 2]> {
+        while let Some(value) = self.value {
+            if self.node.is_null() {
+                self.value = None;
+                return Some([value, self.value_max]);
+            }
+            let node = &self.tree.nodes[self.node];
+            self.node = node.next;
+            self.value = node.range[1].succ_checked(self.value_max);
+            if let Some(value_max) = node.range[0].pred_checked(value) {
+                return Some([value, value_max]);
+            }
+        }
+        None
+    }
+}
+
+/// Position in the untaken ranges which doesn't borrow the tree,
+/// see `RangeTree::cursor_untaken`.
+///
+/// Any modification of the tree makes the cursor stale,
+/// it must be resynchronized with `RangeTree::cursor_resync` to continue.
+#[derive(Clone, Copy)]
+pub struct Cursor<TOrd: RType> {
+    node: NodeId,
+    // the tree 'seq' this cursor is valid for.
+    seq: u64,
+    // maximum of the last range returned.
+    value_last: Option<TOrd>,
+}
+
+/// Position of a time-sliced validation pass, see `RangeTree::validate_incremental`.
+///
+/// The position is stored as a value (not a node),
+/// so the tree may be modified between calls.
+pub struct ValidatorState<TOrd: RType> {
+    // minimum of the last span checked, `None` to start a new pass.
+    resume: Option<TOrd>,
+}
+
+impl<TOrd: RType> ValidatorState<TOrd> {
+    pub fn new() -> ValidatorState<TOrd> {
+        ValidatorState {
+            resume: None,
+        }
+    }
+}
+
+impl<TOrd: RType> Default for ValidatorState<TOrd> {
+    fn default() -> ValidatorState<TOrd> {
+        ValidatorState::new()
+    }
+}
+
+/// Result of `RangeTree::validate_incremental`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationProgress<TOrd: RType> {
+    /// The budget ran out, call again to continue the pass.
+    Incomplete,
+    /// The pass reached the last span without finding problems,
+    /// the next call starts a new pass.
+    Complete,
+    /// The span (untaken range) failed a check, the next call starts a new pass.
+    Invalid {
+        span: [TOrd; 2],
+        reason: &'static str,
+    },
+}
+
+/// Error returned by `RangeTree::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationError<TOrd: RType> {
+    /// The span (untaken range) which failed a check,
+    /// `None` for checks of the whole tree.
+    pub span: Option<[TOrd; 2]>,
+    /// Description of the failed check.
+    pub reason: &'static str,
+}
+
+impl<TOrd: RType + fmt::Debug> fmt::Display for ValidationError<TOrd> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} (span {:?})", self.reason, span),
+            None => f.write_str(self.reason),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<TOrd: RType + fmt::Debug> std::error::Error for ValidationError<TOrd> {}
+
+
+// ----------------------------------------------------------------------------
+// Set Operations
+
+/// Return the ranges common to both sorted lists of non-overlapping ranges,
+/// merging the lists linearly.
+fn ranges_intersect<TOrd, A, B>(
+    a: A,
+    b: B,
+) -> Vec<[TOrd; 2]> where
+    TOrd: RType,
+    A: IntoIterator<Item=[TOrd; 2]>,
+    B: IntoIterator<Item=[TOrd; 2]>,
+{
+    let mut ranges = vec![];
+    let mut a = a.into_iter();
+    let mut b = b.into_iter();
+    let mut range_a = a.next();
+    let mut range_b = b.next();
+    while let (Some(ra), Some(rb)) = (range_a, range_b) {
+        if let Some(range) = span::clamp(&ra, &rb) {
+            vec_util::push(&mut ranges, range);
+        }
+        // advance whichever range ends first, it can't overlap any later ranges.
+        if ra[1] < rb[1] {
+            range_a = a.next();
+        } else {
+            range_b = b.next();
+        }
+    }
+    ranges
+}
+
+
+// ----------------------------------------------------------------------------
+// List API
+
+impl List {
+
+    fn push_front<TOrd: RType, V>(
+        &mut self,
+        nodes: &mut NodePool<TOrd, V>,
+        node: NodeId,
+    ) {
+        debug_assert!(nodes[node].next.is_null() &&
+                      nodes[node].prev.is_null());
+        self.len += 1;
+        if !self.first.is_null() {
+            nodes[node].next = self.first;
+            nodes[self.first].prev = node;
+            nodes[node].prev = NodeId::NULL;
+        } else {
+            self.last = node;
+        }
+        self.first = node;
+    }
+
+    fn push_back<TOrd: RType, V>(
+        &mut self,
+        nodes: &mut NodePool<TOrd, V>,
+        node: NodeId,
+    ) {
+        debug_assert!(nodes[node].next.is_null() &&
+                      nodes[node].prev.is_null());
+        self.len += 1;
+        if !self.first.is_null() {
+            nodes[node].prev = self.last;
+            nodes[self.last].next = node;
+            nodes[node].next = NodeId::NULL;
+        } else {
+            self.first = node;
+        }
+        self.last = node;
+    }
+
+    fn push_after<TOrd: RType, V>(
+        &mut self,
+        nodes: &mut NodePool<TOrd, V>,
+        node_prev: NodeId,
+        node_new: NodeId,
+    ) {
+        // node_new after node_prev
+        self.len += 1;
+
+        // empty list
+        if self.first.is_null() {
+            self.first = node_new;
+            self.last = node_new;
+
+            debug_assert!(nodes[node_new].next.is_null() &&
+                          nodes[node_new].prev.is_null());
+        } else if node_prev.is_null() {
+            // insert at head of list
+            nodes[node_new].prev = NodeId::NULL;
+            nodes[node_new].next = self.first;
+            nodes[self.first].prev = node_new;
+            self.first = node_new;
+        } else {
+            // at end of list
+            if self.last == node_prev {
+                self.last = node_new;
+            }
+
+            nodes[node_new].next = nodes[node_prev].next;
+            nodes[node_new].prev = node_prev;
+            nodes[node_prev].next = node_new;
+            let node_new_next = nodes[node_new].next;
+            if !node_new_next.is_null() {
+                nodes[node_new_next].prev = node_new;
+            }
+        }
+    }
+
+    fn push_before<TOrd: RType, V>(
+        &mut self,
+        nodes: &mut NodePool<TOrd, V>,
+        node_next: NodeId,
+        node_new: NodeId,
+    ) {
+        // node_new before node_next
+        self.len += 1;
+
+        // empty list
+        if self.first.is_null() {
+            self.first = node_new;
+            self.last = node_new;
+            debug_assert!(nodes[node_new].next.is_null() &&
+                          nodes[node_new].prev.is_null());
+        } else if node_next.is_null() {
+            // insert at end of list
+            nodes[node_new].prev = self.last;
+            nodes[node_new].next = NodeId::NULL;
+            nodes[self.last].next = node_new;
+            self.last = node_new;
+        } else {
+            // at beginning of list
+            if self.first == node_next {
+                self.first = node_new;
+            }
+
+            nodes[node_new].next = node_next;
+            nodes[node_new].prev = nodes[node_next].prev;
+            nodes[node_next].prev = node_new;
+            let node_new_prev = nodes[node_new].prev;
+            if !node_new_prev.is_null() {
+                nodes[node_new_prev].next = node_new;
+            }
+        }
+    }
+
+    fn remove<TOrd: RType, V>(
+        &mut self,
+        nodes: &mut NodePool<TOrd, V>,
+        node: NodeId,
+    ) {
+        debug_assert!(self.len != 0);
+        self.len -= 1;
+        let node_next = nodes[node].next;
+        if !node_next.is_null() {
+            nodes[node_next].prev = nodes[node].prev;
+        }
+        let node_prev = nodes[node].prev;
+        if !node_prev.is_null() {
+            nodes[node_prev].next = nodes[node].next;
+        }
+
+        if self.last == node {
+            self.last = nodes[node].prev;
+        }
+        if self.first == node {
+            self.first = nodes[node].next;
+        }
+    }
+
+    fn clear(
+        &mut self,
+    ) {
+        self.first = NodeId::NULL;
+        self.last = NodeId::NULL;
+        self.len = 0;
+    }
+
+}
+
+// ----------------------------------------------------------------------------
+// BTree API
+
+mod rb {
+    use types::{
+        NodeId,
+        NodePool,
+        RType,
+    };
+    use count_offset_between;
+
+    const RED: bool = false;
     const BLACK: bool = true;
 
     macro_rules! key {
@@ -334,9 +1210,11 @@ mod rb {
         }
     }
 
-    fn is_red<TOrd: RType>(node: *mut Node<TOrd>) -> bool
-    {
-        !node.is_null() && unsafe { (*node).color } == RED
+    pub fn is_red<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        node: NodeId,
+    ) -> bool {
+        !node.is_null() && nodes[node].color == RED
     }
 
     fn key_cmp<TOrd: RType>(
@@ -352,333 +1230,626 @@ mod rb {
         }
     }
 
-    fn rotate_left<TOrd: RType>(
-        left: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
-        let left = unsafe { &mut *left };
-        let right = unsafe { &mut *left.right };
-        left.right = right.left;
-        right.left = left;
-        right.color = left.color;
-        left.color = RED;
+    // Return the number of values in the ranges of the subtree at 'node'.
+    #[inline]
+    pub fn count<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        node: NodeId,
+    ) -> u128 {
+        if node.is_null() {
+            0
+        } else {
+            nodes[node].count
+        }
+    }
+
+    // Return the number of values in the largest range of the subtree at 'node'.
+    #[inline]
+    pub fn count_max<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        node: NodeId,
+    ) -> u128 {
+        if node.is_null() {
+            0
+        } else {
+            nodes[node].count_max
+        }
+    }
+
+    // Recalculate the counts of 'node' from its range and children.
+    fn count_update<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        node: NodeId,
+    ) {
+        let left = nodes[node].left;
+        let right = nodes[node].right;
+        let count_range = TOrd::count_range(&nodes[node].range);
+        nodes[node].count = count_range
+            .saturating_add(count(nodes, left))
+            .saturating_add(count(nodes, right));
+        nodes[node].count_max = ::core::cmp::max(
+            count_range,
+            ::core::cmp::max(count_max(nodes, left), count_max(nodes, right)),
+        );
+    }
+
+    fn rotate_left<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        left: NodeId,
+    ) -> NodeId {
+        let right = nodes[left].right;
+        nodes[left].right = nodes[right].left;
+        nodes[right].left = left;
+        nodes[right].color = nodes[left].color;
+        nodes[left].color = RED;
+        count_update(nodes, left);
+        count_update(nodes, right);
         right
     }
 
-    fn rotate_right<TOrd: RType>(
-        right: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
-        let right = unsafe { &mut *right };
-        let left = unsafe { &mut *right.left };
-        right.left = left.right;
-        left.right = right;
-        left.color = right.color;
-        right.color = RED;
+    fn rotate_right<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        right: NodeId,
+    ) -> NodeId {
+        let left = nodes[right].left;
+        nodes[right].left = nodes[left].right;
+        nodes[left].right = right;
+        nodes[left].color = nodes[right].color;
+        nodes[right].color = RED;
+        count_update(nodes, right);
+        count_update(nodes, left);
         left
     }
 
-    fn flip_color<TOrd: RType>(
-        node: *mut Node<TOrd>,
+    fn flip_color<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        node: NodeId,
     ) {
-        let node = unsafe { &mut *node };
-        let left = unsafe { &mut *node.left };
-        let right = unsafe { &mut *node.right };
-        node.color = !node.color;
-        left.color = !left.color;
-        right.color = !right.color;
+        let left = nodes[node].left;
+        let right = nodes[node].right;
+        nodes[node].color = !nodes[node].color;
+        nodes[left].color = !nodes[left].color;
+        nodes[right].color = !nodes[right].color;
     }
 
-    fn move_red_to_left<TOrd: RType>(
-        mut node: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
+    fn move_red_to_left<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        mut node: NodeId,
+    ) -> NodeId {
         // Assuming that h is red and both h.left and h.left.left
         // are black, make h.left or one of its children red.
-        flip_color(node);
-        if unsafe { !(*node).right.is_null() && is_red((*(*node).right).left) } {
-            unsafe {
-                (*node).right = rotate_right((*node).right);
-            }
-            node = rotate_left(node);
-            flip_color(node);
+        flip_color(nodes, node);
+        let right = nodes[node].right;
+        if !right.is_null() && is_red(nodes, nodes[right].left) {
+            nodes[node].right = rotate_right(nodes, right);
+            node = rotate_left(nodes, node);
+            flip_color(nodes, node);
         }
         node
     }
 
-    fn move_red_to_right<TOrd: RType>(
-        mut node: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
+    fn move_red_to_right<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        mut node: NodeId,
+    ) -> NodeId {
         // Assuming that h is red and both h.right and h.right.left
         // are black, make h.right or one of its children red.
-        flip_color(node);
-        if unsafe { !(*node).left.is_null() && is_red((*(*node).left).left) } {
-            node = rotate_right(node);
-            flip_color(node);
+        flip_color(nodes, node);
+        let left = nodes[node].left;
+        if !left.is_null() && is_red(nodes, nodes[left].left) {
+            node = rotate_right(nodes, node);
+            flip_color(nodes, node);
         }
         node
     }
 
-    pub fn insert_root<TOrd: RType>(
-        mut root: *mut Node<TOrd>,
-        node_to_insert: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
-        unsafe fn insert_recursive<TOrd: RType>(
-            mut node: *mut Node<TOrd>,
-            node_to_insert: *mut Node<TOrd>,
-        ) -> *mut Node<TOrd> {
-            if node.is_null() {
-                return node_to_insert;
-            }
-            // let node = unsafe { &mut *node };
+    // Maximum depth of a tree, a red-black tree is at most twice as deep as the
+    // binary logarithm of its size, which is bounded by the 32 bit node index.
+    const PATH_LEN: usize = 2 * 32 + 2;
 
-            let cmp = key_cmp(key!(*node_to_insert), key!(*node));
-            if cmp == -1 {
-                let left = insert_recursive((*node).left, node_to_insert);
-                (*node).left = left;
-            } else if cmp == 1 {
-                let right = insert_recursive((*node).right, node_to_insert);
-                (*node).right = right;
-            } else {
-                // we know this key won't already exist
-                unreachable!();
-            }
+    // Direction from a node to its child, recorded while descending,
+    // so the subtree can be re-linked and balanced on the way back up (without recursion).
+    #[derive(Clone, Copy, PartialEq)]
+    enum Dir {
+        Left,
+        Right,
+        // Descending into the right subtree to replace this node with its successor.
+        RightReplace,
+    }
 
-            if is_red((*node).right) && !is_red((*node).left) {
-                node = rotate_left(node);
-            }
-            if is_red((*node).left) && is_red((*(*node).left).left) {
-                node = rotate_right(node);
-            }
+    struct Path {
+        nodes: [(NodeId, Dir); PATH_LEN],
+        len: usize,
+    }
 
-            if is_red((*node).left) && is_red((*node).right) {
-                flip_color(node);
+    impl Path {
+        #[inline]
+        fn new() -> Path {
+            Path {
+                nodes: [(NodeId::NULL, Dir::Left); PATH_LEN],
+                len: 0,
             }
+        }
 
-            node
+        // Return false when the path is full (only possible for a corrupt tree).
+        #[inline]
+        fn push(
+            &mut self,
+            node: NodeId,
+            step: Dir,
+        ) -> bool {
+            match self.nodes.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = (node, step);
+                    self.len += 1;
+                    true
+                }
+                None => false,
+            }
         }
 
-        unsafe {
-            (*node_to_insert).color = RED;
-            root = insert_recursive(root, node_to_insert);
-            (*root).color = BLACK;
+        #[inline]
+        fn pop(
+            &mut self,
+        ) -> Option<(NodeId, Dir)> {
+            if self.len == 0 {
+                return None;
+            }
+            self.len -= 1;
+            self.nodes.get(self.len).cloned()
         }
-        root
     }
 
-    fn fixup_remove<TOrd: RType>(
-        mut node: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
-        unsafe {
-            if is_red((*node).right) {
-                node = rotate_left(node);
+    pub fn insert_root<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        root: NodeId,
+        node_to_insert: NodeId,
+    ) -> NodeId {
+        let mut path = Path::new();
+        let mut node = root;
+        while !node.is_null() {
+            let cmp = key_cmp(key!(nodes[node_to_insert]), key!(nodes[node]));
+            let step = if cmp == -1 {
+                Dir::Left
+            } else if cmp == 1 {
+                Dir::Right
+            } else {
+                // we know this key won't already exist
+                debug_assert!(false, "key already in the tree");
+                return root;
+            };
+            if !path.push(node, step) {
+                debug_assert!(false, "tree depth exceeds the maximum");
+                return root;
+            }
+            node = if step == Dir::Left { nodes[node].left } else { nodes[node].right };
+        }
+
+        let mut node_sub = node_to_insert;
+        nodes[node_to_insert].color = RED;
+        count_update(nodes, node_to_insert);
+        while let Some((mut node, step)) = path.pop() {
+            if step == Dir::Left {
+                nodes[node].left = node_sub;
+            } else {
+                nodes[node].right = node_sub;
+            }
+            count_update(nodes, node);
+
+            if is_red(nodes, nodes[node].right) && !is_red(nodes, nodes[node].left) {
+                node = rotate_left(nodes, node);
             }
-            if is_red((*node).left) && is_red((*(*node).left).left) {
-                node = rotate_right(node);
+            if is_red(nodes, nodes[node].left) && is_red(nodes, nodes[nodes[node].left].left) {
+                node = rotate_right(nodes, node);
             }
-            if is_red((*node).left) && is_red((*node).right) {
-                flip_color(node);
+            if is_red(nodes, nodes[node].left) && is_red(nodes, nodes[node].right) {
+                flip_color(nodes, node);
             }
-            node
+            node_sub = node;
         }
+        nodes[node_sub].color = BLACK;
+        node_sub
     }
 
-    fn pop_min_recursive<TOrd: RType>(
-        mut node: *mut Node<TOrd>,
-    ) -> (*mut Node<TOrd>, *mut Node<TOrd>) {
-        unsafe {
-            if node.is_null() {
-                return (ptr::null_mut(), ptr::null_mut());
-            } else if (*node).left.is_null() {
-                return (ptr::null_mut(), node);
-            } else if (!is_red((*node).left)) &&
-                      (!is_red((*(*node).left).left))
-            {
-                node = move_red_to_left(node);
-            }
-
-            let (node_left, node_free) = pop_min_recursive((*node).left);
-            (*node).left = node_left;
-            (fixup_remove(node), node_free)
+    fn fixup_remove<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        mut node: NodeId,
+    ) -> NodeId {
+        if is_red(nodes, nodes[node].right) {
+            node = rotate_left(nodes, node);
+        }
+        if is_red(nodes, nodes[node].left) && is_red(nodes, nodes[nodes[node].left].left) {
+            node = rotate_right(nodes, node);
+        }
+        if is_red(nodes, nodes[node].left) && is_red(nodes, nodes[node].right) {
+            flip_color(nodes, node);
         }
+        node
     }
 
-    pub fn remove_root<TOrd: RType>(
-        mut root: *mut Node<TOrd>,
-        node_to_remove: *mut Node<TOrd>,
-    ) -> *mut Node<TOrd> {
+    pub fn remove_root<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        root: NodeId,
+        node_to_remove: NodeId,
+    ) -> NodeId {
+        let mut path = Path::new();
+        let mut node = root;
+        // the successor of 'node_to_remove', once found.
+        let mut node_free = NodeId::NULL;
+        // true while descending to the successor of 'node_to_remove'.
+        let mut pop_min = false;
 
-        unsafe fn remove_recursive<TOrd: RType>(
-            mut node: *mut Node<TOrd>,
-            node_to_remove: *mut Node<TOrd>,
-        ) -> *mut Node<TOrd> {
+        // Descend, making the next node red (as the recursive method does).
+        let node_sub = loop {
             if node.is_null() {
-                return ptr::null_mut();
+                break NodeId::NULL;
             }
-
-            if key_cmp(key!(*node_to_remove), key!(*node)) == -1 {
-                if !(*node).left.is_null() {
-                    if (!is_red((*node).left)) &&
-                       (!is_red((*(*node).left).left))
-                    {
-                        node = move_red_to_left(node);
-                    }
+            let step = if pop_min {
+                if nodes[node].left.is_null() {
+                    node_free = node;
+                    break NodeId::NULL;
+                }
+                if (!is_red(nodes, nodes[node].left)) &&
+                   (!is_red(nodes, nodes[nodes[node].left].left))
+                {
+                    node = move_red_to_left(nodes, node);
+                }
+                Dir::Left
+            } else if key_cmp(key!(nodes[node_to_remove]), key!(nodes[node])) == -1 {
+                if !nodes[node].left.is_null() &&
+                   (!is_red(nodes, nodes[node].left)) &&
+                   (!is_red(nodes, nodes[nodes[node].left].left))
+                {
+                    node = move_red_to_left(nodes, node);
                 }
-                (*node).left = remove_recursive((*node).left, node_to_remove);
+                Dir::Left
             } else {
-                if is_red((*node).left) {
-                    node = rotate_right(node);
+                if is_red(nodes, nodes[node].left) {
+                    node = rotate_right(nodes, node);
                 }
-                if (node == node_to_remove) && ((*node).right.is_null()) {
+                if (node == node_to_remove) && (nodes[node].right.is_null()) {
                     // 'node' removed
-                    return ptr::null_mut();
+                    break NodeId::NULL;
                 }
-                debug_assert!(!(*node).right.is_null());
-                if (!is_red((*node).right)) &&
-                   (!is_red((*(*node).right).left))
+                debug_assert!(!nodes[node].right.is_null());
+                if (!is_red(nodes, nodes[node].right)) &&
+                   (!is_red(nodes, nodes[nodes[node].right].left))
                 {
-                    node = move_red_to_right(node);
+                    node = move_red_to_right(nodes, node);
                 }
-
                 if node == node_to_remove {
                     // minor improvement over original method
                     // no need to double lookup min
-                    let (
-                        node_right,
-                        node_free,
-                    ) = pop_min_recursive((*node).right);
-                    (*node).right = node_right;
-
-                    (*node_free).left = (*node).left;
-                    (*node_free).right = (*node).right;
-                    (*node_free).color = (*node).color;
-
-                    node = node_free;
+                    pop_min = true;
+                    Dir::RightReplace
                 } else {
-                    (*node).right = remove_recursive((*node).right, node_to_remove);
+                    Dir::Right
                 }
-                // 'node' removed
-            }
-            fixup_remove(node)
-        }
-
-        unsafe {
-            root = remove_recursive(root, node_to_remove);
-            if !root.is_null() {
-                (*root).color = BLACK;
+            };
+            if !path.push(node, step) {
+                debug_assert!(false, "tree depth exceeds the maximum");
+                return root;
             }
-        }
-        root
-    }
+            node = if step == Dir::Left { nodes[node].left } else { nodes[node].right };
+        };
 
-    pub fn get_or_lower<TOrd: RType>(
-        root: *mut Node<TOrd>,
-        key: &TOrd,
-    ) -> *mut Node<TOrd> {
-        unsafe fn get_or_lower_recursive<TOrd: RType>(
-            n: *mut Node<TOrd>,
-            key: &TOrd,
-        ) -> *mut Node<TOrd> {
-            // Check if (n.key >= key)
-            // to get the node directly after 'key'
-            // return best node and key_lower
-            let cmp_lower = key_cmp(key!(*n), key);
-            if cmp_lower == 0 {
-                n // exact match
-            } else if cmp_lower == -1 {
-                debug_assert!(key!(*n) <= &key);
-                // n is greater than our best so far
-                if !(*n).right.is_null() {
-                    let n_test = get_or_lower_recursive((*n).right, key);
-                    if !n_test.is_null() {
-                        return n_test;
-                    }
+        // Re-link and balance on the way back up.
+        let mut node_sub = node_sub;
+        while let Some((mut node, step)) = path.pop() {
+            match step {
+                Dir::Left => {
+                    nodes[node].left = node_sub;
+                }
+                Dir::Right => {
+                    nodes[node].right = node_sub;
                 }
-                n
-            } else {  // -1
-                if !(*n).left.is_null() {
-                    return get_or_lower_recursive((*n).left, key);
+                Dir::RightReplace => {
+                    // 'node' removed
+                    nodes[node].right = node_sub;
+                    nodes[node_free].left = nodes[node].left;
+                    nodes[node_free].right = nodes[node].right;
+                    nodes[node_free].color = nodes[node].color;
+                    node = node_free;
                 }
-                ptr::null_mut()
             }
+            count_update(nodes, node);
+            node_sub = fixup_remove(nodes, node);
         }
-
-        unsafe {
-            if !root.is_null() {
-                return get_or_lower_recursive(root, key);
-            }
+        if !node_sub.is_null() {
+            nodes[node_sub].color = BLACK;
         }
-        ptr::null_mut()
+        node_sub
     }
 
-    // External tree API
-    pub fn get_or_upper<TOrd: RType>(
-        root: *mut Node<TOrd>,
+    // Recalculate counts from the node with 'key' up to the root,
+    // needed when the node's range changes (without changing its order).
+    pub fn count_update_to_root<TOrd: RType, V>(
+        nodes: &mut NodePool<TOrd, V>,
+        root: NodeId,
         key: &TOrd,
-    ) -> *mut Node<TOrd> {
-        unsafe fn get_or_upper_recursive<TOrd: RType>(
-            n: *mut Node<TOrd>,
-            key: &TOrd,
-        ) -> *mut Node<TOrd> {
-            // Check if (n.key >= key)
-            // to get the node directly after 'key'
-            // return best node and key_upper
-            let cmp_upper = key_cmp(key!(*n), key);
-            if cmp_upper == 0 {
-                n // exact match
-            } else if cmp_upper == 1 {
-                debug_assert!(key!(*n) >= key);
-                // n is lower than our best so far
-                if !(*n).left.is_null() {
-                    let n_test = get_or_upper_recursive((*n).left, key);
-                    if !n_test.is_null() {
-                        return n_test;
-                    }
-                }
-                n
-            } else {  // -1
-                if !(*n).right.is_null() {
-                    return get_or_upper_recursive((*n).right, key);
-                }
-                ptr::null_mut()
+    ) {
+        let mut path = Path::new();
+        let mut node = root;
+        while !node.is_null() {
+            if !path.push(node, Dir::Left) {
+                debug_assert!(false, "tree depth exceeds the maximum");
+                return;
             }
+            let cmp = key_cmp(key, key!(nodes[node]));
+            node = if cmp == -1 {
+                nodes[node].left
+            } else if cmp == 1 {
+                nodes[node].right
+            } else {
+                break;
+            };
+        }
+        while let Some((node, _)) = path.pop() {
+            count_update(nodes, node);
         }
+    }
 
-        unsafe {
-            if !root.is_null() {
-                return get_or_upper_recursive(root, key);
+    // Return the node containing the value at 'offset' (counting values of all ranges in order)
+    // and the offset of the value within the node's range, the offset must be less than the count.
+    pub fn get_by_offset<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        mut offset: u128,
+    ) -> (NodeId, u128) {
+        let mut node = root;
+        while !node.is_null() {
+            let count_left = count(nodes, nodes[node].left);
+            if offset < count_left {
+                node = nodes[node].left;
+                continue;
+            }
+            offset -= count_left;
+            let count_node = TOrd::count_range(&nodes[node].range);
+            if offset < count_node {
+                return (node, offset);
             }
+            offset -= count_node;
+            node = nodes[node].right;
         }
-        ptr::null_mut()
+        debug_assert!(false, "offset exceeds the count");
+        (NodeId::NULL, 0)
     }
 
-    pub fn is_balanced<TOrd: RType>(
-        root: *mut Node<TOrd>,
-    ) -> bool {
+    // Return the lowest node with at least 'count' values in its range.
+    pub fn get_first_with_count<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        count: u128,
+    ) -> NodeId {
+        let mut node = root;
+        if node.is_null() || count_max(nodes, node) < count {
+            return NodeId::NULL;
+        }
+        while !node.is_null() {
+            let left = nodes[node].left;
+            if count_max(nodes, left) >= count {
+                node = left;
+            } else if TOrd::count_range(&nodes[node].range) >= count {
+                return node;
+            } else {
+                node = nodes[node].right;
+            }
+        }
+        debug_assert!(false, "subtree maximum count is incorrect");
+        NodeId::NULL
+    }
 
-        fn is_balanced_recursive<TOrd: RType>(
-            node: *mut Node<TOrd>,
-            mut black: isize,
-        ) -> bool {
-            if node.is_null() {
-                return black == 0;
+    // Return the node with the fewest values in its range of at least 'count'
+    // (the lowest when there are multiple), NULL when there is none.
+    //
+    // Subtrees without a large enough range are skipped,
+    // the remaining nodes are visited in order until an exact fit is found.
+    pub fn get_best_with_count<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        count: u128,
+    ) -> NodeId {
+        let mut node_best = NodeId::NULL;
+        let mut node_best_count: u128 = 0;
+        let mut stack: [NodeId; PATH_LEN] = [NodeId::NULL; PATH_LEN];
+        let mut stack_len = 0;
+        let mut node = root;
+        loop {
+            while !node.is_null() && count_max(nodes, node) >= count {
+                match stack.get_mut(stack_len) {
+                    Some(slot) => *slot = node,
+                    None => {
+                        debug_assert!(false, "deeper than any balanced tree");
+                        return node_best;
+                    }
+                }
+                stack_len += 1;
+                node = nodes[node].left;
+            }
+            if stack_len == 0 {
+                return node_best;
+            }
+            stack_len -= 1;
+            node = match stack.get(stack_len) {
+                Some(&node) => node,
+                None => return node_best,
+            };
+            let node_count = TOrd::count_range(&nodes[node].range);
+            if node_count >= count && (node_best.is_null() || node_count < node_best_count) {
+                node_best = node;
+                node_best_count = node_count;
+                if node_count == count {
+                    return node_best;
+                }
+            }
+            node = nodes[node].right;
+        }
+    }
+
+    // Return the number of values in ranges before 'key'.
+    pub fn rank<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        key: &TOrd,
+    ) -> u128 {
+        let mut rank: u128 = 0;
+        let mut node = root;
+        while !node.is_null() {
+            let range = &nodes[node].range;
+            if *key < range[0] {
+                node = nodes[node].left;
+                continue;
+            }
+            rank = rank.saturating_add(count(nodes, nodes[node].left));
+            if *key <= range[1] {
+                return rank.saturating_add(count_offset_between(range[0], *key));
+            }
+            rank = rank.saturating_add(TOrd::count_range(range));
+            node = nodes[node].right;
+        }
+        rank
+    }
+
+    pub fn get_or_lower<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        key: &TOrd,
+    ) -> NodeId {
+        // the greatest node less than 'key' so far.
+        let mut node_best = NodeId::NULL;
+        let mut n = root;
+        while !n.is_null() {
+            let cmp_lower = key_cmp(key!(nodes[n]), key);
+            if cmp_lower == 0 {
+                return n; // exact match
+            } else if cmp_lower == -1 {
+                node_best = n;
+                n = nodes[n].right;
+            } else {
+                n = nodes[n].left;
             }
-            if !is_red(node) {
-                black -= 1;
+        }
+        node_best
+    }
+
+    // External tree API
+    pub fn get_or_upper<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+        key: &TOrd,
+    ) -> NodeId {
+        // the lowest node greater than 'key' so far.
+        let mut node_best = NodeId::NULL;
+        let mut n = root;
+        while !n.is_null() {
+            let cmp_upper = key_cmp(key!(nodes[n]), key);
+            if cmp_upper == 0 {
+                return n; // exact match
+            } else if cmp_upper == 1 {
+                node_best = n;
+                n = nodes[n].left;
+            } else {
+                n = nodes[n].right;
             }
-            is_balanced_recursive(unsafe { (*node).left }, black) &&
-            is_balanced_recursive(unsafe { (*node).right }, black)
         }
+        node_best
+    }
 
+    pub fn is_balanced<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        root: NodeId,
+    ) -> bool {
         let mut black: isize = 0;
         let mut node = root;
         while !node.is_null() {
-            if !is_red(node) {
+            if !is_red(nodes, node) {
                 black += 1;
             }
-            node = unsafe { (*node).left };
+            node = nodes[node].left;
+        }
+
+        // Depth first, every path to a leaf must have the same number of black nodes,
+        // right children are stored with the black count remaining below them.
+        let mut stack: [(NodeId, isize); PATH_LEN] = [(NodeId::NULL, 0); PATH_LEN];
+        let mut stack_len = 0;
+        let mut node = root;
+        loop {
+            while !node.is_null() {
+                if !is_red(nodes, node) {
+                    black -= 1;
+                }
+                match stack.get_mut(stack_len) {
+                    Some(slot) => *slot = (nodes[node].right, black),
+                    // deeper than any balanced tree.
+                    None => return false,
+                }
+                stack_len += 1;
+                node = nodes[node].left;
+            }
+            if black != 0 {
+                return false;
+            }
+            if stack_len == 0 {
+                return true;
+            }
+            stack_len -= 1;
+            match stack.get(stack_len) {
+                Some(&(node_right, black_right)) => {
+                    node = node_right;
+                    black = black_right;
+                }
+                None => return false,
+            }
+        }
+    }
+
+    /// Return the number of nodes in the tree.
+    pub fn len<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        node: NodeId,
+    ) -> usize {
+        if node.is_null() {
+            return 0;
         }
-        is_balanced_recursive(root, black)
+        1 + len(nodes, nodes[node].left) + len(nodes, nodes[node].right)
     }
 
+    /// Return the number of nodes on the longest path from the root.
+    pub fn depth<TOrd: RType, V>(
+        nodes: &NodePool<TOrd, V>,
+        node: NodeId,
+    ) -> usize {
+        if node.is_null() {
+            return 0;
+        }
+        1 + ::core::cmp::max(
+            depth(nodes, nodes[node].left),
+            depth(nodes, nodes[node].right),
+        )
+    }
+
+
+}
+
+
+// ----------------------------------------------------------------------------
+// Random (feature)
 
+/// Return a uniformly distributed number in `0..n` (`n` must be non-zero).
+#[cfg(feature = "rand")]
+fn random_below<R: rand_core::RngCore + ?Sized>(
+    rng: &mut R,
+    n: u128,
+) -> u128 {
+    debug_assert!(n != 0);
+    // reject values in the incomplete range at the top, to avoid bias.
+    let zone = u128::MAX - (u128::MAX - n + 1) % n;
+    loop {
+        let value = ((rng.next_u64() as u128) << 64) | (rng.next_u64() as u128);
+        if value <= zone {
+            return value % n;
+        }
+    }
 }
 
 
@@ -695,46 +1866,60 @@ impl<TOrd: RType> RangeTree<TOrd> {
     fn node_alloc(
         &mut self,
         node_data: Node<TOrd>,
-    ) -> *mut Node<TOrd> {
-        self.node_pool.alloc_elem_from(node_data)
+    ) -> NodeId {
+        self.nodes.alloc_elem_from(node_data)
     }
     #[inline]
     fn node_free(
         &mut self,
-        node: *mut Node<TOrd>,
+        node: NodeId,
     ) {
-        self.node_pool.free_elem(unsafe { &mut *node });
+        self.nodes.free_elem(node);
     }
 
     // ------------------------------------------------------------------------
-    // Tree API: USE_BTREE
+    // Tree API: Backend::RbTree
 
     fn tree_insert(
         &mut self,
-        node: *mut Node<TOrd>,
+        node: NodeId,
     ) {
-        debug_assert!(unsafe { (*node).left.is_null() &&
-                               (*node).right.is_null() });
-        self.root = rb::insert_root(self.root, node);
-        debug_assert!(rb::is_balanced(self.root));
+        debug_assert!(self.nodes[node].left.is_null() &&
+                      self.nodes[node].right.is_null());
+        self.root = rb::insert_root(&mut self.nodes, self.root, node);
+        debug_assert!(rb::is_balanced(&self.nodes, self.root));
     }
 
     fn tree_remove(
         &mut self,
-        node: *mut Node<TOrd>,
+        node: NodeId,
     ) {
-        self.root = rb::remove_root(self.root, node);
-        debug_assert!(rb::is_balanced(self.root));
+        self.root = rb::remove_root(&mut self.nodes, self.root, node);
+        debug_assert!(rb::is_balanced(&self.nodes, self.root));
     }
 
-    fn tree_clear(
+    // Update the subtree counts after the range of the node with 'key' changes.
+    fn tree_count_update(
         &mut self,
+        key: TOrd,
     ) {
-        if USE_BTREE {
-            self.root = ptr::null_mut();
+        if self.backend == Backend::RbTree {
+            rb::count_update_to_root(&mut self.nodes, self.root, &key);
         }
     }
 
+    fn tree_depth(
+        &self,
+    ) -> usize {
+        rb::depth(&self.nodes, self.root)
+    }
+
+    fn tree_clear(
+        &mut self,
+    ) {
+        self.root = NodeId::NULL;
+    }
+
     // ------------------------------------------------------------------------
     // Node API
 
@@ -743,10 +1928,11 @@ impl<TOrd: RType> RangeTree<TOrd> {
         range: [TOrd; 2],
     ) {
         let node = self.node_alloc(RangeTree::new_node(range));
-        self.list.push_front(node);
-        if USE_BTREE {
+        self.list.push_front(&mut self.nodes, node);
+        if self.backend == Backend::RbTree {
             self.tree_insert(node);
         }
+        self.notify(SpanEvent::Added(range));
     }
 
     fn node_add_back(
@@ -754,60 +1940,128 @@ impl<TOrd: RType> RangeTree<TOrd> {
         range: [TOrd; 2],
     ) {
         let node = self.node_alloc(RangeTree::new_node(range));
-        self.list.push_back(node);
-        if USE_BTREE {
+        self.list.push_back(&mut self.nodes, node);
+        if self.backend == Backend::RbTree {
             self.tree_insert(node);
         }
+        self.notify(SpanEvent::Added(range));
     }
 
     fn node_add_before(
         &mut self,
-        node_next: *mut Node<TOrd>,
+        node_next: NodeId,
         range: [TOrd; 2],
     ) {
         let node = self.node_alloc(RangeTree::new_node(range));
-        self.list.push_before(node_next, node);
-        if USE_BTREE {
+        self.list.push_before(&mut self.nodes, node_next, node);
+        if self.backend == Backend::RbTree {
             self.tree_insert(node);
         }
+        self.notify(SpanEvent::Added(range));
     }
 
     fn node_add_after(
         &mut self,
-        node_prev: *mut Node<TOrd>,
+        node_prev: NodeId,
         range: [TOrd; 2],
     ) {
         let node = self.node_alloc(RangeTree::new_node(range));
-        self.list.push_after(node_prev, node);
-        if USE_BTREE {
+        self.list.push_after(&mut self.nodes, node_prev, node);
+        if self.backend == Backend::RbTree {
             self.tree_insert(node);
         }
+        self.notify(SpanEvent::Added(range));
     }
 
     fn node_remove(
         &mut self,
-        node: *mut Node<TOrd>,
+        node: NodeId,
     ) {
-        if USE_BTREE {
+        if self.backend == Backend::RbTree {
             self.tree_remove(node);
         }
-        self.list.remove(node);
+        self.list.remove(&mut self.nodes, node);
+        let range = self.nodes[node].range;
         self.node_free(node);
+        self.notify(SpanEvent::Removed(range));
+    }
+
+    // Change the minimum of 'node', keeping its order with other nodes.
+    fn node_set_min(
+        &mut self,
+        node: NodeId,
+        value: TOrd,
+    ) {
+        let range = self.nodes[node].range;
+        self.nodes[node].range[0] = value;
+        self.tree_count_update(value);
+        self.notify(SpanEvent::Resized { old: range, new: [value, range[1]] });
+    }
+
+    // Change the maximum of 'node', keeping its order with other nodes.
+    fn node_set_max(
+        &mut self,
+        node: NodeId,
+        value: TOrd,
+    ) {
+        let range = self.nodes[node].range;
+        self.nodes[node].range[1] = value;
+        self.tree_count_update(range[0]);
+        self.notify(SpanEvent::Resized { old: range, new: [range[0], value] });
+    }
+
+    #[inline]
+    fn notify(
+        &mut self,
+        event: SpanEvent<TOrd>,
+    ) {
+        if let Some(ref mut observer) = self.observer {
+            observer_call(observer, event);
+        }
     }
 
     fn new_empty(
         range: [TOrd; 2],
+        chunk_size: usize,
     ) -> RangeTree<TOrd> {
         RangeTree {
-            range: range,
+            range,
             list: List {
-                first: ptr::null_mut(),
-                last: ptr::null_mut(),
+                first: NodeId::NULL,
+                last: NodeId::NULL,
+                len: 0,
             },
-            node_pool: mempool_elem::MemPool::new(1024),
+            nodes: mempool_elem::MemPool::new(chunk_size),
+
+            root: NodeId::NULL,
+            backend: Backend::RbTree,
+
+            count_untaken: 0,
+
+            seq: 0,
+            journal: None,
+            journal_seq: 0,
+            history: None,
+            observer: None,
+
+            #[cfg(feature = "diagnostics")]
+            labels: ::alloc::collections::BTreeMap::new(),
+
+            growth: None,
 
-            // USE_BTREE
-            root: ptr::null_mut(),
+            release_pending: None,
+            release_pending_limit: 0,
+
+            #[cfg(feature = "handles")]
+            release_queue: None,
+
+            #[cfg(feature = "std")]
+            reservations: reservation::Reservations::new(),
+
+            rate_history: None,
+            rate_history_limit: 0,
+
+            count_taken_max: 0,
         }
     }
 
@@ -815,81 +2069,143 @@ impl<TOrd: RType> RangeTree<TOrd> {
         range: [TOrd; 2],
     ) -> Node<TOrd> {
         Node {
-            next: ptr::null_mut(),
-            prev: ptr::null_mut(),
+            next: NodeId::NULL,
+            prev: NodeId::NULL,
 
-            range: range,
+            range,
 
-            left: ptr::null_mut(),
-            right: ptr::null_mut(),
+            left: NodeId::NULL,
+            right: NodeId::NULL,
             color: false,
+            count: 0,
+            count_max: 0,
+
+            value: (),
         }
     }
 
     fn find_node_from_value(
         &self,
         value: &TOrd,
-    ) -> *mut Node<TOrd> {
-        if USE_BTREE {
-            let node = rb::get_or_lower(self.root, value);
-            if !node.is_null() {
-                let node = unsafe { &mut *node };
-                if (value >= &node.range[0]) &&
-                   (value <= &node.range[1])
-                {
-                    return node
-                }
+    ) -> NodeId {
+        if self.backend == Backend::RbTree {
+            let node = rb::get_or_lower(&self.nodes, self.root, value);
+            if !node.is_null() &&
+               (value >= &self.nodes[node].range[0]) &&
+               (value <= &self.nodes[node].range[1])
+            {
+                return node;
             }
-            ptr::null_mut()
+            NodeId::NULL
         } else {
             let mut node = self.list.first;
             while !node.is_null() {
-                if (value >= unsafe { &(*node).range[0] } ) &&
-                   (value <= unsafe { &(*node).range[1] } )
+                if (value >= &self.nodes[node].range[0]) &&
+                   (value <= &self.nodes[node].range[1])
                 {
                     return node;
                 }
-                node = unsafe { (*node).next };
+                node = self.nodes[node].next;
+            }
+            NodeId::NULL
+        }
+    }
+
+    // Return the node containing 'value', or the first node after it.
+    fn find_node_at_or_after(
+        &self,
+        value: &TOrd,
+    ) -> NodeId {
+        if self.backend == Backend::RbTree {
+            let node = rb::get_or_lower(&self.nodes, self.root, value);
+            if node.is_null() {
+                self.list.first
+            } else if &self.nodes[node].range[1] >= value {
+                node
+            } else {
+                self.nodes[node].next
             }
-            ptr::null_mut()
+        } else {
+            let mut node = self.list.first;
+            while !node.is_null() && &self.nodes[node].range[1] < value {
+                node = self.nodes[node].next;
+            }
+            node
+        }
+    }
+
+    // Return the node containing 'value', or the last node before it.
+    fn find_node_at_or_before(
+        &self,
+        value: &TOrd,
+    ) -> NodeId {
+        if self.backend == Backend::RbTree {
+            rb::get_or_lower(&self.nodes, self.root, value)
+        } else {
+            let mut node = self.list.last;
+            while !node.is_null() && &self.nodes[node].range[0] > value {
+                node = self.nodes[node].prev;
+            }
+            node
+        }
+    }
+
+    // Return the node containing all values in 'range' or null.
+    fn find_node_from_range(
+        &self,
+        range: &[TOrd; 2],
+    ) -> NodeId {
+        let node = self.find_node_from_value(&range[0]);
+        if !node.is_null() && self.nodes[node].range[1] >= range[1] {
+            node
+        } else {
+            NodeId::NULL
         }
     }
 
+    // Check all values in 'range' are taken.
+    fn is_range_taken(
+        &self,
+        range: &[TOrd; 2],
+    ) -> bool {
+        let node = self.find_node_at_or_after(&range[0]);
+        node.is_null() || self.nodes[node].range[0] > range[1]
+    }
+
     fn find_node_pair_around_value(
         &self,
         value: &TOrd,
-    ) -> (*mut Node<TOrd>, *mut Node<TOrd>) {
-        if value < unsafe { &(*(self.list.first)).range[0] } {
-            return (ptr::null_mut(), self.list.first);
-        } else if value > unsafe { &(*(self.list.last)).range[1] } {
-            return (self.list.last, ptr::null_mut());
+    ) -> (NodeId, NodeId) {
+        if value < &self.nodes[self.list.first].range[0] {
+            return (NodeId::NULL, self.list.first);
+        } else if value > &self.nodes[self.list.last].range[1] {
+            return (self.list.last, NodeId::NULL);
         } else {
-            if USE_BTREE {
-                let node_next = rb::get_or_upper(self.root, value);
+            if self.backend == Backend::RbTree {
+                let node_next = rb::get_or_upper(&self.nodes, self.root, value);
                 if !node_next.is_null() {
-                    let node_next = unsafe { &mut *node_next };
-                    let node_prev = unsafe { &mut *(*node_next).prev };
-                    if (&node_prev.range[1] < value) &&
-                       (&node_next.range[0] > value)
+                    let node_prev = self.nodes[node_next].prev;
+                    if (&self.nodes[node_prev].range[1] < value) &&
+                       (&self.nodes[node_next].range[0] > value)
                     {
                         return (node_prev, node_next)
                     }
                 }
             } else {
                 let mut node_prev = self.list.first;
-                let mut node_next = unsafe { (*node_prev).next };
+                let mut node_next = self.nodes[node_prev].next;
                 while !node_next.is_null() {
-                    if unsafe {(&(*node_prev).range[1] < value) &&
-                               (&(*node_next).range[0] > value) }
+                    if (&self.nodes[node_prev].range[1] < value) &&
+                       (&self.nodes[node_next].range[0] > value)
                     {
                         return (node_prev, node_next)
                     }
                     node_prev = node_next;
-                    node_next = unsafe { (*node_next).next };
+                    node_next = self.nodes[node_next].next;
                 }
             }
         }
-        (ptr::null_mut(), ptr::null_mut())
+        (NodeId::NULL, NodeId::NULL)
     }
 
     /// Create a new range tree.
@@ -900,200 +2216,2421 @@ impl<TOrd: RType> RangeTree<TOrd> {
         range: [TOrd; 2],
         full: bool,
     ) -> RangeTree<TOrd> {
-        let mut r = RangeTree::new_empty(range);
+        RangeTree::new_with_chunk_size(range, full, NODE_CHUNK_SIZE)
+    }
+
+    // Create a tree where the node pool grows by 'chunk_size',
+    // for callers creating many small trees.
+    pub(crate) fn new_with_chunk_size(
+        range: [TOrd; 2],
+        full: bool,
+        chunk_size: usize,
+    ) -> RangeTree<TOrd> {
+        let mut r = RangeTree::new_empty(range, chunk_size);
         if !full {
             r.node_add_front(range);
+            r.count_untaken = TOrd::count_range(&range);
         }
         r
     }
 
-    /// Clear an existing range tree.
+    /// Create a new range tree over every value of the type,
+    /// `[TOrd::min_value(), TOrd::max_value()]`.
     ///
-    /// * `full` When true, the tree is reset with all values *taken*.
-    pub fn clear(
-        &mut self,
+    /// * `full` When true, the tree is created with all values *taken*.
+    pub fn new_full_domain(
         full: bool,
-    ) {
-        self.list.clear();
-        self.tree_clear();
-        self.node_pool.clear();
-
-        let range = [self.range[0], self.range[1]];
-        if !full {
-            self.node_add_front(range);
-        }
+    ) -> RangeTree<TOrd> where
+        TOrd: Bounded,
+    {
+        RangeTree::new([TOrd::min_value(), TOrd::max_value()], full)
     }
 
-    fn take_impl(
-        &mut self,
-        value: TOrd,
-        node: *mut Node<TOrd>,
-    ) {
-        unsafe {
-            if (*node).range[0] == value {
-                if (*node).range[1] != value {
-                    (*node).range[0] += TOrd::one();
-                } else {
-                    debug_assert!((*node).range[0] == (*node).range[1]);
-                    self.node_remove(node);
-                }
-            }
-            else if (*node).range[1] == value {
-                (*node).range[1] -= TOrd::one();
-            } else {
-                let range_next: [TOrd; 2] = [value + TOrd::one(), (*node).range[1]];
-                (*node).range[1] = value - TOrd::one();
-                self.node_add_after(node, range_next);
-            }
-        }
+    /// Return a builder for a tree over every value of the type (until bounds are set),
+    /// see `RangeTreeBuilder`.
+    pub fn builder<'a>() -> RangeTreeBuilder<'a, TOrd> where
+        TOrd: Bounded,
+    {
+        RangeTreeBuilder::new([TOrd::min_value(), TOrd::max_value()])
     }
 
-    /// Take a value from the tree.
+    /// Move a taken value to another tree,
+    /// releasing it from this tree and taking it from `dest`.
     ///
-    /// Note: taking a value which is already taken will panic.
-    /// use `retake` in cases when its not know.
-    pub fn take(
+    /// Both trees are checked before either is modified.
+    pub fn transfer(
         &mut self,
         value: TOrd,
-    ) {
-        let node = self.find_node_from_value(&value);
-        debug_assert!(!node.is_null());
-        self.take_impl(value, node);
+        dest: &mut RangeTree<TOrd>,
+    ) -> Result<(), TransferError> {
+        self.transfer_range([value, value], dest)
     }
 
-    /// Take a value which may already be taken,
-    /// returning true if the value didn't already exist in the tree.
-    pub fn retake(
+    /// Move a [minimum, maximum] range (inclusive) of taken values to another tree,
+    /// see `transfer`.
+    ///
+    /// Values pending from deferred releasing in this tree are flushed first.
+    pub fn transfer_range(
         &mut self,
-        value: TOrd,
-    ) -> bool {
-        let node = self.find_node_from_value(&value);
-        if !node.is_null() {
-            self.take_impl(value, node);
-            true
+        range: [TOrd; 2],
+        dest: &mut RangeTree<TOrd>,
+    ) -> Result<(), TransferError> {
+        if range[0] > range[1] {
+            return Err(TransferError::Source(RangeTreeError::InvalidRange));
+        }
+        if !self.in_bounds(&range[0]) || !self.in_bounds(&range[1]) {
+            return Err(TransferError::Source(RangeTreeError::OutOfBounds));
+        }
+        if !dest.in_bounds(&range[0]) || !dest.in_bounds(&range[1]) {
+            return Err(TransferError::Dest(RangeTreeError::OutOfBounds));
+        }
+        self.flush();
+        if !self.is_range_taken(&range) {
+            return Err(TransferError::Source(RangeTreeError::NotTaken));
+        }
+        let node = dest.find_node_from_range(&range);
+        if node.is_null() {
+            return Err(TransferError::Dest(RangeTreeError::AlreadyTaken));
+        }
+        if range[0] == range[1] {
+            self.release_impl(range[0]);
+            dest.take_impl(range[0], node);
         } else {
-            false
+            self.release_range_impl(range);
+            dest.take_range_impl(range, node);
         }
+        Ok(())
     }
 
-    /// Take any value from the range tree.
-    pub fn take_any(
-        &mut self,
-    ) -> Option<TOrd> {
-        if !self.list.first.is_null() {
-            let node = self.list.first;
-            let value = unsafe { (*node).range[0] };
-            if value == unsafe { (*node).range[1] } {
-                self.node_remove(node);
-            } else {
-                unsafe {
-                    (*self.list.first).range[0] += TOrd::one();
-                }
-            }
-            Some(value)
-        } else {
-            None
-        }
+    /// Return the [minimum, maximum] values (inclusive) of this range tree.
+    pub fn bounds(
+        &self,
+    ) -> [TOrd; 2] {
+        self.range
     }
 
-    /// Check if the tree has this value (not taken).
-    pub fn has(
+    #[inline]
+    fn in_bounds(
         &self,
-        value: TOrd,
+        value: &TOrd,
     ) -> bool {
-        if (value < self.range[0]) ||
-           (value > self.range[1])
-        {
-            return true;
+        (value >= &self.range[0]) && (value <= &self.range[1])
+    }
+
+    /// Return a view of this tree which can only take and release values within `range`.
+    ///
+    /// Changes are made directly to this tree.
+    /// Returns `None` when the range has its minimum greater than its maximum.
+    pub fn sub_allocator(
+        &mut self,
+        range: [TOrd; 2],
+    ) -> Option<SubAllocator<'_, TOrd>> {
+        if range[0] > range[1] {
+            return None;
         }
-        let node = self.find_node_from_value(&value);
-        !node.is_null()
+        Some(SubAllocator::new(self, range))
     }
 
-    /// Check if no values in the tree are taken.
-    pub fn is_empty(
+    /// Return a read-only view of this tree restricted to `range`,
+    /// for querying a window of values without copying them.
+    ///
+    /// Returns `None` when the range has its minimum greater than its maximum.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// r.take(12);
+    /// let window = r.slice([10, 19]).unwrap();
+    /// assert!(!window.has(12) && !window.has(20));
+    /// assert_eq!(window.count_untaken(), 9);
+    /// assert_eq!(window.iter_untaken().collect::<Vec<_>>(), [[10, 11], [13, 19]]);
+    /// ```
+    pub fn slice(
         &self,
-    ) -> bool {
-        if self.list.first.is_null() {
-            return false;  // NULL
+        range: [TOrd; 2],
+    ) -> Option<RangeTreeSlice<'_, TOrd>> {
+        if range[0] > range[1] {
+            return None;
         }
-        (self.list.first == self.list.last) &&
-        (unsafe { self.range[0] == (*self.list.first).range[0] }) &&
-        (unsafe { self.range[1] == (*self.list.first).range[1] })
+        Some(RangeTreeSlice::new(self, range))
     }
 
-    /// Check if all values in the tree are taken.
-    pub fn is_full(
-        &self,
-    ) -> bool {
-        self.list.first.is_null()
+    /// Start a transaction, where takes & releases are undone
+    /// unless `Transaction::commit` is called.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut ids: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// {
+    ///     let mut t = ids.transaction();
+    ///     t.take_any();
+    ///     t.try_take(5).unwrap();
+    ///     // dropped without committing.
+    /// }
+    /// assert_eq!(ids.count_taken(), 0);
+    ///
+    /// let mut t = ids.transaction();
+    /// t.try_take(5).unwrap();
+    /// t.commit();
+    /// assert!(!ids.has(5));
+    /// ```
+    pub fn transaction(
+        &mut self,
+    ) -> Transaction<'_, TOrd> {
+        Transaction::new(self)
     }
 
-    /// Release a value that has been taken.
-    pub fn release(
+    /// Take any value (see `take_any`), returning a guard which releases it when dropped
+    /// unless `ScopedValue::keep` is called.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut ids: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// {
+    ///     let id = ids.take_scoped().unwrap();
+    ///     assert_eq!(*id, 0);
+    ///     // dropped without keeping.
+    /// }
+    /// assert_eq!(ids.count_taken(), 0);
+    ///
+    /// assert_eq!(ids.take_scoped().unwrap().keep(), 0);
+    /// assert!(!ids.has(0));
+    /// ```
+    pub fn take_scoped(
         &mut self,
-        value: TOrd,
-    ) {
-        let (
-            touch_prev,
-            touch_next,
-            node_prev,
-            node_next,
-        ) = {
-            if !self.list.first.is_null() {
-                let (
-                    node_prev,
-                    node_next,
-                ) = self.find_node_pair_around_value(&value);
-                /* the value must have been already taken */
-                debug_assert!(!(node_prev.is_null() && node_next.is_null()));
+    ) -> Option<ScopedValue<'_, TOrd>> {
+        let value = self.take_any()?;
+        Some(ScopedValue::new(self, value))
+    }
 
-                /* Cases:
+    /// Take any value (see `take_any`), returning a handle which doesn't borrow the tree.
+    ///
+    /// Once the handle is dropped its value is released by the tree's next mutation
+    /// (any method taking `&mut self`, including `flush`),
+    /// until then it's reported as taken (as with deferred releasing).
+    ///
+    /// Handles which outlive `clear` or `invert` are detached from the tree,
+    /// dropping them has no effect.
+    ///
+    /// Note: releasing a handle's value directly (instead of dropping the handle) isn't detected,
+    /// if the value is taken again before the handle is dropped, dropping the handle releases it.
+    /// Use `OwnedValue::keep` before releasing the value directly.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut ids: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// let id = ids.take_handle().unwrap();
+    /// assert_eq!(*id, 0);
+    /// drop(id);
+    /// assert!(!ids.has(0));
+    /// ids.flush();
+    /// assert!(ids.has(0));
+    /// ```
+    #[cfg(feature = "handles")]
+    pub fn take_handle(
+        &mut self,
+    ) -> Option<OwnedValue<TOrd>> {
+        let value = self.take_any()?;
+        let queue = self.release_queue.get_or_insert_with(|| {
+            ::std::sync::Arc::new(handle::ReleaseQueue::new())
+        });
+        Some(OwnedValue::new(value, queue.clone()))
+    }
+
+    // Detach existing handles, so dropping them doesn't release values which may be taken again.
+    #[inline]
+    fn release_queue_detach(
+        &mut self,
+    ) {
+        #[cfg(feature = "handles")]
+        {
+            self.release_queue = None;
+        }
+    }
+
+    // Release the values of dropped handles, see `take_handle`.
+    #[inline]
+    fn release_queue_drain(
+        &mut self,
+    ) {
+        #[cfg(feature = "handles")]
+        {
+            let values = match self.release_queue {
+                Some(ref queue) => queue.drain(),
+                None => return,
+            };
+            for value in values {
+                // the value may have been released directly,
+                // or be outside the bounds after they're reduced.
+                if self.in_bounds(&value) && self.find_node_from_value(&value).is_null() {
+                    self.release_impl(value);
+                }
+            }
+        }
+    }
+
+    /// Take every value in the [minimum, maximum] range (inclusive) until the reservation
+    /// is committed (keeping them taken) or cancelled (releasing them),
+    /// returning an error when any value is out of bounds or already taken (nothing is taken).
+    ///
+    /// Reserved values are excluded from `take_any` and can't be released by `try_release`.
+    /// When `deadline` is reached the reservation expires, releasing its values.
+    /// Expiry is checked by `take_any`, `take_any_max` & `commit_reservation`
+    /// (with the `no-panic` feature, only by `reap_reservations` & `commit_reservation`).
+    ///
+    /// Values pending from deferred releasing are flushed first (so they can be reserved).
+    /// Reservations are removed by `clear` & `invert`.
+    ///
+    /// Note: releasing reserved values by other means (such as `release`) isn't detected.
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut ids: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// let id = ids.reserve_range([0, 9], Some(Instant::now() + Duration::from_secs(30))).unwrap();
+    /// assert_eq!(ids.take_any(), Some(10));
+    /// assert_eq!(ids.commit_reservation(id), Ok([0, 9]));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn reserve_range(
+        &mut self,
+        range: [TOrd; 2],
+        deadline: Option<::std::time::Instant>,
+    ) -> Result<ReservationId, RangeTreeError> {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        if !self.in_bounds(&range[0]) || !self.in_bounds(&range[1]) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.flush();
+        let node = self.find_node_from_range(&range);
+        if node.is_null() {
+            return Err(RangeTreeError::AlreadyTaken);
+        }
+        if range[0] == range[1] {
+            self.take_impl(range[0], node);
+        } else {
+            self.take_range_impl(range, node);
+        }
+        Ok(self.reservations.insert(range, deadline))
+    }
+
+    /// Keep the values of a reservation taken, returning their range.
+    ///
+    /// Returns `RangeTreeError::Expired` when the reservation has expired
+    /// (its values are released), or was already committed or cancelled.
+    #[cfg(feature = "std")]
+    pub fn commit_reservation(
+        &mut self,
+        id: ReservationId,
+    ) -> Result<[TOrd; 2], RangeTreeError> {
+        self.reap_reservations();
+        self.reservations.remove(id).ok_or(RangeTreeError::Expired)
+    }
+
+    /// Release the values of a reservation, returning their range.
+    ///
+    /// Returns `RangeTreeError::Expired` when the reservation has expired,
+    /// or was already committed or cancelled.
+    #[cfg(feature = "std")]
+    pub fn cancel_reservation(
+        &mut self,
+        id: ReservationId,
+    ) -> Result<[TOrd; 2], RangeTreeError> {
+        let range = self.reservations.remove(id).ok_or(RangeTreeError::Expired)?;
+        self.release_all_in(range);
+        Ok(range)
+    }
+
+    /// Release the values of reservations whose deadline has been reached,
+    /// returning the number of reservations which expired.
+    #[cfg(feature = "std")]
+    pub fn reap_reservations(
+        &mut self,
+    ) -> usize {
+        if !self.reservations.is_expiring() {
+            return 0;
+        }
+        let ranges = self.reservations.remove_expired(::std::time::Instant::now());
+        for &range in &ranges {
+            self.release_all_in(range);
+        }
+        ranges.len()
+    }
+
+    /// Check if the value is held by a reservation, see `reserve_range`.
+    #[cfg(feature = "std")]
+    pub fn is_reserved(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        self.reservations.contains(value)
+    }
+
+    fn from_ranges_untaken_impl<I>(
+        range: [TOrd; 2],
+        ranges: I,
+        chunk_size: usize,
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> where
+        I: IntoIterator<Item=[TOrd; 2]>,
+    {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        let mut r = RangeTree::new_empty(range, chunk_size);
+        for range_untaken in ranges {
+            if range_untaken[0] > range_untaken[1] {
+                return Err(RangeTreeError::InvalidRange);
+            }
+            if !r.in_bounds(&range_untaken[0]) || !r.in_bounds(&range_untaken[1]) {
+                return Err(RangeTreeError::OutOfBounds);
+            }
+            // Only saturates when every value of a 128 bit type is untaken.
+            r.count_untaken = r.count_untaken.saturating_add(TOrd::count_range(&range_untaken));
+            let node_last = r.list.last;
+            if !node_last.is_null() {
+                let range_last = r.nodes[node_last].range;
+                if range_last[1] >= range_untaken[0] {
+                    return Err(RangeTreeError::InvalidRange);
+                }
+                if range_last[1].precedes(range_untaken[0]) {
+                    r.node_set_max(node_last, range_untaken[1]);
+                    continue;
+                }
+            }
+            r.node_add_back(range_untaken);
+        }
+        Ok(r)
+    }
+
+    /// Create a range tree from its untaken ranges,
+    /// all other values within `range` are taken.
+    ///
+    /// The ranges must be sorted, non-overlapping and within `range`,
+    /// adjacent ranges are merged.
+    ///
+    /// Returns `RangeTreeError::OutOfBounds` for ranges outside `range`
+    /// and `RangeTreeError::InvalidRange` for unsorted, overlapping or reversed ranges.
+    pub fn from_ranges_untaken(
+        range: [TOrd; 2],
+        ranges_untaken: &[[TOrd; 2]],
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> {
+        RangeTree::from_ranges_untaken_impl(range, ranges_untaken.iter().cloned(), NODE_CHUNK_SIZE)
+    }
+
+    /// Create a range tree from its taken ranges,
+    /// all other values within `range` are untaken.
+    ///
+    /// The same rules as `from_ranges_untaken` apply.
+    pub fn from_ranges_taken(
+        range: [TOrd; 2],
+        ranges_taken: &[[TOrd; 2]],
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> {
+        RangeTree::from_ranges_taken_impl(range, ranges_taken, NODE_CHUNK_SIZE)
+    }
+
+    fn from_ranges_taken_impl(
+        range: [TOrd; 2],
+        ranges_taken: &[[TOrd; 2]],
+        chunk_size: usize,
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        // Validate first, so the gaps between taken ranges can be computed without overflow.
+        let mut range_prev: Option<[TOrd; 2]> = None;
+        for range_taken in ranges_taken {
+            if range_taken[0] > range_taken[1] {
+                return Err(RangeTreeError::InvalidRange);
+            }
+            if range_taken[0] < range[0] || range_taken[1] > range[1] {
+                return Err(RangeTreeError::OutOfBounds);
+            }
+            if let Some(range_prev) = range_prev {
+                if range_prev[1] >= range_taken[0] {
+                    return Err(RangeTreeError::InvalidRange);
+                }
+            }
+            range_prev = Some(*range_taken);
+        }
+
+        let mut ranges_untaken = Vec::with_capacity(ranges_taken.len() + 1);
+        // The first untaken value after the previous taken range, `None` past the maximum.
+        let mut value_next = Some(range[0]);
+        for range_taken in ranges_taken {
+            if let Some(value) = value_next {
+                if let Some(value_max) = range_taken[0].pred_checked(value) {
+                    ranges_untaken.push([value, value_max]);
+                }
+            }
+            value_next = range_taken[1].succ_checked(range[1]);
+        }
+        if let Some(value) = value_next {
+            ranges_untaken.push([value, range[1]]);
+        }
+        RangeTree::from_ranges_untaken_impl(range, ranges_untaken, chunk_size)
+    }
+
+    /// Create a range tree with `values` taken (in any order, duplicates are ignored),
+    /// sorting the values and coalescing runs into ranges.
+    ///
+    /// Returns `RangeTreeError::OutOfBounds` when a value is outside `range`.
+    pub fn from_values_taken<I>(
+        range: [TOrd; 2],
+        values: I,
+    ) -> Result<RangeTree<TOrd>, RangeTreeError> where
+        I: IntoIterator<Item=TOrd>,
+    {
+        let mut values: Vec<TOrd> = values.into_iter().collect();
+        values.sort_unstable();
+        values.dedup();
+        let mut ranges_taken: Vec<[TOrd; 2]> = vec![];
+        for value in values {
+            match ranges_taken.last_mut() {
+                Some(range_prev) if range_prev[1].precedes(value) => range_prev[1] = value,
+                _ => ranges_taken.push([value, value]),
+            }
+        }
+        RangeTree::from_ranges_taken(range, &ranges_taken)
+    }
+
+    /// Return a compact binary encoding of the bounds and untaken spans,
+    /// read by `from_bytes`.
+    ///
+    /// The format is versioned and independent of the platform (see the `snapshot` module),
+    /// so it can be stored in files or sent between machines.
+    /// Values pending from deferred releasing are stored as taken.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 1000], false);
+    /// r.take(500);
+    /// let bytes = r.to_bytes();
+    /// assert_eq!(RangeTree::from_bytes(&bytes), Ok(r));
+    /// ```
+    pub fn to_bytes(
+        &self,
+    ) -> Vec<u8> where
+        TOrd: Bounded,
+    {
+        snapshot::encode(self)
+    }
+
+    /// Create a range tree from data written by `to_bytes`.
+    ///
+    /// Returns an error for data which is truncated, corrupt or written for another value type
+    /// (of a different size).
+    pub fn from_bytes(
+        bytes: &[u8],
+    ) -> Result<RangeTree<TOrd>, DecodeError> where
+        TOrd: Bounded,
+    {
+        snapshot::decode(bytes)
+    }
+
+    /// Create a range tree containing a single value.
+    ///
+    /// * `taken` When true, the value is *taken*.
+    pub fn singleton(
+        value: TOrd,
+        taken: bool,
+    ) -> RangeTree<TOrd> {
+        RangeTree::new([value, value], taken)
+    }
+
+    /// Clear an existing range tree.
+    ///
+    /// * `full` When true, the tree is reset with all values *taken*.
+    pub fn clear(
+        &mut self,
+        full: bool,
+    ) {
+        self.list.clear();
+        self.tree_clear();
+        self.nodes.clear();
+        self.notify(SpanEvent::Cleared);
+        #[cfg(feature = "diagnostics")]
+        self.labels.clear();
+        if let Some(ref mut pending) = self.release_pending {
+            pending.clear();
+        }
+        self.release_queue_detach();
+        #[cfg(feature = "std")]
+        self.reservations.clear();
+
+        let range = [self.range[0], self.range[1]];
+        if !full {
+            self.node_add_front(range);
+            self.count_untaken = TOrd::count_range(&range);
+        } else {
+            self.count_untaken = 0;
+        }
+        self.journal_push(RangeOp::Clear(full));
+    }
+
+    /// Swap taken and untaken values,
+    /// rebuilding the tree from the gaps between untaken ranges.
+    ///
+    /// Values pending from deferred releasing are flushed first.
+    pub fn invert(
+        &mut self,
+    ) {
+        self.flush();
+        let ranges = self.ranges_taken_as_vec();
+        self.list.clear();
+        self.tree_clear();
+        self.nodes.clear();
+        self.notify(SpanEvent::Cleared);
+        #[cfg(feature = "diagnostics")]
+        self.labels.clear();
+        self.release_queue_detach();
+        #[cfg(feature = "std")]
+        self.reservations.clear();
+
+        self.count_untaken = 0;
+        for range in ranges {
+            // Only saturates when every value of a 128 bit type is untaken.
+            self.count_untaken = self.count_untaken.saturating_add(TOrd::count_range(&range));
+            self.node_add_back(range);
+        }
+        self.journal_push(RangeOp::Invert);
+    }
+
+    fn take_impl(
+        &mut self,
+        value: TOrd,
+        node: NodeId,
+    ) {
+        self.journal_push(RangeOp::Take(value));
+        self.count_untaken -= 1;
+        if self.nodes[node].range[0] == value {
+            if self.nodes[node].range[1] != value {
+                self.node_set_min(node, self.nodes[node].range[0].succ());
+            } else {
+                debug_assert!(self.nodes[node].range[0] == self.nodes[node].range[1]);
+                self.node_remove(node);
+            }
+        }
+        else if self.nodes[node].range[1] == value {
+            self.node_set_max(node, self.nodes[node].range[1].pred());
+        } else {
+            let range_next: [TOrd; 2] = [value.succ(), self.nodes[node].range[1]];
+            self.node_set_max(node, value.pred());
+            self.node_add_after(node, range_next);
+        }
+    }
+
+    // Take 'range' which must be contained in 'node'.
+    fn take_range_impl(
+        &mut self,
+        range: [TOrd; 2],
+        node: NodeId,
+    ) {
+        self.journal_push(RangeOp::TakeRange(range));
+        self.count_untaken -= TOrd::count_range(&range);
+        debug_assert!(self.nodes[node].range[0] <= range[0] &&
+                      self.nodes[node].range[1] >= range[1]);
+        if self.nodes[node].range[0] == range[0] {
+            if self.nodes[node].range[1] != range[1] {
+                self.node_set_min(node, range[1].succ());
+            } else {
+                self.node_remove(node);
+            }
+        } else if self.nodes[node].range[1] == range[1] {
+            self.node_set_max(node, range[0].pred());
+        } else {
+            let range_next: [TOrd; 2] = [range[1].succ(), self.nodes[node].range[1]];
+            self.node_set_max(node, range[0].pred());
+            self.node_add_after(node, range_next);
+        }
+    }
+
+    /// Take a value from the tree.
+    ///
+    /// Note: taking a value which is already taken will panic.
+    /// use `retake` in cases when its not know.
+    /// With the `no-panic` feature the value is ignored instead (use `try_take` to detect this).
+    pub fn take(
+        &mut self,
+        value: TOrd,
+    ) {
+        self.release_queue_drain();
+        debug_assert!(self.in_bounds(&value));
+        let node = self.find_node_from_value(&value);
+        debug_assert!(!node.is_null());
+        #[cfg(feature = "no-panic")]
+        {
+            if node.is_null() {
+                return;
+            }
+        }
+        self.take_impl(value, node);
+    }
+
+    /// Take a value, returning an error (instead of panicking) when it's already taken.
+    pub fn try_take(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        self.release_queue_drain();
+        if !self.in_bounds(&value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        let node = self.find_node_from_value(&value);
+        if node.is_null() {
+            return Err(RangeTreeError::AlreadyTaken);
+        }
+        self.take_impl(value, node);
+        Ok(())
+    }
+
+    /// Take `value` when it's untaken, with a single lookup
+    /// (instead of `has` followed by `take`).
+    ///
+    /// Returns true when the value was taken by this call,
+    /// false when it was already taken or is outside the tree bounds.
+    pub fn take_if_available(
+        &mut self,
+        value: TOrd,
+    ) -> bool {
+        self.release_queue_drain();
+        let node = self.find_node_from_value(&value);
+        if !node.is_null() {
+            self.take_impl(value, node);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a value which may already be taken,
+    /// returning true if the value didn't already exist in the tree.
+    ///
+    /// This is the same as `take_if_available`, where the name describes the result.
+    pub fn retake(
+        &mut self,
+        value: TOrd,
+    ) -> bool {
+        self.take_if_available(value)
+    }
+
+    // Raise the maximum bound to 'value', the new values are untaken.
+    fn grow_max_impl(
+        &mut self,
+        value: TOrd,
+    ) {
+        debug_assert!(value > self.range[1]);
+        self.journal_push(RangeOp::GrowMax(value));
+        let value_min = self.range[1].succ();
+        self.range[1] = value;
+        // Only saturates when every value of a 128 bit type is untaken.
+        self.count_untaken = self.count_untaken.saturating_add(TOrd::count_range(&[value_min, value]));
+        let node = self.list.last;
+        if !node.is_null() && self.nodes[node].range[1].precedes(value_min) {
+            self.node_set_max(node, value);
+        } else {
+            self.node_add_back([value_min, value]);
+        }
+    }
+
+    // Lower the minimum bound to 'value', the new values are untaken.
+    fn grow_min_impl(
+        &mut self,
+        value: TOrd,
+    ) {
+        debug_assert!(value < self.range[0]);
+        self.journal_push(RangeOp::GrowMin(value));
+        let value_max = self.range[0].pred();
+        self.range[0] = value;
+        // Only saturates when every value of a 128 bit type is untaken.
+        self.count_untaken = self.count_untaken.saturating_add(TOrd::count_range(&[value, value_max]));
+        let node = self.list.first;
+        if !node.is_null() && value_max.precedes(self.nodes[node].range[0]) {
+            self.node_set_min(node, value);
+        } else {
+            self.node_add_front([value, value_max]);
+        }
+    }
+
+    /// Raise the maximum bound to `value`, the new values are untaken.
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when `value` is less than the maximum bound.
+    pub fn grow_max(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        self.release_queue_drain();
+        if value < self.range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        if value > self.range[1] {
+            self.grow_max_impl(value);
+        }
+        Ok(())
+    }
+
+    /// Lower the minimum bound to `value`, the new values are untaken.
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when `value` is greater than the minimum bound.
+    pub fn grow_min(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        self.release_queue_drain();
+        if value > self.range[0] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        if value < self.range[0] {
+            self.grow_min_impl(value);
+        }
+        Ok(())
+    }
+
+    // Lower the maximum bound to 'value', removing untaken values above it.
+    fn shrink_max_impl(
+        &mut self,
+        value: TOrd,
+    ) {
+        debug_assert!(value >= self.range[0] && value < self.range[1]);
+        self.journal_push(RangeOp::ShrinkMax(value));
+        let mut node = self.list.last;
+        while !node.is_null() && self.nodes[node].range[0] > value {
+            let node_prev = self.nodes[node].prev;
+            self.count_untaken = self.count_untaken.saturating_sub(TOrd::count_range(&self.nodes[node].range));
+            self.node_remove(node);
+            node = node_prev;
+        }
+        if !node.is_null() && self.nodes[node].range[1] > value {
+            let range_max = self.nodes[node].range[1];
+            self.count_untaken = self.count_untaken.saturating_sub(TOrd::count_range(&[value.succ(), range_max]));
+            self.node_set_max(node, value);
+        }
+        #[cfg(feature = "diagnostics")]
+        {
+            let values: Vec<TOrd> = self.labels.range(value..).map(|(k, _)| *k).filter(|k| *k > value).collect();
+            for value in values {
+                self.labels.remove(&value);
+            }
+        }
+        self.range[1] = value;
+    }
+
+    /// Lower the maximum bound to `value`, which fails when any values above it are taken.
+    ///
+    /// Values pending from deferred releasing are flushed first.
+    pub fn try_shrink_max(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), ShrinkError> {
+        self.release_queue_drain();
+        if !self.in_bounds(&value) {
+            return Err(ShrinkError::OutOfBounds);
+        }
+        self.flush();
+        if value < self.range[1] {
+            if !self.all_untaken_in([value.succ(), self.range[1]]) {
+                return Err(ShrinkError::Taken);
+            }
+            self.shrink_max_impl(value);
+        }
+        Ok(())
+    }
+
+    /// Lower the maximum bound to `value`, forgetting any taken values above it.
+    ///
+    /// Values pending from deferred releasing are flushed first.
+    pub fn force_shrink_max(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), ShrinkError> {
+        self.release_queue_drain();
+        if !self.in_bounds(&value) {
+            return Err(ShrinkError::OutOfBounds);
+        }
+        self.flush();
+        if value < self.range[1] {
+            self.shrink_max_impl(value);
+        }
+        Ok(())
+    }
+
+    /// Split the tree into two trees with the bounds `[minimum, value]` and `[value + 1, maximum]`,
+    /// each keeping the taken & untaken values within its bounds.
+    ///
+    /// The second tree uses the same backend, growth and deferred releasing as this tree,
+    /// the first tree keeps its settings except for growth (its maximum bound is now fixed).
+    /// Journaling, undo and observers only apply to the first tree.
+    /// Values pending from deferred releasing are flushed first.
+    ///
+    /// Returns `RangeTreeError::OutOfBounds` when `value` is outside the bounds or is the maximum bound.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// r.take(10);
+    /// r.take(60);
+    /// let (a, b) = r.split_at(49).unwrap();
+    /// assert_eq!(a.ranges_taken_as_vec(), [[10, 10]]);
+    /// assert_eq!((b.bounds(), b.ranges_taken_as_vec()), ([50, 99], vec![[60, 60]]));
+    /// ```
+    pub fn split_at(
+        mut self,
+        value: TOrd,
+    ) -> Result<(RangeTree<TOrd>, RangeTree<TOrd>), RangeTreeError> {
+        if !(self.in_bounds(&value) && value < self.range[1]) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.flush();
+        let range = [value.succ(), self.range[1]];
+        let mut ranges_untaken: Vec<[TOrd; 2]> = vec![];
+        let mut node = self.find_node_at_or_after(&range[0]);
+        while !node.is_null() {
+            let range_untaken = self.nodes[node].range;
+            ranges_untaken.push([::core::cmp::max(range_untaken[0], range[0]), range_untaken[1]]);
+            node = self.nodes[node].next;
+        }
+        let mut other = match RangeTree::from_ranges_untaken_impl(range, ranges_untaken, self.nodes.chunk_size()) {
+            Ok(other) => other,
+            Err(_) => unreachable!("spans of a valid tree"),
+        };
+        other.set_backend(self.backend);
+        other.growth = self.growth.take();
+        other.release_pending = self.release_pending.as_ref().map(|_| vec![]);
+        other.release_pending_limit = self.release_pending_limit;
+        #[cfg(feature = "diagnostics")]
+        {
+            other.labels = self.labels.split_off(&range[0]);
+        }
+        self.shrink_max_impl(value);
+        Ok((self, other))
+    }
+
+    /// Append a tree whose bounds start directly after this tree's maximum bound,
+    /// the reverse of `split_at`.
+    /// Spans at the boundary are merged and this tree's settings are kept.
+    ///
+    /// Values pending from deferred releasing (in either tree) are flushed first.
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the bounds of `other` don't follow this tree's.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut a: RangeTree<u32> = RangeTree::from_ranges_untaken([0, 9], &[[5, 9]]).unwrap();
+    /// let b: RangeTree<u32> = RangeTree::from_ranges_untaken([10, 19], &[[10, 12]]).unwrap();
+    /// a.append(b).unwrap();
+    /// assert_eq!(a.ranges_untaken_as_vec(), [[5, 12]]);
+    /// ```
+    pub fn append(
+        &mut self,
+        mut other: RangeTree<TOrd>,
+    ) -> Result<(), RangeTreeError> {
+        if !self.range[1].precedes(other.range[0]) {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.flush();
+        other.flush();
+        // Values of 'other' are added untaken, then its taken ranges are taken
+        // (so journaling & undo record the change).
+        self.grow_max_impl(other.range[1]);
+        let ranges = other.ranges_taken_as_vec();
+        self.take_ranges_untaken(&ranges);
+        #[cfg(feature = "diagnostics")]
+        self.labels.append(&mut other.labels);
+        Ok(())
+    }
+
+    // Grow the maximum bound by the growth increment (when set and the limit isn't reached).
+    fn grow_impl(
+        &mut self,
+    ) {
+        if let Some(growth) = self.growth {
+            if (self.range[1] < growth.limit) && (growth.increment != 0) {
+                // The number of values between the maximum bound and the limit.
+                let count_max = TOrd::count_range(&[self.range[1], growth.limit]) - 1;
+                let value = if count_max > growth.increment {
+                    TOrd::count_offset(self.range[1], growth.increment)
+                } else {
+                    growth.limit
+                };
+                self.grow_max_impl(value);
+            }
+        }
+    }
+
+    /// Set how the maximum bound grows when `take_any` finds no untaken values,
+    /// `None` (the default) to fail instead.
+    /// An increment of zero disables growth.
+    ///
+    /// Note that `clear` keeps the grown bounds.
+    pub fn set_growth(
+        &mut self,
+        growth: Option<Growth<TOrd>>,
+    ) {
+        self.growth = growth;
+    }
+
+    /// Set how spans are looked up, `Backend::RbTree` by default.
+    ///
+    /// `Backend::List` avoids the cost of balancing, useful for trees with few spans
+    /// (lookups are linear in the number of spans).
+    pub fn set_backend(
+        &mut self,
+        backend: Backend,
+    ) {
+        if self.backend == backend {
+            return;
+        }
+        self.backend = backend;
+        self.tree_clear();
+        if backend == Backend::RbTree {
+            let mut node = self.list.first;
+            while !node.is_null() {
+                self.nodes[node].left = NodeId::NULL;
+                self.nodes[node].right = NodeId::NULL;
+                self.tree_insert(node);
+                node = self.nodes[node].next;
+            }
+        }
+    }
+
+    /// Return how spans are looked up, see `set_backend`.
+    pub fn backend(
+        &self,
+    ) -> Backend {
+        self.backend
+    }
+
+    /// Take any value from the range tree.
+    ///
+    /// When the tree is full and growth is enabled (see `set_growth`),
+    /// the maximum bound is increased, returning `None` once the limit is reached.
+    pub fn take_any(
+        &mut self,
+    ) -> Option<TOrd> {
+        self.take_any_prepare();
+        if !self.list.first.is_null() {
+            let node = self.list.first;
+            let value = self.nodes[node].range[0];
+            self.journal_push(RangeOp::Take(value));
+            self.count_untaken -= 1;
+            if value == self.nodes[node].range[1] {
+                self.node_remove(node);
+            } else {
+                self.node_set_min(self.list.first, self.nodes[self.list.first].range[0].succ());
+            }
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Take up to `n` values (lowest first), returning them in ascending order.
+    ///
+    /// This is equivalent to calling `take_any` `n` times,
+    /// fewer values are returned when the tree is full (and can't grow).
+    pub fn take_n(
+        &mut self,
+        n: usize,
+    ) -> Vec<TOrd> {
+        self.release_queue_drain();
+        let capacity = ::core::cmp::min(n as u128, self.count_untaken) as usize;
+        let mut values = vec_util::with_capacity(capacity);
+        self.take_n_impl(n, |value| vec_util::push(&mut values, value));
+        values
+    }
+
+    /// Fill `values` with taken values (lowest first), see `take_n`.
+    ///
+    /// Returns the number of values written, less than `values.len()` when the tree is full.
+    pub fn take_n_into(
+        &mut self,
+        values: &mut [TOrd],
+    ) -> usize {
+        self.release_queue_drain();
+        let mut iter = values.iter_mut();
+        self.take_n_impl(iter.len(), |value| {
+            if let Some(slot) = iter.next() {
+                *slot = value;
+            }
+        })
+    }
+
+    /// Take up to `n` values (lowest first) as ranges, returning the number of values taken
+    /// and the ranges in ascending order (adjacent ranges are merged).
+    ///
+    /// Unlike `take_n` the values aren't listed individually, whole spans are taken at once,
+    /// so taking many values is fast.
+    /// Fewer values are taken when the tree is full (and can't grow).
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// r.take(3);
+    /// assert_eq!(r.take_upto(5), (5, vec![[0, 2], [4, 5]]));
+    /// assert_eq!(r.take_upto(100), (4, vec![[6, 9]]));
+    /// ```
+    pub fn take_upto(
+        &mut self,
+        n: u128,
+    ) -> (u128, Vec<[TOrd; 2]>) {
+        self.release_queue_drain();
+        let mut ranges: Vec<[TOrd; 2]> = vec![];
+        let count = self.take_ranges_impl(n, |range| match ranges.last_mut() {
+            // values added by growing follow the previous range.
+            Some(range_prev) if range_prev[1].precedes(range[0]) => range_prev[1] = range[1],
+            _ => vec_util::push(&mut ranges, range),
+        });
+        (count, ranges)
+    }
+
+    // Take up to 'n' values, passing each to 'f', whole nodes are taken at once.
+    fn take_n_impl<F>(
+        &mut self,
+        n: usize,
+        mut f: F,
+    ) -> usize where
+        F: FnMut(TOrd),
+    {
+        let count = self.take_ranges_impl(n as u128, |range| {
+            let mut value = range[0];
+            loop {
+                f(value);
+                if value == range[1] {
+                    break;
+                }
+                value = value.succ();
+            }
+        });
+        count as usize
+    }
+
+    // Take up to 'n' values (lowest first), passing each range taken to 'f'.
+    fn take_ranges_impl<F>(
+        &mut self,
+        n: u128,
+        mut f: F,
+    ) -> u128 where
+        F: FnMut([TOrd; 2]),
+    {
+        let mut remaining = n;
+        while remaining != 0 {
+            if self.list.first.is_null() {
+                self.grow_impl();
+                if self.list.first.is_null() {
+                    break;
+                }
+            }
+            let node = self.list.first;
+            let mut range = self.nodes[node].range;
+            let count = TOrd::count_range(&range);
+            if count > remaining {
+                range[1] = TOrd::count_offset(range[0], remaining - 1);
+            }
+            remaining -= ::core::cmp::min(count, remaining);
+            if range[0] == range[1] {
+                self.take_impl(range[0], node);
+            } else {
+                self.take_range_impl(range, node);
+            }
+            f(range);
+        }
+        n - remaining
+    }
+
+    /// Take any value as `take_any` does,
+    /// returning `RangeTreeError::Exhausted` when there are no untaken values.
+    pub fn try_take_any(
+        &mut self,
+    ) -> Result<TOrd, RangeTreeError> {
+        self.take_any().ok_or(RangeTreeError::Exhausted)
+    }
+
+    // Shared by `take_any` & `take_any_max`:
+    // release dropped handles & expired reservations, then grow when there are no untaken values.
+    fn take_any_prepare(
+        &mut self,
+    ) {
+        self.release_queue_drain();
+        // reading the clock isn't known not to panic.
+        #[cfg(all(feature = "std", not(feature = "no-panic")))]
+        {
+            if self.reservations.is_expiring() {
+                self.reap_reservations();
+            }
+        }
+        if self.list.first.is_null() {
+            self.grow_impl();
+        }
+    }
+
+    /// Take the highest untaken value from the range tree,
+    /// growing the maximum bound (see `set_growth`) when there are no untaken values.
+    pub fn take_any_max(
+        &mut self,
+    ) -> Option<TOrd> {
+        self.take_any_prepare();
+        let node = self.list.last;
+        if node.is_null() {
+            return None;
+        }
+        let value = self.nodes[node].range[1];
+        self.take_impl(value, node);
+        Some(value)
+    }
+
+    /// Take the lowest untaken value which is greater than or equal to `hint`,
+    /// wrapping around to the lowest untaken value when there is none.
+    pub fn take_any_from(
+        &mut self,
+        hint: TOrd,
+    ) -> Option<TOrd> {
+        self.release_queue_drain();
+        let node = self.find_node_at_or_after(&hint);
+        if node.is_null() {
+            return self.take_any();
+        }
+        let value = ::core::cmp::max(self.nodes[node].range[0], hint);
+        self.take_impl(value, node);
+        Some(value)
+    }
+
+    /// Take an untaken value chosen at random (all untaken values are equally likely),
+    /// returning `None` when the tree is full.
+    ///
+    /// The value is found using `kth_untaken`.
+    #[cfg(feature = "rand")]
+    pub fn take_random<R: rand_core::RngCore + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> Option<TOrd> {
+        self.release_queue_drain();
+        if self.list.first.is_null() {
+            return None;
+        }
+        let value = self.kth_untaken(random_below(rng, self.count_untaken))?;
+        let node = self.find_node_from_value(&value);
+        self.take_impl(value, node);
+        Some(value)
+    }
+
+    /// Take `n` consecutive values from the first span large enough to contain them,
+    /// returning the first value or `None` when there is no such span.
+    pub fn take_any_contiguous(
+        &mut self,
+        n: TOrd,
+    ) -> Option<TOrd> where
+        TOrd: Zero,
+    {
+        self.take_any_contiguous_fit(n, Fit::First)
+    }
+
+    /// Take `n` consecutive values from the start of a span chosen by `fit`,
+    /// returning the first value or `None` when no span is large enough.
+    ///
+    /// The lookup tree finds the span for `Fit::First` and `Fit::Worst` in O(log n).
+    /// For `Fit::Best` it skips subtrees without a large enough span,
+    /// so the cost depends on how many spans are large enough (O(n) when most are)
+    /// and an exact fit stops the search early.
+    /// All untaken spans are checked with `Backend::List`.
+    pub fn take_any_contiguous_fit(
+        &mut self,
+        n: TOrd,
+        fit: Fit,
+    ) -> Option<TOrd> where
+        TOrd: Zero,
+    {
+        self.release_queue_drain();
+        if n <= TOrd::zero() {
+            return None;
+        }
+        let n_count = TOrd::count_range(&[TOrd::zero(), n]) - 1;
+        let mut node_best: NodeId = NodeId::NULL;
+        let mut node_best_count: u128 = 0;
+        let mut node = self.list.first;
+        if self.backend == Backend::RbTree {
+            let count_max = rb::count_max(&self.nodes, self.root);
+            if count_max < n_count {
+                return None;
+            }
+            match fit {
+                Fit::First => {
+                    node_best = rb::get_first_with_count(&self.nodes, self.root, n_count);
+                    node = NodeId::NULL;
+                }
+                Fit::Worst => {
+                    node_best = rb::get_first_with_count(&self.nodes, self.root, count_max);
+                    node = NodeId::NULL;
+                }
+                Fit::Best => {
+                    node_best = rb::get_best_with_count(&self.nodes, self.root, n_count);
+                    node = NodeId::NULL;
+                }
+            }
+        }
+        while !node.is_null() {
+            let count = TOrd::count_range(&self.nodes[node].range);
+            if count >= n_count {
+                let is_better = node_best.is_null() || match fit {
+                    Fit::First => false,
+                    Fit::Best => count < node_best_count,
+                    Fit::Worst => count > node_best_count,
+                };
+                if is_better {
+                    node_best = node;
+                    node_best_count = count;
+                    if (fit == Fit::First) || (fit == Fit::Best && count == n_count) {
+                        break;
+                    }
+                }
+            }
+            node = self.nodes[node].next;
+        }
+        if node_best.is_null() {
+            return None;
+        }
+        let value = self.nodes[node_best].range[0];
+        self.take_range_impl([value, TOrd::count_offset(value, n_count - 1)], node_best);
+        Some(value)
+    }
+
+    /// Return the largest untaken span (the lowest when there are multiple),
+    /// `None` when the tree is full.
+    ///
+    /// This is O(log n) using the lookup tree (O(n) with `Backend::List`),
+    /// useful for checking if `take_any_contiguous` can succeed.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let r: RangeTree<u32> = RangeTree::from_ranges_untaken([0, 99], &[[0, 4], [10, 19], [50, 59]]).unwrap();
+    /// assert_eq!(r.max_contiguous_untaken(), Some([10, 19]));
+    /// ```
+    pub fn max_contiguous_untaken(
+        &self,
+    ) -> Option<[TOrd; 2]> {
+        let node = if self.backend == Backend::RbTree {
+            rb::get_first_with_count(&self.nodes, self.root, rb::count_max(&self.nodes, self.root))
+        } else {
+            let mut node_best = NodeId::NULL;
+            let mut node_best_count: u128 = 0;
+            let mut node = self.list.first;
+            while !node.is_null() {
+                let count = TOrd::count_range(&self.nodes[node].range);
+                if count > node_best_count {
+                    node_best = node;
+                    node_best_count = count;
+                }
+                node = self.nodes[node].next;
+            }
+            node_best
+        };
+        if node.is_null() {
+            return None;
+        }
+        Some(self.nodes[node].range)
+    }
+
+    /// Take `n` consecutive values starting at a multiple of `align`,
+    /// returning the first value or `None` when there is no such run (see `find_run`).
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// r.take(3);
+    /// assert_eq!(r.take_aligned(8, 8), Some(8));
+    /// assert_eq!(r.take_aligned(8, 8), Some(16));
+    /// assert_eq!(r.take_aligned(4, 4), Some(4));
+    /// ```
+    pub fn take_aligned(
+        &mut self,
+        n: TOrd,
+        align: TOrd,
+    ) -> Option<TOrd> where
+        TOrd: Zero,
+    {
+        self.release_queue_drain();
+        let (range, node) = self.find_run_impl(n, align)?;
+        self.take_range_impl(range, node);
+        Some(range[0])
+    }
+
+    /// Return the first value of the first untaken run of `n` values
+    /// starting at a multiple of `align`, without taking it.
+    ///
+    /// Returns `None` when there is no such run (or `n` or `align` are less than one).
+    pub fn find_run(
+        &self,
+        n: TOrd,
+        align: TOrd,
+    ) -> Option<TOrd> where
+        TOrd: Zero,
+    {
+        self.find_run_impl(n, align).map(|(range, _)| range[0])
+    }
+
+    fn find_run_impl(
+        &self,
+        n: TOrd,
+        align: TOrd,
+    ) -> Option<([TOrd; 2], NodeId)> where
+        TOrd: Zero,
+    {
+        if (n <= TOrd::zero()) || (align <= TOrd::zero()) {
+            return None;
+        }
+        // offsets are u128 so wide spans & negative values don't overflow.
+        let n_count = count_offset_between(TOrd::zero(), n);
+        let align = count_offset_between(TOrd::zero(), align);
+        // spans before the first one large enough (ignoring alignment) can be skipped.
+        let mut node = if self.backend == Backend::RbTree {
+            rb::get_first_with_count(&self.nodes, self.root, n_count)
+        } else {
+            self.list.first
+        };
+        while !node.is_null() {
+            let range = self.nodes[node].range;
+            // the offset to round up to the alignment.
+            let offset = if range[0] >= TOrd::zero() {
+                (align - count_offset_between(TOrd::zero(), range[0]) % align) % align
+            } else {
+                count_offset_between(range[0], TOrd::zero()) % align
+            };
+            let offset_max = count_offset_between(range[0], range[1]);
+            if offset <= offset_max && offset_max - offset >= n_count - 1 {
+                let value = TOrd::count_offset(range[0], offset);
+                return Some(([value, TOrd::count_offset(value, n_count - 1)], node));
+            }
+            node = self.nodes[node].next;
+        }
+        None
+    }
+
+    /// Check if there is an untaken run of `n` values starting at a multiple of `align`,
+    /// see `find_run`.
+    pub fn has_run(
+        &self,
+        n: TOrd,
+        align: TOrd,
+    ) -> bool where
+        TOrd: Zero,
+    {
+        self.find_run(n, align).is_some()
+    }
+
+    /// Check if the tree has this value (not taken).
+    ///
+    /// Values outside the tree bounds are never available, returning false.
+    pub fn has(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        if !self.in_bounds(&value) {
+            return false;
+        }
+        let node = self.find_node_from_value(&value);
+        !node.is_null()
+    }
+
+    /// Return the lowest untaken value which is greater than or equal to `value`,
+    /// without taking it.
+    pub fn first_untaken_at_or_after(
+        &self,
+        value: TOrd,
+    ) -> Option<TOrd> {
+        let node = self.find_node_at_or_after(&value);
+        if node.is_null() {
+            return None;
+        }
+        Some(::core::cmp::max(self.nodes[node].range[0], value))
+    }
+
+    /// Return the highest untaken value which is less than or equal to `value`,
+    /// without taking it.
+    pub fn last_untaken_at_or_before(
+        &self,
+        value: TOrd,
+    ) -> Option<TOrd> {
+        let node = self.find_node_at_or_before(&value);
+        if node.is_null() {
+            return None;
+        }
+        Some(::core::cmp::min(self.nodes[node].range[1], value))
+    }
+
+    /// Return the lowest taken value which is greater than or equal to `value`.
+    pub fn first_taken_at_or_after(
+        &self,
+        value: TOrd,
+    ) -> Option<TOrd> {
+        if value > self.range[1] {
+            return None;
+        }
+        let value = ::core::cmp::max(value, self.range[0]);
+        let node = self.find_node_from_value(&value);
+        if node.is_null() {
+            return Some(value);
+        }
+        // nodes are never adjacent, so the value after a node is taken (when in bounds).
+        self.nodes[node].range[1].succ_checked(self.range[1])
+    }
+
+    /// Return the untaken value at index `k` (counting from zero in ascending order),
+    /// `None` when there are `k` or fewer untaken values.
+    ///
+    /// This uses the number of values in each subtree of the lookup tree,
+    /// `Backend::List` checks each span in order.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let r: RangeTree<u32> = RangeTree::from_ranges_untaken([0, 99], &[[10, 12], [50, 59]]).unwrap();
+    /// assert_eq!(r.kth_untaken(0), Some(10));
+    /// assert_eq!(r.kth_untaken(3), Some(50));
+    /// assert_eq!(r.kth_untaken(13), None);
+    /// assert_eq!(r.rank_untaken(55), 8);
+    /// ```
+    pub fn kth_untaken(
+        &self,
+        k: u128,
+    ) -> Option<TOrd> {
+        if k >= self.count_untaken {
+            return None;
+        }
+        if self.backend == Backend::RbTree {
+            let (node, offset) = rb::get_by_offset(&self.nodes, self.root, k);
+            return Some(TOrd::count_offset(self.nodes[node].range[0], offset));
+        }
+        let mut offset = k;
+        let mut node = self.list.first;
+        while !node.is_null() {
+            let range = self.nodes[node].range;
+            let count = TOrd::count_range(&range);
+            if offset < count {
+                return Some(TOrd::count_offset(range[0], offset));
+            }
+            offset -= count;
+            node = self.nodes[node].next;
+        }
+        None
+    }
+
+    /// Return the number of untaken values less than `value`,
+    /// so `kth_untaken(rank_untaken(value))` is the first untaken value at or after `value`.
+    ///
+    /// This uses the lookup tree as `kth_untaken` does.
+    pub fn rank_untaken(
+        &self,
+        value: TOrd,
+    ) -> u128 {
+        if self.backend == Backend::RbTree {
+            return rb::rank(&self.nodes, self.root, &value);
+        }
+        let mut rank: u128 = 0;
+        let mut node = self.list.first;
+        while !node.is_null() {
+            let range = self.nodes[node].range;
+            if value < range[0] {
+                break;
+            }
+            if value <= range[1] {
+                return rank.saturating_add(count_offset_between(range[0], value));
+            }
+            rank = rank.saturating_add(TOrd::count_range(&range));
+            node = self.nodes[node].next;
+        }
+        rank
+    }
+
+    /// Return the taken value at index `k` (counting from zero in ascending order),
+    /// `None` when there are `k` or fewer taken values.
+    ///
+    /// This uses the lookup tree as `kth_untaken` does.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let r: RangeTree<u32> = RangeTree::from_ranges_taken([0, 99], &[[10, 12], [50, 59]]).unwrap();
+    /// assert_eq!(r.kth_taken(0), Some(10));
+    /// assert_eq!(r.kth_taken(3), Some(50));
+    /// assert_eq!(r.kth_taken(13), None);
+    /// assert_eq!(r.rank_taken(55), 8);
+    /// ```
+    pub fn kth_taken(
+        &self,
+        k: u128,
+    ) -> Option<TOrd> {
+        if k >= self.count_taken() {
+            return None;
+        }
+        // Taken values are in the gaps between spans,
+        // find the last span with at most 'k' taken values before it.
+        let mut node_prev = NodeId::NULL;
+        let mut node_prev_taken: u128 = 0;
+        // untaken values before 'node' (excluding its left subtree for the lookup tree).
+        let mut untaken: u128 = 0;
+        if self.backend == Backend::RbTree {
+            let mut node = self.root;
+            while !node.is_null() {
+                let node_ref = &self.nodes[node];
+                let untaken_before = untaken.saturating_add(rb::count(&self.nodes, node_ref.left));
+                let taken = count_offset_between(self.range[0], node_ref.range[0]).saturating_sub(untaken_before);
+                if k < taken {
+                    node = node_ref.left;
+                } else {
+                    node_prev = node;
+                    node_prev_taken = taken;
+                    untaken = untaken_before.saturating_add(TOrd::count_range(&node_ref.range));
+                    node = node_ref.right;
+                }
+            }
+        } else {
+            let mut node = self.list.first;
+            while !node.is_null() {
+                let node_ref = &self.nodes[node];
+                let taken = count_offset_between(self.range[0], node_ref.range[0]).saturating_sub(untaken);
+                if k < taken {
+                    break;
+                }
+                node_prev = node;
+                node_prev_taken = taken;
+                untaken = untaken.saturating_add(TOrd::count_range(&node_ref.range));
+                node = node_ref.next;
+            }
+        }
+        if node_prev.is_null() {
+            return Some(TOrd::count_offset(self.range[0], k));
+        }
+        Some(TOrd::count_offset(self.nodes[node_prev].range[1], (k - node_prev_taken) + 1))
+    }
+
+    /// Return the number of taken values less than `value`, see `rank_untaken`.
+    pub fn rank_taken(
+        &self,
+        value: TOrd,
+    ) -> u128 {
+        if value <= self.range[0] {
+            0
+        } else if value > self.range[1] {
+            self.count_taken()
+        } else {
+            count_offset_between(self.range[0], value) - self.rank_untaken(value)
+        }
+    }
+
+    /// Check if every value in the [minimum, maximum] range (inclusive) is untaken,
+    /// ranges which aren't entirely within the tree bounds return false.
+    pub fn all_untaken_in(
+        &self,
+        range: [TOrd; 2],
+    ) -> bool {
+        if range[0] > range[1] || !self.in_bounds(&range[0]) || !self.in_bounds(&range[1]) {
+            return false;
+        }
+        !self.find_node_from_range(&range).is_null()
+    }
+
+    /// Check if any value in the [minimum, maximum] range (inclusive) is untaken.
+    pub fn any_untaken_in(
+        &self,
+        range: [TOrd; 2],
+    ) -> bool {
+        if range[0] > range[1] {
+            return false;
+        }
+        !self.is_range_taken(&range)
+    }
+
+    /// Return the number of untaken values in the [minimum, maximum] range (inclusive).
+    ///
+    /// This is O(log n) using the lookup tree's counts (O(n) with `Backend::List`).
+    pub fn count_untaken_in(
+        &self,
+        range: [TOrd; 2],
+    ) -> u128 {
+        self.slice(range).map_or(0, |slice| slice.count_untaken())
+    }
+
+    /// Return the number of values in the tree bounds (taken or not).
+    ///
+    /// Counts are `u128`, so this is exact except for a tree over every `u128` or `i128` value,
+    /// which saturates at `u128::MAX` (see `Count`).
+    pub fn capacity(
+        &self,
+    ) -> u128 {
+        TOrd::count_range(&self.range)
+    }
+
+    /// Return a value which formats the tree using `options`,
+    /// limiting the output size of fragmented trees.
+    pub fn display(
+        &self,
+        options: DisplayOptions,
+    ) -> RangeTreeDisplay<'_, TOrd> {
+        RangeTreeDisplay::new(self, options)
+    }
+
+    /// Return a value which formats the tree as a bar of `width` characters followed by its spans
+    /// (as written by the alternate `{:#}` format), useful for seeing fragmentation in logs.
+    ///
+    /// Each character covers an equal part of the bounds:
+    /// `#` when all its values are taken, `.` when all are untaken and `+` when mixed.
+    /// The bar is narrower for trees with fewer than `width` values.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// for i in 0..25 {
+    ///     r.take(i);
+    /// }
+    /// r.take(60);
+    /// assert_eq!(
+    ///     r.ascii_map(10).to_string(),
+    ///     "##+...+...\n\
+    ///      bounds [0, 99], untaken [[25, 59], [61, 99]]\n\
+    ///      taken 26, untaken 74, spans 2 (26.0% occupied)",
+    /// );
+    /// ```
+    pub fn ascii_map(
+        &self,
+        width: usize,
+    ) -> AsciiMap<'_, TOrd> {
+        AsciiMap::new(self, width)
+    }
+
+    /// Write the internal red-black tree in Graphviz DOT format,
+    /// each node is labeled with its untaken span and filled with its color.
+    ///
+    /// Missing children are written as points, so left & right children can be told apart.
+    /// Only the graph is written for `Backend::List` (which has no tree).
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// r.take(5);
+    /// let mut dot = String::new();
+    /// r.dump_dot(&mut dot).unwrap();
+    /// assert!(dot.starts_with("digraph RangeTree {"));
+    /// assert!(dot.contains("label=\"[6, 9]\""));
+    /// ```
+    pub fn dump_dot<W: fmt::Write>(
+        &self,
+        w: &mut W,
+    ) -> fmt::Result where
+        TOrd: fmt::Display,
+    {
+        w.write_str("digraph RangeTree {\n")?;
+        w.write_str("    graph [ordering=out];\n")?;
+        w.write_str("    node [shape=box, style=filled, fontcolor=white];\n")?;
+        if !self.root.is_null() {
+            self.dump_dot_node(w, self.root, &mut 0)?;
+        }
+        w.write_str("}\n")
+    }
+
+    // Write 'node' & its children, returning the identifier written for 'node'.
+    fn dump_dot_node<W: fmt::Write>(
+        &self,
+        w: &mut W,
+        node: NodeId,
+        id_next: &mut usize,
+    ) -> Result<usize, fmt::Error> where
+        TOrd: fmt::Display,
+    {
+        let id = *id_next;
+        *id_next += 1;
+        if node.is_null() {
+            writeln!(w, "    n{} [shape=point, label=\"\"];", id)?;
+            return Ok(id);
+        }
+        let node_ref = &self.nodes[node];
+        writeln!(
+            w, "    n{} [label=\"[{}, {}]\", fillcolor={}];",
+            id, node_ref.range[0], node_ref.range[1],
+            if rb::is_red(&self.nodes, node) { "red" } else { "black" },
+        )?;
+        for &child in &[node_ref.left, node_ref.right] {
+            let id_child = self.dump_dot_node(w, child, id_next)?;
+            writeln!(w, "    n{} -> n{};", id, id_child)?;
+        }
+        Ok(id)
+    }
+
+    /// Return the number of taken values.
+    pub fn count_taken(
+        &self,
+    ) -> u128 {
+        self.capacity() - self.count_untaken
+    }
+
+    /// Return the number of untaken values.
+    pub fn count_untaken(
+        &self,
+    ) -> u128 {
+        self.count_untaken
+    }
+
+    /// Return the ratio of taken values to all values in the bounds, from 0.0 to 1.0.
+    ///
+    /// Counts are converted to `f64` before dividing, so this doesn't overflow for any bounds
+    /// (use `count_taken` and `capacity` for exact counts).
+    pub fn occupancy(
+        &self,
+    ) -> f64 {
+        self.count_taken() as f64 / self.capacity() as f64
+    }
+
+    /// Return the number of contiguous ranges which have not been taken.
+    pub fn count_ranges_untaken(
+        &self,
+    ) -> usize {
+        self.list.len
+    }
+
+    /// Return the number of untaken spans, maintained as values are taken & released.
+    ///
+    /// This is the same as `count_ranges_untaken`.
+    pub fn spans_untaken(
+        &self,
+    ) -> usize {
+        self.count_ranges_untaken()
+    }
+
+    /// Check if no values in the tree are taken.
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        if self.list.first.is_null() {
+            return false;  // NULL
+        }
+        (self.list.first == self.list.last) &&
+        (self.range[0] == self.nodes[self.list.first].range[0]) &&
+        (self.range[1] == self.nodes[self.list.first].range[1])
+    }
+
+    /// Check if all values in the tree are taken.
+    pub fn is_full(
+        &self,
+    ) -> bool {
+        self.list.first.is_null()
+    }
+
+    /// Release a value that has been taken.
+    ///
+    /// Note: releasing a value which isn't taken will panic.
+    /// use `try_release` in cases when its not known.
+    /// With the `no-panic` feature the value is ignored instead
+    /// (except for values pending from deferred releasing, which are ignored by `flush`).
+    ///
+    /// When deferred releasing is enabled (see `set_release_deferred`)
+    /// the value remains taken until the next `flush`.
+    pub fn release(
+        &mut self,
+        value: TOrd,
+    ) {
+        debug_assert!(self.in_bounds(&value));
+        self.release_queue_drain();
+        #[cfg(feature = "no-panic")]
+        {
+            if !self.in_bounds(&value) || !self.find_node_from_value(&value).is_null() {
+                return;
+            }
+        }
+        if let Some(ref mut pending) = self.release_pending {
+            vec_util::push(pending, value);
+            if pending.len() < self.release_pending_limit {
+                return;
+            }
+        } else {
+            self.release_impl(value);
+            return;
+        }
+        self.flush();
+    }
+
+    fn release_impl(
+        &mut self,
+        value: TOrd,
+    ) {
+        self.journal_push(RangeOp::Release(value));
+        self.count_untaken += 1;
+        #[cfg(feature = "diagnostics")]
+        self.labels.remove(&value);
+        self.release_range_nodes([value, value]);
+    }
+
+    // Release 'range' which must be taken.
+    fn release_range_impl(
+        &mut self,
+        range: [TOrd; 2],
+    ) {
+        self.journal_push(RangeOp::ReleaseRange(range));
+        self.count_untaken += TOrd::count_range(&range);
+        #[cfg(feature = "diagnostics")]
+        {
+            let values: Vec<TOrd> = self.labels.range(range[0]..=range[1]).map(|(k, _)| *k).collect();
+            for value in values {
+                self.labels.remove(&value);
+            }
+        }
+        self.release_range_nodes(range);
+    }
+
+    fn release_range_nodes(
+        &mut self,
+        range: [TOrd; 2],
+    ) {
+        let (
+            touch_prev,
+            touch_next,
+            node_prev,
+            node_next,
+        ) = {
+            if !self.list.first.is_null() {
+                let (
+                    node_prev,
+                    node_next,
+                ) = self.find_node_pair_around_value(&range[0]);
+                /* the range must have been already taken */
+                debug_assert!(!(node_prev.is_null() && node_next.is_null()));
+                debug_assert!(node_next.is_null() ||
+                              self.nodes[node_next].range[0] > range[1]);
+
+                /* Cases:
                  * 1) fill the gap between prev & next (two spans into one span).
-                 * 2) touching prev, (grow prev.max up one).
-                 * 3) touching next, (grow next.min down one).
+                 * 2) touching prev, (grow prev.max up).
+                 * 3) touching next, (grow next.min down).
                  * 4) touching neither, add a new segment. */
                 (
                     (!node_prev.is_null() &&
-                     unsafe { ((*node_prev).range[1] + TOrd::one()) == value }),
+                     self.nodes[node_prev].range[1].precedes(range[0])),
                     (!node_next.is_null() &&
-                     unsafe { ((*node_next).range[0] - TOrd::one()) == value }),
+                     range[1].precedes(self.nodes[node_next].range[0])),
                     node_prev,
                     node_next,
                 )
             } else {
                 // we could handle this case (4) inline,
                 // since its not a common case - use regular logic.
-                (false, false, ptr::null_mut(), ptr::null_mut())
+                (false, false, NodeId::NULL, NodeId::NULL)
+            }
+        };
+
+        if touch_prev && touch_next {
+            // case 1:
+            self.node_set_max(node_prev, self.nodes[node_next].range[1]);
+            self.node_remove(node_next);
+        } else if touch_prev {
+            // case 2:
+            debug_assert!(self.nodes[node_prev].range[1].precedes(range[0]));
+            self.node_set_max(node_prev, range[1]);
+        } else if touch_next {
+            // case 3:
+            debug_assert!(range[1].precedes(self.nodes[node_next].range[0]));
+            self.node_set_min(node_next, range[0]);
+        } else {
+            // case 4:
+            if !node_prev.is_null() {
+                self.node_add_after(node_prev, range);
+            } else if !node_next.is_null() {
+                self.node_add_before(node_next, range);
+            } else {
+                debug_assert!(self.list.first.is_null());
+                self.node_add_back(range);
+            }
+        }
+    }
+
+    /// Enable deferred releasing, where `release` adds values to a pending buffer
+    /// which is applied on `flush` or once it holds `limit` values.
+    /// `None` flushes and disables deferred releasing.
+    ///
+    /// This is faster for releasing many values at once,
+    /// however pending values are reported as taken by all queries until they're flushed.
+    pub fn set_release_deferred(
+        &mut self,
+        limit: Option<usize>,
+    ) {
+        match limit {
+            Some(limit) => {
+                if self.release_pending.is_none() {
+                    self.release_pending = Some(vec![]);
+                }
+                self.release_pending_limit = limit;
+            }
+            None => {
+                self.flush();
+                self.release_pending = None;
+            }
+        }
+    }
+
+    /// Apply values pending from deferred releasing (see `set_release_deferred`),
+    /// releasing runs of consecutive values at once.
+    pub fn flush(
+        &mut self,
+    ) {
+        self.release_queue_drain();
+        let mut pending = match self.release_pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        // Sorting can panic (on an inconsistent `Ord`),
+        // so with `no-panic` values are released one at a time.
+        #[cfg(feature = "no-panic")]
+        for &value in &pending {
+            if self.find_node_from_value(&value).is_null() {
+                self.release_impl(value);
             }
+        }
+        #[cfg(not(feature = "no-panic"))]
+        self.release_sorted(&mut pending);
+        // reuse the allocation.
+        pending.clear();
+        self.release_pending = Some(pending);
+    }
+
+    // Release values (sorting them in-place), coalescing runs into ranges.
+    #[cfg(not(feature = "no-panic"))]
+    fn release_sorted(
+        &mut self,
+        pending: &mut Vec<TOrd>,
+    ) {
+        pending.sort_unstable();
+        pending.dedup();
+        self.release_sorted_unique(pending);
+    }
+
+    // Release ascending values without duplicates, coalescing runs into ranges.
+    // Values which aren't taken (or are outside the bounds) are skipped.
+    #[cfg(not(feature = "no-panic"))]
+    fn release_sorted_unique(
+        &mut self,
+        values: &[TOrd],
+    ) {
+        let is_taken = |r: &RangeTree<TOrd>, value: &TOrd| {
+            r.in_bounds(value) && r.find_node_from_value(value).is_null()
         };
+        let mut i = 0;
+        while i < values.len() {
+            let mut range = [values[i], values[i]];
+            i += 1;
+            if !is_taken(self, &range[0]) {
+                continue;
+            }
+            while i < values.len() && range[1].precedes(values[i]) && is_taken(self, &values[i]) {
+                range[1] = values[i];
+                i += 1;
+            }
+            if range[0] == range[1] {
+                self.release_impl(range[0]);
+            } else {
+                self.release_range_impl(range);
+            }
+        }
+    }
+
+    /// Release many taken values at once, in any order.
+    ///
+    /// Values are sorted and runs of consecutive values are each released as a single range,
+    /// which is much faster than calling `release` for each value.
+    /// Deferred releasing (see `set_release_deferred`) doesn't apply, values are released immediately.
+    ///
+    /// Values which aren't taken (including duplicates) or are outside the bounds are ignored.
+    /// Values which are already sorted (without duplicates) aren't copied.
+    pub fn release_many(
+        &mut self,
+        values: &[TOrd],
+    ) {
+        self.release_queue_drain();
+        // Sorting can panic (on an inconsistent `Ord`),
+        // so with `no-panic` values are released one at a time.
+        #[cfg(feature = "no-panic")]
+        for &value in values {
+            if self.in_bounds(&value) && self.find_node_from_value(&value).is_null() {
+                self.release_impl(value);
+            }
+        }
+        #[cfg(not(feature = "no-panic"))]
+        {
+            if values.windows(2).all(|pair| pair[0] < pair[1]) {
+                self.release_sorted_unique(values);
+            } else {
+                self.release_sorted(&mut values.to_vec());
+            }
+        }
+    }
+
+    /// Release values which are untaken in `other`,
+    /// so the untaken values are the union of both trees.
+    ///
+    /// Values pending from deferred releasing are flushed first
+    /// (those pending in `other` count as taken).
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the trees have different bounds.
+    pub fn union_with(
+        &mut self,
+        other: &RangeTree<TOrd>,
+    ) -> Result<(), RangeTreeError> {
+        if self.range != other.range {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.flush();
+        for range in ranges_intersect(self.iter_taken(), other.iter_untaken()) {
+            if range[0] == range[1] {
+                self.release_impl(range[0]);
+            } else {
+                self.release_range_impl(range);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take values which are taken in `other`,
+    /// so the untaken values are the intersection of both trees.
+    ///
+    /// Values pending from deferred releasing are flushed first
+    /// (those pending in `other` count as taken).
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the trees have different bounds.
+    pub fn intersect_with(
+        &mut self,
+        other: &RangeTree<TOrd>,
+    ) -> Result<(), RangeTreeError> {
+        if self.range != other.range {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.flush();
+        let ranges = ranges_intersect(self.iter_untaken(), other.iter_taken());
+        self.take_ranges_untaken(&ranges);
+        Ok(())
+    }
+
+    /// Take values which are untaken in `other`,
+    /// so the untaken values are the difference of this tree and `other`.
+    ///
+    /// Values pending from deferred releasing are flushed first
+    /// (those pending in `other` count as taken).
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the trees have different bounds.
+    pub fn subtract(
+        &mut self,
+        other: &RangeTree<TOrd>,
+    ) -> Result<(), RangeTreeError> {
+        if self.range != other.range {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.flush();
+        let ranges = ranges_intersect(self.iter_untaken(), other.iter_untaken());
+        self.take_ranges_untaken(&ranges);
+        Ok(())
+    }
+
+    /// Compare with an earlier state of the tree,
+    /// returning the ranges `(taken_since, released_since)`:
+    /// taken in this tree but untaken in `other` and untaken in this tree but taken in `other`.
+    ///
+    /// Applying the result to `other` (taking & releasing the ranges) makes it equal to this tree,
+    /// useful for sending changes to a replica.
+    /// Values pending from deferred releasing count as taken.
+    ///
+    /// Note: both trees must have the same bounds.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// r.take(2);
+    /// let before = RangeTree::from_ranges_taken([0, 9], &r.ranges_taken_as_vec()).unwrap();
+    /// r.release(2);
+    /// r.take(5);
+    /// r.take(6);
+    /// assert_eq!(r.diff(&before), (vec![[5, 6]], vec![[2, 2]]));
+    /// ```
+    pub fn diff(
+        &self,
+        other: &RangeTree<TOrd>,
+    ) -> (Vec<[TOrd; 2]>, Vec<[TOrd; 2]>) {
+        debug_assert!(self.range == other.range);
+        (
+            ranges_intersect(self.iter_taken(), other.iter_untaken()),
+            ranges_intersect(self.iter_untaken(), other.iter_taken()),
+        )
+    }
 
-        unsafe {
-            if touch_prev && touch_next {
-                // case 1:
-                (*node_prev).range[1] = (*node_next).range[1];
-                self.node_remove(node_next);
-            } else if touch_prev {
-                // case 2:
-                debug_assert!(((*node_prev).range[1] + TOrd::one()) == value);
-                (*node_prev).range[1] = value;
-            } else if touch_next {
-                // case 3:
-                debug_assert!(((*node_next).range[0] - TOrd::one()) == value);
-                (*node_next).range[0] = value;
+    // Take sorted ranges which must be untaken.
+    fn take_ranges_untaken(
+        &mut self,
+        ranges: &[[TOrd; 2]],
+    ) {
+        for &range in ranges {
+            let node = self.find_node_from_value(&range[0]);
+            debug_assert!(!node.is_null());
+            if range[0] == range[1] {
+                self.take_impl(range[0], node);
             } else {
-                // case 4:
-                let range_new = [value, value];
-                if !node_prev.is_null() {
-                    self.node_add_after(node_prev, range_new);
-                } else if !node_next.is_null() {
-                    self.node_add_before(node_next, range_new);
+                self.take_range_impl(range, node);
+            }
+        }
+    }
+
+    /// Take every untaken value in the [minimum, maximum] range (inclusive),
+    /// returning the ranges which were taken (in ascending order).
+    ///
+    /// Values outside the tree bounds are ignored.
+    /// Values pending from deferred releasing are flushed first (so they're taken too).
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// r.take(15);
+    /// assert_eq!(r.take_all_in([10, 19]), [[10, 14], [16, 19]]);
+    /// assert_eq!(r.ranges_untaken_as_vec(), [[0, 9], [20, 99]]);
+    /// ```
+    pub fn take_all_in(
+        &mut self,
+        range: [TOrd; 2],
+    ) -> Vec<[TOrd; 2]> {
+        if range[0] > range[1] {
+            return vec![];
+        }
+        self.flush();
+        let ranges: Vec<[TOrd; 2]> = self.iter_untaken_in(range).collect();
+        self.take_ranges_untaken(&ranges);
+        ranges
+    }
+
+    /// Take every value in the [minimum, maximum] range (inclusive) when they're all untaken.
+    ///
+    /// Otherwise the tree isn't modified and the error reports the ranges which conflict
+    /// and the nearest untaken range of the same length, which can be taken instead.
+    /// Values pending from deferred releasing are flushed first (so they can be taken).
+    ///
+    /// A range with its minimum greater than its maximum is an error
+    /// with no conflicts and no suggestion.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    /// r.take(15);
+    /// let err = r.take_exact_range_or_suggest([10, 19]).unwrap_err();
+    /// assert_eq!(err.conflicts, [[15, 15]]);
+    /// assert_eq!(err.suggestion, Some([5, 14]));
+    /// assert_eq!(r.take_exact_range_or_suggest([5, 14]), Ok(()));
+    /// ```
+    pub fn take_exact_range_or_suggest(
+        &mut self,
+        range: [TOrd; 2],
+    ) -> Result<(), RangeConflict<TOrd>> {
+        if range[0] > range[1] {
+            return Err(RangeConflict {
+                conflicts: vec![],
+                suggestion: None,
+            });
+        }
+        self.flush();
+        if !self.all_untaken_in(range) {
+            return Err(RangeConflict {
+                conflicts: self.ranges_not_untaken_in(range),
+                suggestion: self.find_untaken_nearest(range),
+            });
+        }
+        let node = self.find_node_from_range(&range);
+        if range[0] == range[1] {
+            self.take_impl(range[0], node);
+        } else {
+            self.take_range_impl(range, node);
+        }
+        Ok(())
+    }
+
+    // Return the ranges between untaken ranges in 'range' (taken or outside the tree bounds).
+    fn ranges_not_untaken_in(
+        &self,
+        range: [TOrd; 2],
+    ) -> Vec<[TOrd; 2]> {
+        let mut ranges: Vec<[TOrd; 2]> = vec![];
+        // The first value after the previous untaken range, `None` past the maximum.
+        let mut value_next = Some(range[0]);
+        for range_untaken in self.iter_untaken_in(range) {
+            if let Some(value) = value_next {
+                if let Some(value_max) = range_untaken[0].pred_checked(value) {
+                    ranges.push([value, value_max]);
+                }
+            }
+            value_next = range_untaken[1].succ_checked(range[1]);
+        }
+        if let Some(value) = value_next {
+            ranges.push([value, range[1]]);
+        }
+        ranges
+    }
+
+    // Return the untaken range with as many values as 'range',
+    // which starts nearest the start of 'range' (the lowest when there are multiple).
+    fn find_untaken_nearest(
+        &self,
+        range: [TOrd; 2],
+    ) -> Option<[TOrd; 2]> {
+        let n_step = count_offset_between(range[0], range[1]);
+        let mut range_best: Option<[TOrd; 2]> = None;
+        let mut distance_best: u128 = 0;
+        let mut node = self.list.first;
+        while !node.is_null() {
+            let node_range = self.nodes[node].range;
+            if node_range[0] > range[0] && range_best.is_some() &&
+                count_offset_between(range[0], node_range[0]) >= distance_best
+            {
+                // spans after this are further away.
+                break;
+            }
+            let node_step = count_offset_between(node_range[0], node_range[1]);
+            if node_step >= n_step {
+                let (value, distance) = if node_range[0] >= range[0] {
+                    (node_range[0], count_offset_between(range[0], node_range[0]))
                 } else {
-                    debug_assert!(self.list.first.is_null());
-                    self.node_add_back(range_new);
+                    // the last start in the span which fits.
+                    let value_last = TOrd::count_offset(node_range[0], node_step - n_step);
+                    if value_last >= range[0] {
+                        (range[0], 0)
+                    } else {
+                        (value_last, count_offset_between(value_last, range[0]))
+                    }
+                };
+                if range_best.is_none() || distance < distance_best {
+                    range_best = Some([value, TOrd::count_offset(value, n_step)]);
+                    distance_best = distance;
+                }
+            }
+            node = self.nodes[node].next;
+        }
+        range_best
+    }
+
+    /// Release every taken value in the [minimum, maximum] range (inclusive),
+    /// returning the ranges which were released (in ascending order).
+    ///
+    /// Values outside the tree bounds are ignored.
+    /// Values pending from deferred releasing are flushed first.
+    ///
+    /// ```
+    /// use rangetree::RangeTree;
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 99], true);
+    /// r.release(15);
+    /// assert_eq!(r.release_all_in([10, 19]), [[10, 14], [16, 19]]);
+    /// assert_eq!(r.ranges_untaken_as_vec(), [[10, 19]]);
+    /// ```
+    pub fn release_all_in(
+        &mut self,
+        range: [TOrd; 2],
+    ) -> Vec<[TOrd; 2]> {
+        if range[0] > range[1] {
+            return vec![];
+        }
+        let range = match span::clamp(&range, &self.range) {
+            Some(range) => range,
+            None => return vec![],
+        };
+        self.flush();
+        let ranges = self.ranges_not_untaken_in(range);
+        for &range_taken in &ranges {
+            if range_taken[0] == range_taken[1] {
+                self.release_impl(range_taken[0]);
+            } else {
+                self.release_range_impl(range_taken);
+            }
+        }
+        ranges
+    }
+
+    /// Release taken values for which `f` returns false, visiting values in ascending order.
+    ///
+    /// Values pending from deferred releasing are flushed first.
+    pub fn retain_taken<F>(
+        &mut self,
+        mut f: F,
+    ) where
+        F: FnMut(TOrd) -> bool,
+    {
+        self.flush();
+        let mut ranges: Vec<[TOrd; 2]> = vec![];
+        for range in self.iter_taken() {
+            let mut value = range[0];
+            loop {
+                if !f(value) {
+                    match ranges.last_mut() {
+                        Some(range_prev) if range_prev[1].precedes(value) => range_prev[1] = value,
+                        _ => vec_util::push(&mut ranges, [value, value]),
+                    }
                 }
+                if value == range[1] {
+                    break;
+                }
+                value = value.succ();
+            }
+        }
+        for range in ranges {
+            if range[0] == range[1] {
+                self.release_impl(range[0]);
+            } else {
+                self.release_range_impl(range);
+            }
+        }
+    }
+
+    /// Release the lowest taken value, returning it or `None` when no values are taken.
+    ///
+    /// Values pending from deferred releasing are flushed first,
+    /// the returned value is released immediately (it's never deferred).
+    pub fn release_any(
+        &mut self,
+    ) -> Option<TOrd> {
+        self.flush();
+        let value = self.iter_taken().next()?[0];
+        self.release_impl(value);
+        Some(value)
+    }
+
+    /// Release a value, returning an error (instead of panicking) when it isn't taken.
+    ///
+    /// Values pending from deferred releasing have already been released,
+    /// so they return `RangeTreeError::NotTaken` (checking them is linear in the number pending).
+    pub fn try_release(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        self.release_queue_drain();
+        if !self.in_bounds(&value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        if !self.find_node_from_value(&value).is_null() ||
+            self.release_pending.as_ref().is_some_and(|pending| pending.contains(&value))
+        {
+            return Err(RangeTreeError::NotTaken);
+        }
+        #[cfg(feature = "std")]
+        {
+            if self.reservations.contains(value) {
+                return Err(RangeTreeError::Reserved);
             }
         }
+        self.release(value);
+        Ok(())
     }
 
     /// Return a vector containing [minimum, maximum] pairs (inclusive)
@@ -1101,87 +4638,775 @@ impl<TOrd: RType> RangeTree<TOrd> {
     pub fn ranges_taken_as_vec(
         &self,
     ) -> Vec<[TOrd; 2]> {
-        let mut ret: Vec<[TOrd; 2]> = vec![];
-        if self.is_empty() {
-            // pass
-        } else if self.list.first.is_null() {
-            ret.push(self.range);
-        } else {
-            unsafe {
-                if (*self.list.first).range[0] != self.range[0] {
-                    ret.push([
-                        self.range[0],
-                        (*self.list.first).range[0] - TOrd::one(),
-                    ]);
-                }
-            }
+        self.iter_taken().collect()
+    }
 
-            unsafe {
-                let mut node_prev = self.list.first;
-                let mut node_next = (*node_prev).next;
-                while !node_next.is_null() {
-                    ret.push([
-                        (*node_prev).range[1] + TOrd::one(),
-                        (*node_next).range[0] - TOrd::one(),
-                    ]);
-                    node_prev = node_next;
-                    node_next = (*node_next).next;
-                }
+    /// Return a cursor at the first untaken range, see `cursor_next`.
+    pub fn cursor_untaken(
+        &self,
+    ) -> Cursor<TOrd> {
+        Cursor {
+            node: self.list.first,
+            seq: self.seq,
+            value_last: None,
+        }
+    }
+
+    /// Return the next untaken [minimum, maximum] range (inclusive),
+    /// or an error when the tree has been modified since the cursor was last synchronized.
+    pub fn cursor_next(
+        &self,
+        cursor: &mut Cursor<TOrd>,
+    ) -> Result<Option<[TOrd; 2]>, RangeTreeError> {
+        if cursor.seq != self.seq {
+            return Err(RangeTreeError::StaleCursor);
+        }
+        if cursor.node.is_null() {
+            return Ok(None);
+        }
+        let node = &self.nodes[cursor.node];
+        let mut range = node.range;
+        if let Some(value_last) = cursor.value_last {
+            // the range has been extended since it was returned (see `cursor_resync`).
+            if range[0] <= value_last {
+                range[0] = value_last.succ();
             }
+        }
+        cursor.node = node.next;
+        cursor.value_last = Some(range[1]);
+        Ok(Some(range))
+    }
 
-            unsafe {
-                if (*self.list.last).range[1] != self.range[1] {
-                    ret.push([
-                        (*self.list.last).range[1] + TOrd::one(),
-                        self.range[1],
-                    ]);
+    /// Update a stale cursor after the tree has been modified,
+    /// continuing with untaken values after the last range it returned.
+    pub fn cursor_resync(
+        &self,
+        cursor: &mut Cursor<TOrd>,
+    ) {
+        cursor.seq = self.seq;
+        cursor.node = match cursor.value_last {
+            None => self.list.first,
+            Some(value_last) => {
+                let node = self.find_node_at_or_after(&value_last);
+                if !node.is_null() && self.nodes[node].range[1] == value_last {
+                    self.nodes[node].next
+                } else {
+                    node
                 }
             }
+        };
+    }
+
+    /// Return an iterator over untaken ranges as `iter_untaken` does,
+    /// merging ranges separated by a taken gap when `gap_fn` returns true.
+    ///
+    /// * `gap_fn` called with the [minimum, maximum] pair (inclusive) of each taken gap.
+    pub fn iter_untaken_coalesced<F>(
+        &self,
+        gap_fn: F,
+    ) -> IterUntakenCoalesced<'_, TOrd, F> where
+        F: FnMut([TOrd; 2]) -> bool,
+    {
+        IterUntakenCoalesced {
+            iter: self.iter_untaken(),
+            range_next: None,
+            gap_fn,
         }
+    }
 
-        ret
+    /// Return an iterator over untaken spans, merging spans separated by a taken gap
+    /// when `gap_fn` returns true.
+    ///
+    /// This is the same as `iter_untaken_coalesced`.
+    pub fn iter_untaken_spans_coalesced<F>(
+        &self,
+        gap_fn: F,
+    ) -> IterUntakenCoalesced<'_, TOrd, F> where
+        F: FnMut([TOrd; 2]) -> bool,
+    {
+        self.iter_untaken_coalesced(gap_fn)
     }
 
+    /// Return an iterator over [minimum, maximum] pairs (inclusive)
+    /// of contiguous ranges which have been taken.
+    pub fn iter_taken(
+        &self,
+    ) -> IterTaken<'_, TOrd> {
+        IterTaken {
+            value: Some(self.range[0]),
+            value_max: self.range[1],
+            node: self.list.first,
+            tree: self,
+        }
+    }
 
     /// Return a vector containing [minimum, maximum] pairs (inclusive)
     /// of contiguous ranges which have not been taken.
     pub fn ranges_untaken_as_vec(
         &self,
     ) -> Vec<[TOrd; 2]> {
-        let mut ret: Vec<[TOrd; 2]> = vec![];
-        if self.is_empty() {
-            ret.push(self.range);
-        } else if self.list.first.is_null() {
-            // pass
+        self.iter_untaken().collect()
+    }
+
+    /// Return an iterator over [minimum, maximum] pairs (inclusive)
+    /// of contiguous ranges which have not been taken.
+    pub fn iter_untaken(
+        &self,
+    ) -> IterUntaken<'_, TOrd> {
+        IterUntaken {
+            nodes: NodeSpan::new(&self.nodes, self.list.first, self.list.last),
+            tree: self,
+        }
+    }
+
+    // Untaken ranges overlapping 'range', clipped to it (shared by slices & sub-allocators).
+    pub(crate) fn iter_untaken_in(
+        &self,
+        range: [TOrd; 2],
+    ) -> impl DoubleEndedIterator<Item=[TOrd; 2]> + '_ {
+        debug_assert!(range[0] <= range[1]);
+        IterUntaken {
+            nodes: NodeSpan::new(
+                &self.nodes,
+                self.find_node_at_or_after(&range[0]),
+                self.find_node_at_or_before(&range[1]),
+            ),
+            tree: self,
+        }.filter_map(move |r| span::clamp(&r, &range))
+    }
+
+    /// Consume the tree, returning an iterator over untaken [minimum, maximum] ranges (inclusive).
+    ///
+    /// Values pending from deferred releasing are flushed first.
+    pub fn into_ranges_untaken(
+        mut self,
+    ) -> IntoIterUntaken<TOrd> {
+        self.flush();
+        IntoIterUntaken {
+            nodes: NodeSpan::new(&self.nodes, self.list.first, self.list.last),
+            tree: self,
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Diagnostics (feature)
+
+    /// Take a value as `take` does, recording `label` for `leak_report`.
+    #[cfg(feature = "diagnostics")]
+    pub fn take_labeled(
+        &mut self,
+        value: TOrd,
+        label: &'static str,
+    ) {
+        self.take(value);
+        self.labels.insert(value, label);
+    }
+
+    /// Take any value as `take_any` does, recording `label` for `leak_report`.
+    #[cfg(feature = "diagnostics")]
+    pub fn take_any_labeled(
+        &mut self,
+        label: &'static str,
+    ) -> Option<TOrd> {
+        let value = self.take_any();
+        if let Some(value) = value {
+            self.labels.insert(value, label);
+        }
+        value
+    }
+
+    /// Return the number of labeled values which are still taken, for each label
+    /// (sorted by label).
+    #[cfg(feature = "diagnostics")]
+    pub fn leak_report(
+        &self,
+    ) -> Vec<(&'static str, usize)> {
+        let mut counts: ::alloc::collections::BTreeMap<&'static str, usize> =
+            ::alloc::collections::BTreeMap::new();
+        for label in self.labels.values() {
+            *counts.entry(label).or_insert(0) += 1;
+        }
+        counts.into_iter().collect()
+    }
+
+    // ------------------------------------------------------------------------
+    // Statistics
+
+    /// Record the untaken count for the last `limit` modifications,
+    /// used by `estimated_time_to_exhaustion`. `None` disables recording.
+    pub fn set_rate_history(
+        &mut self,
+        limit: Option<usize>,
+    ) {
+        match limit {
+            Some(limit) if limit != 0 => {
+                let mut history = self.rate_history.take().unwrap_or_default();
+                while history.len() > limit {
+                    history.pop_front();
+                }
+                // Recording must not allocate, see `journal_push`.
+                history.reserve_exact(limit - history.len());
+                self.rate_history = Some(history);
+                self.rate_history_limit = limit;
+            }
+            _ => {
+                self.rate_history = None;
+            }
+        }
+    }
+
+    /// Return statistics of the tree (counts, span lengths and settings).
+    ///
+    /// This visits every untaken span.
+    pub fn stats(
+        &self,
+    ) -> RangeTreeStats<TOrd> {
+        RangeTreeStats::new(self)
+    }
+
+    /// Reserve memory for at least `additional` more nodes (untaken ranges),
+    /// so fragmenting the tree doesn't allocate until they're used.
+    ///
+    /// Taking a value from within an untaken range adds at most one node.
+    pub fn reserve(
+        &mut self,
+        additional: usize,
+    ) {
+        self.nodes.reserve(additional);
+    }
+
+    /// Reserve memory as `reserve` does,
+    /// returning `RangeTreeError::OutOfMemory` when it can't be allocated (the tree is unchanged).
+    ///
+    /// This never aborts (with the `no-panic` feature), so it can be called ahead of
+    /// taking values where allocation failure must be handled.
+    pub fn try_reserve(
+        &mut self,
+        additional: usize,
+    ) -> Result<(), RangeTreeError> {
+        if self.nodes.try_reserve(additional) {
+            Ok(())
         } else {
-            unsafe {
-                let mut node = self.list.first;
-                while !node.is_null() {
-                    ret.push([
-                        (*node).range[0],
-                        (*node).range[1],
-                    ]);
-                    node = (*node).next;
+            Err(RangeTreeError::OutOfMemory)
+        }
+    }
+
+    /// Release memory of the node pool which is only used by free nodes.
+    ///
+    /// Nodes in use aren't moved, only free nodes after the last node in use are removed,
+    /// so a fragmented tree may not shrink until more spans are joined.
+    pub fn shrink_to_fit(
+        &mut self,
+    ) {
+        self.nodes.shrink();
+    }
+
+    /// Return the memory used by the tree's nodes.
+    pub fn memory_usage(
+        &self,
+    ) -> MemoryUsage {
+        MemoryUsage {
+            capacity: self.nodes.capacity(),
+            nodes: self.nodes.elems_len(),
+            nodes_free: self.nodes.free_len(),
+            bytes:
+                ::core::mem::size_of::<RangeTree<TOrd>>() +
+                self.nodes.bytes() +
+                self.release_pending.as_ref().map_or(0, |values| {
+                    values.capacity() * ::core::mem::size_of::<TOrd>()
+                }),
+        }
+    }
+
+    /// Return `stats` as a single line JSON object, see `RangeTreeStats::to_json`.
+    pub fn stats_json(
+        &self,
+    ) -> String where
+        TOrd: ::core::fmt::Display,
+    {
+        self.stats().to_json()
+    }
+
+    /// Estimate the number of modifications until the tree is full,
+    /// from the net number of values taken over the last `window` modifications
+    /// (limited to the recorded history, see `set_rate_history`).
+    ///
+    /// Returns `None` when there is no history or values aren't being used up.
+    pub fn estimated_time_to_exhaustion(
+        &self,
+        window: usize,
+    ) -> Option<u128> {
+        let history = self.rate_history.as_ref()?;
+        let window = ::core::cmp::min(window, history.len());
+        if window == 0 {
+            return None;
+        }
+        let count_prev = history[history.len() - window];
+        if count_prev <= self.count_untaken {
+            return None;
+        }
+        let taken = count_prev - self.count_untaken;
+        // round up, any remaining values need another modification.
+        Some(self.count_untaken.saturating_mul(window as u128).div_ceil(taken))
+    }
+
+    // ------------------------------------------------------------------------
+    // Journal
+
+    fn journal_push(
+        &mut self,
+        op: RangeOp<TOrd>,
+    ) {
+        self.seq += 1;
+        let count_taken = self.count_taken();
+        if count_taken > self.count_taken_max {
+            self.count_taken_max = count_taken;
+        }
+        if let Some(ref mut journal) = self.journal {
+            vec_util::push(journal, op);
+        }
+        if let Some(ref mut history) = self.history {
+            history.redo.clear();
+            match op {
+                RangeOp::Clear(_) |
+                RangeOp::GrowMax(_) |
+                RangeOp::GrowMin(_) |
+                RangeOp::ShrinkMax(_) => {
+                    // the previous state isn't recorded, so earlier operations can't be undone.
+                    history.undo.clear();
                 }
+                _ => vec_util::push(&mut history.undo, op),
+            }
+        }
+        if let Some(ref mut history) = self.rate_history {
+            if history.len() == self.rate_history_limit {
+                history.pop_front();
+            }
+            vec_util::push_back_reserved(history, self.count_untaken);
+        }
+    }
+
+    /// Return the sequence number, incremented on every modification.
+    pub fn seq(
+        &self,
+    ) -> u64 {
+        self.seq
+    }
+
+    /// Enable or disable recording of modifications.
+    ///
+    /// Enabling starts recording from the current sequence number,
+    /// disabling discards the recorded operations.
+    pub fn set_journal(
+        &mut self,
+        enable: bool,
+    ) {
+        if enable {
+            if self.journal.is_none() {
+                self.journal = Some(vec![]);
+                self.journal_seq = self.seq;
             }
+        } else {
+            self.journal = None;
         }
+    }
 
-        ret
+    /// Discard recorded operations before `seq`,
+    /// once all consumers of `delta_since` have caught up.
+    pub fn journal_discard_before(
+        &mut self,
+        seq: u64,
+    ) {
+        if let Some(ref mut journal) = self.journal {
+            if seq > self.journal_seq {
+                let len = ::core::cmp::min((seq - self.journal_seq) as usize, journal.len());
+                journal.drain(..len);
+                self.journal_seq += len as u64;
+            }
+        }
     }
 
-    #[allow(dead_code)]
-    fn print(
+    /// Return the operations since `seq` and the sequence number to pass on the next call.
+    ///
+    /// Returns `None` when the journal is disabled
+    /// or no longer holds the operations since `seq`,
+    /// in this case the full state needs to be copied.
+    pub fn delta_since(
         &self,
+        seq: u64,
+    ) -> Option<(Vec<RangeOp<TOrd>>, u64)> {
+        if let Some(ref journal) = self.journal {
+            if (seq >= self.journal_seq) && (seq <= self.seq) {
+                let ops = journal[(seq - self.journal_seq) as usize..].to_vec();
+                return Some((ops, self.seq));
+            }
+        }
+        None
+    }
+
+    /// Return the recorded operations, removing them from the journal,
+    /// for a replica to apply with `apply_delta`.
+    ///
+    /// Returns an empty vector when the journal is disabled.
+    pub fn drain_journal(
+        &mut self,
+    ) -> Vec<RangeOp<TOrd>> {
+        match self.journal {
+            Some(ref mut journal) => {
+                self.journal_seq = self.seq;
+                ::core::mem::take(journal)
+            }
+            None => vec![],
+        }
+    }
+
+    /// Apply operations returned by `delta_since` (from a tree with the same initial state).
+    pub fn apply_delta(
+        &mut self,
+        ops: &[RangeOp<TOrd>],
+    ) {
+        self.release_queue_drain();
+        for op in ops {
+            self.apply_op(*op);
+        }
+    }
+
+    fn apply_op(
+        &mut self,
+        op: RangeOp<TOrd>,
+    ) {
+        match op {
+            RangeOp::Take(value) => self.take(value),
+            RangeOp::Release(value) => self.release_impl(value),
+            RangeOp::ReleaseRange(range) => self.release_range_impl(range),
+            RangeOp::TakeRange(range) => {
+                let node = self.find_node_from_value(&range[0]);
+                debug_assert!(!node.is_null());
+                self.take_range_impl(range, node);
+            }
+            RangeOp::Clear(full) => self.clear(full),
+            RangeOp::GrowMax(value) => self.grow_max_impl(value),
+            RangeOp::GrowMin(value) => self.grow_min_impl(value),
+            RangeOp::ShrinkMax(value) => self.shrink_max_impl(value),
+            RangeOp::Invert => self.invert(),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Undo
+
+    /// Enable or disable recording of modifications for `undo` and `redo`,
+    /// disabling discards the recorded operations.
+    ///
+    /// Clearing and changing the bounds can't be undone,
+    /// these discard the operations recorded before them.
+    pub fn set_undo(
+        &mut self,
+        enable: bool,
+    ) {
+        if enable {
+            if self.history.is_none() {
+                self.history = Some(History {
+                    undo: vec![],
+                    redo: vec![],
+                });
+            }
+        } else {
+            self.history = None;
+        }
+    }
+
+    /// Undo the last `n` modifications, returning the number undone
+    /// (less than `n` when there are no more to undo).
+    ///
+    /// Undoing is a modification too (it's journaled & increments `seq`),
+    /// so replicas are kept in sync with `delta_since` or `drain_journal`.
+    /// Values pending from deferred releasing are flushed first.
+    pub fn undo(
+        &mut self,
+        n: usize,
+    ) -> usize {
+        self.flush();
+        // taken while undoing, so the reversed operations aren't recorded.
+        let mut history = match self.history.take() {
+            Some(history) => history,
+            None => return 0,
+        };
+        let mut count = 0;
+        while count < n {
+            let op = match history.undo.pop() {
+                Some(op) => op,
+                None => break,
+            };
+            match op {
+                RangeOp::Take(value) => self.release_impl(value),
+                RangeOp::Release(value) => self.apply_op(RangeOp::Take(value)),
+                RangeOp::TakeRange(range) => self.release_range_impl(range),
+                RangeOp::ReleaseRange(range) => self.apply_op(RangeOp::TakeRange(range)),
+                RangeOp::Invert => self.invert(),
+                RangeOp::Clear(_) |
+                RangeOp::GrowMax(_) |
+                RangeOp::GrowMin(_) |
+                RangeOp::ShrinkMax(_) => {
+                    debug_assert!(false, "operation can't be undone");
+                }
+            }
+            vec_util::push(&mut history.redo, op);
+            count += 1;
+        }
+        self.history = Some(history);
+        count
+    }
+
+    /// Redo the last `n` modifications reverted by `undo`, returning the number redone.
+    ///
+    /// Any other modification discards the operations which can be redone.
+    pub fn redo(
+        &mut self,
+        n: usize,
+    ) -> usize {
+        self.flush();
+        let mut history = match self.history.take() {
+            Some(history) => history,
+            None => return 0,
+        };
+        let mut count = 0;
+        while count < n {
+            let op = match history.redo.pop() {
+                Some(op) => op,
+                None => break,
+            };
+            self.apply_op(op);
+            vec_util::push(&mut history.undo, op);
+            count += 1;
+        }
+        self.history = Some(history);
+        count
+    }
+
+    // ------------------------------------------------------------------------
+    // Observer
+
+    /// Call `observer` whenever an untaken span is added, removed or resized,
+    /// replacing any previously set observer.
+    ///
+    /// Useful for keeping external structures (a free-space index or visualization) in sync.
+    /// The observer is called after the change is made, see `SpanEvent` for details.
+    ///
+    /// With the `no-panic` feature, a panic in the observer aborts the process.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use rangetree::{RangeTree, SpanEvent};
+    ///
+    /// let mut r: RangeTree<u32> = RangeTree::new([0, 9], false);
+    /// let events = Arc::new(Mutex::new(vec![]));
+    /// let events_observer = events.clone();
+    /// r.set_observer(move |event| events_observer.lock().unwrap().push(event));
+    /// r.take(0);
+    /// assert_eq!(*events.lock().unwrap(), [SpanEvent::Resized { old: [0, 9], new: [1, 9] }]);
+    /// ```
+    pub fn set_observer<F>(
+        &mut self,
+        observer: F,
+    ) where
+        F: FnMut(SpanEvent<TOrd>) + Send + Sync + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Remove the observer set by `set_observer`.
+    pub fn remove_observer(
+        &mut self,
     ) {
+        self.observer = None;
+    }
+
+    // ------------------------------------------------------------------------
+    // Validation
+
+    fn validate_node(
+        &self,
+        node: NodeId,
+    ) -> Result<(), &'static str> {
+        let node_ref = &self.nodes[node];
+        if node_ref.range[0] > node_ref.range[1] {
+            return Err("span minimum is greater than its maximum");
+        }
+        if (node_ref.range[0] < self.range[0]) ||
+           (node_ref.range[1] > self.range[1])
+        {
+            return Err("span is outside the tree bounds");
+        }
+        if node_ref.prev.is_null() {
+            if self.list.first != node {
+                return Err("span without a previous link isn't first");
+            }
+        } else {
+            let node_prev = &self.nodes[node_ref.prev];
+            if node_prev.next != node {
+                return Err("span links are inconsistent");
+            }
+            if node_prev.range[1] >= node_ref.range[0] {
+                return Err("span overlaps or is out of order with the previous span");
+            }
+            if node_prev.range[1].precedes(node_ref.range[0]) {
+                return Err("span is adjacent to the previous span (not merged)");
+            }
+        }
+        if node_ref.next.is_null() && self.list.last != node {
+            return Err("span without a next link isn't last");
+        }
+        if self.backend == Backend::RbTree {
+            if rb::get_or_lower(&self.nodes, self.root, &node_ref.range[0]) != node {
+                return Err("span isn't found in the lookup tree");
+            }
+            let count = TOrd::count_range(&node_ref.range)
+                .saturating_add(rb::count(&self.nodes, node_ref.left))
+                .saturating_add(rb::count(&self.nodes, node_ref.right));
+            if node_ref.count != count {
+                return Err("span's lookup tree count doesn't match its subtree");
+            }
+            let count_max = ::core::cmp::max(
+                TOrd::count_range(&node_ref.range),
+                ::core::cmp::max(rb::count_max(&self.nodes, node_ref.left), rb::count_max(&self.nodes, node_ref.right)),
+            );
+            if node_ref.count_max != count_max {
+                return Err("span's lookup tree largest count doesn't match its subtree");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the integrity of the tree: span ordering and overlap, list links,
+    /// the untaken count and the lookup tree (balance and consistency with the list).
+    ///
+    /// This checks every span, see `validate_incremental` to spread the work over many calls.
+    pub fn validate(
+        &self,
+    ) -> Result<(), ValidationError<TOrd>> {
+        let error = |span: Option<[TOrd; 2]>, reason: &'static str| Err(ValidationError { span, reason });
+        if self.range[0] > self.range[1] {
+            return error(None, "tree bounds minimum is greater than its maximum");
+        }
+        let mut len = 0;
+        let mut count_untaken: u128 = 0;
         let mut node = self.list.first;
-        print!("print: [");
         while !node.is_null() {
-            unsafe {
-                print!("[{}, {}], ", (*node).range[0], (*node).range[1]);
-                node = (*node).next;
+            let range = self.nodes[node].range;
+            len += 1;
+            if len > self.list.len {
+                return error(Some(range), "list has more spans than its length");
+            }
+            if let Err(reason) = self.validate_node(node) {
+                return error(Some(range), reason);
+            }
+            count_untaken = count_untaken.saturating_add(TOrd::count_range(&range));
+            node = self.nodes[node].next;
+        }
+        if len != self.list.len {
+            return error(None, "list has fewer spans than its length");
+        }
+        if count_untaken != self.count_untaken {
+            return error(None, "untaken count doesn't match the spans");
+        }
+        if self.backend == Backend::RbTree {
+            if !rb::is_balanced(&self.nodes, self.root) {
+                return error(None, "lookup tree isn't balanced");
             }
+            if rb::len(&self.nodes, self.root) != len {
+                return error(None, "lookup tree and list have a different number of spans");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the integrity of the tree, spreading the work over many calls.
+    ///
+    /// * `state` the position of the pass, kept between calls.
+    /// * `budget_nodes` the maximum number of spans to check in this call.
+    ///
+    /// The tree may be modified between calls,
+    /// spans changed behind the current position are checked on the next pass.
+    /// Balancing of the lookup tree isn't checked since it can't be done incrementally.
+    pub fn validate_incremental(
+        &self,
+        state: &mut ValidatorState<TOrd>,
+        budget_nodes: usize,
+    ) -> ValidationProgress<TOrd> {
+        let mut node = match state.resume {
+            None => self.list.first,
+            Some(value) => {
+                // first span starting after 'value'
+                if self.backend == Backend::RbTree {
+                    let mut node = rb::get_or_upper(&self.nodes, self.root, &value);
+                    if !node.is_null() && self.nodes[node].range[0] == value {
+                        node = self.nodes[node].next;
+                    }
+                    node
+                } else {
+                    let mut node = self.list.first;
+                    while !node.is_null() && self.nodes[node].range[0] <= value {
+                        node = self.nodes[node].next;
+                    }
+                    node
+                }
+            }
+        };
+
+        for _ in 0..budget_nodes {
+            if node.is_null() {
+                state.resume = None;
+                return ValidationProgress::Complete;
+            }
+            if let Err(reason) = self.validate_node(node) {
+                state.resume = None;
+                return ValidationProgress::Invalid {
+                    span: self.nodes[node].range,
+                    reason,
+                };
+            }
+            state.resume = Some(self.nodes[node].range[0]);
+            node = self.nodes[node].next;
+        }
+
+        if node.is_null() {
+            state.resume = None;
+            ValidationProgress::Complete
+        } else {
+            ValidationProgress::Incomplete
+        }
+    }
+}
+
+/// Trees are equal when they have the same bounds and the same values taken,
+/// settings (such as the journal) and internal layout aren't compared.
+///
+/// Values pending a deferred release are considered taken, see `RangeTree::set_release_deferred`.
+impl<TOrd: RType> PartialEq for RangeTree<TOrd> {
+    fn eq(
+        &self,
+        other: &RangeTree<TOrd>,
+    ) -> bool {
+        self.range == other.range &&
+        self.list.len == other.list.len &&
+        self.iter_untaken().eq(other.iter_untaken())
+    }
+}
+
+impl<TOrd: RType> Eq for RangeTree<TOrd> {}
+
+/// Collect taken values into a tree over every value of the type,
+/// use `RangeTree::from_values_taken` for other bounds.
+impl<TOrd: RType + Bounded> ::core::iter::FromIterator<TOrd> for RangeTree<TOrd> {
+    fn from_iter<I: IntoIterator<Item=TOrd>>(
+        values: I,
+    ) -> RangeTree<TOrd> {
+        match RangeTree::from_values_taken([TOrd::min_value(), TOrd::max_value()], values) {
+            Ok(tree) => tree,
+            // every value is within the full domain.
+            Err(_) => unreachable!(),
         }
-        println!("]");
     }
 }
 
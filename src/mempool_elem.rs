@@ -1,17 +1,47 @@
 // Apache License, Version 2.0
 // (c) Campbell Barton, 2016
 
-/// This module handles many small allocations of the same type
-/// using memory chunks and a single linked list for a free-chain of elements.
+//! This module handles many small allocations of the same type
+//! stored in a single vector, using a single linked list for a free-chain of elements.
+//!
+//! Elements are referenced by their index (`ElemId`) so the storage may be reallocated,
+//! users of this API need to define get/set methods
+//! so they can be members of the free-chain.
+
+use core::cmp;
+use core::mem;
+use core::ops;
+use alloc::vec::Vec;
+
+use vec_util;
+
+/// Index of an element in a `MemPool`, or `ElemId::NULL` for none.
 ///
-/// Users of this API need to define get/set methods
-/// so they can be members of the free-chain.
+/// 32 bits are used (instead of `usize`) to keep links between elements small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ElemId(u32);
+
+impl ElemId {
+    pub const NULL: ElemId = ElemId(u32::MAX);
+
+    #[inline]
+    pub fn is_null(
+        self,
+    ) -> bool {
+        self == ElemId::NULL
+    }
 
-use std::ptr;
+    #[inline]
+    fn index(
+        self,
+    ) -> usize {
+        self.0 as usize
+    }
+}
 
 pub trait MemElemUtils {
-    fn free_ptr_get(&self) -> *mut Self;
-    fn free_ptr_set(&mut self, ptr: *mut Self);
+    fn free_id_get(&self) -> ElemId;
+    fn free_id_set(&mut self, id: ElemId);
 }
 
 pub trait MemElem:
@@ -22,18 +52,38 @@ impl<TElem> MemElem for TElem where TElem:
     MemElemUtils +
     {}
 
-struct MemChunk<TElem: MemElem> {
-    data: Vec<TElem>,
-}
-
 pub struct MemPool<TElem: MemElem> {
-    /// Data storage.
-    chunks: Vec<MemChunk<TElem>>,
-    /// Number of elements per chunk.
+    /// Data storage, elements in use and in the free-chain.
+    elems: Vec<TElem>,
+    /// Minimum number of elements to allocate at once.
     chunk_size: usize,
     /// Single linked list of freed elements to be reused.
-    /// `free_ptr_get` is used to store the *chain* terminating at `null`.
-    free: *mut TElem,
+    /// `free_id_get` is used to store the *chain* terminating at `NULL`.
+    free: ElemId,
+    /// Number of elements in the free-chain.
+    free_len: usize,
+}
+
+impl<TElem: MemElem> ops::Index<ElemId> for MemPool<TElem> {
+    type Output = TElem;
+
+    #[inline]
+    fn index(
+        &self,
+        id: ElemId,
+    ) -> &TElem {
+        vec_util::get(&self.elems, id.index())
+    }
+}
+
+impl<TElem: MemElem> ops::IndexMut<ElemId> for MemPool<TElem> {
+    #[inline]
+    fn index_mut(
+        &mut self,
+        id: ElemId,
+    ) -> &mut TElem {
+        vec_util::get_mut(&mut self.elems, id.index())
+    }
 }
 
 impl <TElem: MemElem> MemPool<TElem> {
@@ -41,55 +91,149 @@ impl <TElem: MemElem> MemPool<TElem> {
         chunk_size: usize,
     ) -> MemPool<TElem> {
         MemPool {
-            chunks: vec![
-                MemChunk {
-                    data: Vec::with_capacity(chunk_size),
-                },
-            ],
-            chunk_size: chunk_size,
-            free: ptr::null_mut(),
+            elems: vec_util::with_capacity(chunk_size),
+            chunk_size,
+            free: ElemId::NULL,
+            free_len: 0,
         }
     }
 
     pub fn clear(
         &mut self,
     ) {
-        self.chunks.truncate(1);
-        self.chunks[0].data.clear();
-        debug_assert!(self.chunks[0].data.capacity() == self.chunk_size);
-        self.free = ptr::null_mut();
+        self.elems.clear();
+        self.elems.shrink_to(self.chunk_size);
+        self.free = ElemId::NULL;
+        self.free_len = 0;
+    }
+
+    /// Reserve capacity so at least `additional` elements can be allocated
+    /// without reallocating.
+    pub fn reserve(
+        &mut self,
+        additional: usize,
+    ) {
+        if self.free_len < additional {
+            vec_util::reserve_exact(&mut self.elems, additional - self.free_len);
+        }
+    }
+
+    /// Reserve as `reserve` does, returning false when memory can't be allocated.
+    pub fn try_reserve(
+        &mut self,
+        additional: usize,
+    ) -> bool {
+        self.free_len >= additional ||
+            vec_util::try_reserve_exact(&mut self.elems, additional - self.free_len)
+    }
+
+    /// Remove elements in the free-chain which are after the last element in use,
+    /// releasing unused capacity.
+    ///
+    /// Elements in use are never moved, so their `ElemId` remains valid.
+    pub fn shrink(
+        &mut self,
+    ) {
+        let mut is_free = vec![false; self.elems.len()];
+        let mut id = self.free;
+        while !id.is_null() {
+            is_free[id.index()] = true;
+            id = self[id].free_id_get();
+        }
+        let len = is_free.iter().rposition(|is_free| !is_free).map_or(0, |index| index + 1);
+        if len != self.elems.len() {
+            // Rebuild the free-chain without the removed elements (keeping the order).
+            let mut id = self.free;
+            let mut id_prev = ElemId::NULL;
+            self.free = ElemId::NULL;
+            self.free_len = 0;
+            while !id.is_null() {
+                let id_next = self[id].free_id_get();
+                if id.index() < len {
+                    if id_prev.is_null() {
+                        self.free = id;
+                    } else {
+                        self[id_prev].free_id_set(id);
+                    }
+                    id_prev = id;
+                    self.free_len += 1;
+                }
+                id = id_next;
+            }
+            if !id_prev.is_null() {
+                self[id_prev].free_id_set(ElemId::NULL);
+            }
+            self.elems.truncate(len);
+        }
+        self.elems.shrink_to_fit();
+    }
+
+    /// Return the number of elements which memory is allocated for.
+    pub fn capacity(
+        &self,
+    ) -> usize {
+        self.elems.capacity()
+    }
+
+    /// Return the minimum number of elements allocated at once.
+    pub fn chunk_size(
+        &self,
+    ) -> usize {
+        self.chunk_size
+    }
+
+    /// Return the number of elements stored (in use or in the free-chain).
+    pub fn elems_len(
+        &self,
+    ) -> usize {
+        self.elems.len()
+    }
+
+    /// Return the number of elements in the free-chain.
+    pub fn free_len(
+        &self,
+    ) -> usize {
+        self.free_len
+    }
+
+    /// Return the number of bytes allocated for elements.
+    pub fn bytes(
+        &self,
+    ) -> usize {
+        self.elems.capacity() * mem::size_of::<TElem>()
     }
 
     pub fn alloc_elem_from(
         &mut self,
         from: TElem,
-    ) -> *mut TElem {
+    ) -> ElemId {
         if self.free.is_null() {
-            if self.chunks.last().unwrap().data.len() == self.chunk_size {
-                self.chunks.push(MemChunk {
-                    data: Vec::with_capacity(self.chunk_size),
-                });
+            let index = self.elems.len();
+            if index >= ElemId::NULL.index() {
+                vec_util::capacity_overflow();
             }
-            let chunk = self.chunks.last_mut().unwrap();
-            chunk.data.push(from);
-            chunk.data.last_mut().unwrap()
-        } else {
-            let elem = self.free;
-            unsafe {
-                self.free = (*elem).free_ptr_get();
-                ptr::write(elem, from);
+            if index == self.elems.capacity() {
+                // grow by at least 'chunk_size' elements.
+                vec_util::reserve_exact(&mut self.elems, cmp::max(self.chunk_size, index));
             }
-            unsafe { &mut *elem }
+            vec_util::push(&mut self.elems, from);
+            ElemId(index as u32)
+        } else {
+            let id = self.free;
+            self.free_len -= 1;
+            self.free = self[id].free_id_get();
+            self[id] = from;
+            id
         }
     }
 
     pub fn free_elem(
         &mut self,
-        elem: *mut TElem,
+        id: ElemId,
     ) {
-        unsafe {
-            (*elem).free_ptr_set(self.free);
-        }
-        self.free = elem;
+        let free = self.free;
+        self[id].free_id_set(free);
+        self.free = id;
+        self.free_len += 1;
     }
 }
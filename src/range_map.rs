@@ -0,0 +1,286 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Interval map, associating a value with each [minimum, maximum] range (inclusive),
+//! using the same node list & red-black tree as `RangeTree`.
+
+use types::{
+    List,
+    Node,
+    NodeId,
+    RType,
+};
+use mempool_elem;
+use rb;
+use RangeTreeError;
+use StepChecked;
+
+// Values are `None` for nodes in the pool's free-chain,
+// so dropping the pool only drops the values of nodes in use.
+type MapNode<TOrd, V> = Node<TOrd, Option<V>>;
+
+/// Map of disjoint [minimum, maximum] ranges (inclusive) to values.
+///
+/// Adjacent ranges with equal values are merged into a single range.
+pub struct RangeMap<TOrd: RType, V> {
+    list: List,
+    root: NodeId,
+    nodes: mempool_elem::MemPool<MapNode<TOrd, V>>,
+}
+
+/// Iterator over [minimum, maximum] ranges (inclusive) and their values,
+/// see `RangeMap::iter`.
+pub struct RangeMapIter<'a, TOrd: 'a + RType, V: 'a> {
+    node: NodeId,
+    map: &'a RangeMap<TOrd, V>,
+}
+
+impl<'a, TOrd: RType, V> Iterator for RangeMapIter<'a, TOrd, V> {
+    type Item = ([TOrd; 2], &'a V);
+
+    fn next(
+        &mut self,
+    ) -> Option<Self::Item> {
+        if self.node.is_null() {
+            return None;
+        }
+        let node = &self.map.nodes[self.node];
+        self.node = node.next;
+        node.value.as_ref().map(|value| (node.range, value))
+    }
+}
+
+impl<TOrd: RType, V> Default for RangeMap<TOrd, V> {
+    fn default() -> RangeMap<TOrd, V> {
+        RangeMap::new()
+    }
+}
+
+impl<TOrd: RType, V> RangeMap<TOrd, V> {
+    /// Create an empty range map.
+    pub fn new() -> RangeMap<TOrd, V> {
+        RangeMap {
+            list: List {
+                first: NodeId::NULL,
+                last: NodeId::NULL,
+                len: 0,
+            },
+            root: NodeId::NULL,
+            nodes: mempool_elem::MemPool::new(1024),
+        }
+    }
+
+    /// Return the number of ranges in the map.
+    pub fn len(
+        &self,
+    ) -> usize {
+        self.list.len
+    }
+
+    /// Check if the map has no ranges.
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.list.first.is_null()
+    }
+
+    /// Remove all ranges.
+    pub fn clear(
+        &mut self,
+    ) {
+        self.list.clear();
+        self.root = NodeId::NULL;
+        self.nodes.clear();
+    }
+
+    /// Return the value of the range containing `value`.
+    pub fn get(
+        &self,
+        value: TOrd,
+    ) -> Option<&V> {
+        self.get_range(value).map(|(_, value)| value)
+    }
+
+    /// Return the [minimum, maximum] range (inclusive) containing `value` and its value.
+    pub fn get_range(
+        &self,
+        value: TOrd,
+    ) -> Option<([TOrd; 2], &V)> {
+        let node = self.find_node_from_value(&value);
+        if node.is_null() {
+            return None;
+        }
+        let node = &self.nodes[node];
+        node.value.as_ref().map(|value| (node.range, value))
+    }
+
+    /// Return an iterator over [minimum, maximum] ranges (inclusive) and their values,
+    /// in ascending order.
+    pub fn iter(
+        &self,
+    ) -> RangeMapIter<'_, TOrd, V> {
+        RangeMapIter {
+            node: self.list.first,
+            map: self,
+        }
+    }
+
+    fn find_node_from_value(
+        &self,
+        value: &TOrd,
+    ) -> NodeId {
+        let node = rb::get_or_lower(&self.nodes, self.root, value);
+        if !node.is_null() && &self.nodes[node].range[1] >= value {
+            node
+        } else {
+            NodeId::NULL
+        }
+    }
+
+    // Return the node containing 'value', or the first node after it.
+    fn find_node_at_or_after(
+        &self,
+        value: &TOrd,
+    ) -> NodeId {
+        let node = rb::get_or_lower(&self.nodes, self.root, value);
+        if node.is_null() {
+            self.list.first
+        } else if &self.nodes[node].range[1] >= value {
+            node
+        } else {
+            self.nodes[node].next
+        }
+    }
+
+    // Add a node after 'node_prev' (at the front when null).
+    fn node_add_after(
+        &mut self,
+        node_prev: NodeId,
+        range: [TOrd; 2],
+        value: V,
+    ) {
+        let node = self.nodes.alloc_elem_from(Node {
+            next: NodeId::NULL,
+            prev: NodeId::NULL,
+
+            range,
+
+            left: NodeId::NULL,
+            right: NodeId::NULL,
+            color: false,
+            count: 0,
+            count_max: 0,
+
+            value: Some(value),
+        });
+        if node_prev.is_null() {
+            self.list.push_front(&mut self.nodes, node);
+        } else {
+            self.list.push_after(&mut self.nodes, node_prev, node);
+        }
+        self.root = rb::insert_root(&mut self.nodes, self.root, node);
+        debug_assert!(rb::is_balanced(&self.nodes, self.root));
+    }
+
+    fn node_remove(
+        &mut self,
+        node: NodeId,
+    ) {
+        self.root = rb::remove_root(&mut self.nodes, self.root, node);
+        debug_assert!(rb::is_balanced(&self.nodes, self.root));
+        self.list.remove(&mut self.nodes, node);
+        self.nodes[node].value = None;
+        self.nodes.free_elem(node);
+    }
+}
+
+impl<TOrd: RType, V: Clone + PartialEq> RangeMap<TOrd, V> {
+    /// Associate `value` with a [minimum, maximum] range (inclusive),
+    /// replacing the values of any overlapping ranges.
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the range is reversed.
+    pub fn insert_range(
+        &mut self,
+        range: [TOrd; 2],
+        value: V,
+    ) -> Result<(), RangeTreeError> {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.insert_range_impl(range, value);
+        Ok(())
+    }
+
+    pub(crate) fn insert_range_impl(
+        &mut self,
+        range: [TOrd; 2],
+        value: V,
+    ) {
+        debug_assert!(range[0] <= range[1]);
+        self.remove_range_impl(range);
+
+        let node_next = self.find_node_at_or_after(&range[0]);
+        let node_prev = if node_next.is_null() { self.list.last } else { self.nodes[node_next].prev };
+        let join_prev = !node_prev.is_null() &&
+            self.nodes[node_prev].range[1].precedes(range[0]) &&
+            self.nodes[node_prev].value.as_ref() == Some(&value);
+        let join_next = !node_next.is_null() &&
+            range[1].precedes(self.nodes[node_next].range[0]) &&
+            self.nodes[node_next].value.as_ref() == Some(&value);
+        if join_prev && join_next {
+            self.nodes[node_prev].range[1] = self.nodes[node_next].range[1];
+            self.node_remove(node_next);
+        } else if join_prev {
+            self.nodes[node_prev].range[1] = range[1];
+        } else if join_next {
+            // the key decreases, there are no nodes between so the tree order is kept.
+            self.nodes[node_next].range[0] = range[0];
+        } else {
+            self.node_add_after(node_prev, range, value);
+        }
+    }
+
+    /// Remove values from a [minimum, maximum] range (inclusive),
+    /// splitting ranges which are partially overlapped.
+    ///
+    /// Returns `RangeTreeError::InvalidRange` when the range is reversed.
+    pub fn remove_range(
+        &mut self,
+        range: [TOrd; 2],
+    ) -> Result<(), RangeTreeError> {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        self.remove_range_impl(range);
+        Ok(())
+    }
+
+    pub(crate) fn remove_range_impl(
+        &mut self,
+        range: [TOrd; 2],
+    ) {
+        debug_assert!(range[0] <= range[1]);
+        let mut node = self.find_node_at_or_after(&range[0]);
+        while !node.is_null() && self.nodes[node].range[0] <= range[1] {
+            let node_next = self.nodes[node].next;
+            let node_range = self.nodes[node].range;
+            let keep_min = node_range[0] < range[0];
+            let keep_max = node_range[1] > range[1];
+            if keep_min && keep_max {
+                let value = self.nodes[node].value.clone();
+                self.nodes[node].range[1] = range[0].pred();
+                if let Some(value) = value {
+                    self.node_add_after(node, [range[1].succ(), node_range[1]], value);
+                }
+            } else if keep_min {
+                self.nodes[node].range[1] = range[0].pred();
+            } else if keep_max {
+                // the key increases, no nodes are between this and 'range[1]'.
+                self.nodes[node].range[0] = range[1].succ();
+            } else {
+                self.node_remove(node);
+            }
+            node = node_next;
+        }
+    }
+}
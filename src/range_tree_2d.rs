@@ -0,0 +1,162 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Rectangle allocation from a grid, see `RangeTree2d`.
+
+use core::cmp;
+use alloc::vec::Vec;
+
+use {
+    RangeTree,
+    RangeTreeError,
+};
+
+// Rows typically have few spans, a small chunk size keeps large grids from
+// reserving memory for nodes which are never used.
+const ROW_CHUNK_SIZE: usize = 8;
+
+/// Allocator of rectangles from a [width, height] grid (texture-atlas or tile allocation),
+/// using a `RangeTree` for each row.
+pub struct RangeTree2d {
+    size: [usize; 2],
+    rows: Vec<RangeTree<usize>>,
+}
+
+impl RangeTree2d {
+    /// Create a grid of [width, height] cells, all untaken.
+    pub fn new(
+        size: [usize; 2],
+    ) -> RangeTree2d {
+        let rows = if size[0] == 0 {
+            vec![]
+        } else {
+            (0..size[1]).map(|_| {
+                RangeTree::new_with_chunk_size([0, size[0] - 1], false, ROW_CHUNK_SIZE)
+            }).collect()
+        };
+        RangeTree2d {
+            size,
+            rows,
+        }
+    }
+
+    /// Return the [width, height] of the grid.
+    pub fn size(
+        &self,
+    ) -> [usize; 2] {
+        self.size
+    }
+
+    /// Check if the cell at `x`, `y` is untaken.
+    pub fn has(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> bool {
+        x < self.size[0] && y < self.size[1] && self.rows[y].has(x)
+    }
+
+    /// Return the number of untaken cells.
+    pub fn count_untaken(
+        &self,
+    ) -> u128 {
+        self.rows.iter().map(|row| row.count_untaken()).sum()
+    }
+
+    /// Take a rectangle of `w` by `h` cells,
+    /// returning the (x, y) of its lowest corner, or `None` when there is no room.
+    ///
+    /// The lowest row is used first, then the lowest column.
+    pub fn take_rect(
+        &mut self,
+        w: usize,
+        h: usize,
+    ) -> Option<(usize, usize)> {
+        if w == 0 || h == 0 || w > self.size[0] || h > self.size[1] {
+            return None;
+        }
+        for y in 0..=(self.size[1] - h) {
+            if let Some(x) = self.find_rect_in_rows(w, y, h) {
+                for row in &mut self.rows[y..(y + h)] {
+                    let node = row.find_node_from_value(&x);
+                    row.take_range_impl([x, x + w - 1], node);
+                }
+                return Some((x, y));
+            }
+        }
+        None
+    }
+
+    /// Release a rectangle taken by `take_rect`,
+    /// returning an error when it's outside the grid or any of its cells aren't taken
+    /// (the grid isn't modified when this is returned).
+    pub fn release_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        w: usize,
+        h: usize,
+    ) -> Result<(), RangeTreeError> {
+        if w == 0 || h == 0 {
+            return Ok(());
+        }
+        let in_bounds = |value: usize, size: usize, bound: usize| {
+            value.checked_add(size).is_some_and(|end| end <= bound)
+        };
+        if !in_bounds(x, w, self.size[0]) || !in_bounds(y, h, self.size[1]) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        let range = [x, x + w - 1];
+        let rows = &mut self.rows[y..(y + h)];
+        if !rows.iter().all(|row| row.is_range_taken(&range)) {
+            return Err(RangeTreeError::NotTaken);
+        }
+        for row in rows {
+            row.release_range_impl(range);
+        }
+        Ok(())
+    }
+
+    // Return the lowest column where 'w' cells are untaken in all rows from 'y' to 'y + h'.
+    fn find_rect_in_rows(
+        &self,
+        w: usize,
+        y: usize,
+        h: usize,
+    ) -> Option<usize> {
+        let mut x = 0;
+        'search: while x + w <= self.size[0] {
+            // first span of the first row with room.
+            let mut node = self.rows[y].find_node_at_or_after(&x);
+            loop {
+                if node.is_null() {
+                    return None;
+                }
+                let range = self.rows[y].nodes[node].range;
+                x = cmp::max(x, range[0]);
+                if range[1] - x + 1 >= w {
+                    break;
+                }
+                node = self.rows[y].nodes[node].next;
+            }
+            // the remaining rows, on failure search again from the next untaken cell.
+            for row in &self.rows[(y + 1)..(y + h)] {
+                let node = row.find_node_at_or_after(&x);
+                if node.is_null() {
+                    return None;
+                }
+                let range = row.nodes[node].range;
+                if range[0] > x {
+                    x = range[0];
+                    continue 'search;
+                }
+                if range[1] - x + 1 < w {
+                    x = range[1] + 1;
+                    continue 'search;
+                }
+            }
+            return Some(x);
+        }
+        None
+    }
+}
@@ -0,0 +1,110 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Ranges reserved until committed, cancelled or expired, see `RangeTree::reserve_range`.
+
+use std::time::Instant;
+
+use alloc::vec::Vec;
+
+use types::RType;
+use span;
+
+/// Identifies a reservation made by `RangeTree::reserve_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReservationId(u64);
+
+struct Reservation<TOrd> {
+    id: ReservationId,
+    range: [TOrd; 2],
+    deadline: Option<Instant>,
+}
+
+/// The reservations of a tree, there are usually few so they're stored in a vector.
+pub struct Reservations<TOrd: RType> {
+    items: Vec<Reservation<TOrd>>,
+    id_next: u64,
+    // number of reservations with a deadline, so expiry is only checked when needed.
+    expiring: usize,
+}
+
+impl<TOrd: RType> Reservations<TOrd> {
+    pub fn new() -> Reservations<TOrd> {
+        Reservations {
+            items: vec![],
+            id_next: 0,
+            expiring: 0,
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        range: [TOrd; 2],
+        deadline: Option<Instant>,
+    ) -> ReservationId {
+        let id = ReservationId(self.id_next);
+        self.id_next += 1;
+        if deadline.is_some() {
+            self.expiring += 1;
+        }
+        self.items.push(Reservation {
+            id,
+            range,
+            deadline,
+        });
+        id
+    }
+
+    /// Remove a reservation, returning its range (`None` when it doesn't exist).
+    pub fn remove(
+        &mut self,
+        id: ReservationId,
+    ) -> Option<[TOrd; 2]> {
+        let index = self.items.iter().position(|item| item.id == id)?;
+        let item = self.items.swap_remove(index);
+        if item.deadline.is_some() {
+            self.expiring -= 1;
+        }
+        Some(item.range)
+    }
+
+    /// Remove reservations whose deadline is at or before `now`, returning their ranges.
+    pub fn remove_expired(
+        &mut self,
+        now: Instant,
+    ) -> Vec<[TOrd; 2]> {
+        let mut ranges = vec![];
+        let mut index = 0;
+        while index < self.items.len() {
+            if self.items[index].deadline.is_some_and(|deadline| deadline <= now) {
+                ranges.push(self.items.swap_remove(index).range);
+                self.expiring -= 1;
+            } else {
+                index += 1;
+            }
+        }
+        ranges
+    }
+
+    /// Check if any reservation has a deadline.
+    pub fn is_expiring(
+        &self,
+    ) -> bool {
+        self.expiring != 0
+    }
+
+    pub fn contains(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        self.items.iter().any(|item| span::contains(&item.range, &value))
+    }
+
+    /// Remove all reservations (their ids are no longer valid).
+    pub fn clear(
+        &mut self,
+    ) {
+        self.items.clear();
+        self.expiring = 0;
+    }
+}
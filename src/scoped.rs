@@ -0,0 +1,77 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! A value released when it goes out of scope, see `RangeTree::take_scoped`.
+
+use core::ops;
+
+use types::RType;
+use RangeTree;
+
+/// A taken value which is released when dropped, unless `keep` is called.
+///
+/// The tree is borrowed for the lifetime of the guard,
+/// use `tree` to access it meanwhile.
+#[must_use = "the value is released when the guard is dropped"]
+pub struct ScopedValue<'a, TOrd: 'a + RType> {
+    tree: &'a mut RangeTree<TOrd>,
+    // `None` once kept.
+    value: Option<TOrd>,
+}
+
+impl<'a, TOrd: RType> ScopedValue<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a mut RangeTree<TOrd>,
+        value: TOrd,
+    ) -> ScopedValue<'a, TOrd> {
+        ScopedValue {
+            tree,
+            value: Some(value),
+        }
+    }
+
+    /// Return the taken value.
+    pub fn value(
+        &self,
+    ) -> TOrd {
+        self.value.unwrap()
+    }
+
+    /// Return the tree the value was taken from.
+    ///
+    /// Note: when the value is released from the tree, dropping the guard doesn't release it again,
+    /// however if it's taken again meanwhile, dropping the guard releases it.
+    pub fn tree(
+        &mut self,
+    ) -> &mut RangeTree<TOrd> {
+        self.tree
+    }
+
+    /// Keep the value taken, returning it.
+    pub fn keep(
+        mut self,
+    ) -> TOrd {
+        self.value.take().unwrap()
+    }
+}
+
+impl<'a, TOrd: RType> ops::Deref for ScopedValue<'a, TOrd> {
+    type Target = TOrd;
+
+    fn deref(
+        &self,
+    ) -> &TOrd {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<'a, TOrd: RType> Drop for ScopedValue<'a, TOrd> {
+    fn drop(
+        &mut self,
+    ) {
+        if let Some(value) = self.value.take() {
+            // the value may have been released through `tree`.
+            let _ = self.tree.try_release(value);
+        }
+    }
+}
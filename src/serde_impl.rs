@@ -0,0 +1,49 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Serialization (feature `serde`).
+//!
+//! A tree is stored as its bounds and untaken ranges,
+//! the internal structure is rebuilt on deserialization.
+
+use serde::{
+    de,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+use alloc::vec::Vec;
+
+use types::RType;
+use RangeTree;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "RangeTree")]
+struct RangeTreeData<TOrd> {
+    bounds: [TOrd; 2],
+    untaken: Vec<[TOrd; 2]>,
+}
+
+impl<TOrd: RType + Serialize> Serialize for RangeTree<TOrd> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        RangeTreeData {
+            bounds: self.range,
+            untaken: self.ranges_untaken_as_vec(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, TOrd: RType + Deserialize<'de>> Deserialize<'de> for RangeTree<TOrd> {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RangeTree<TOrd>, D::Error> {
+        let data: RangeTreeData<TOrd> = RangeTreeData::deserialize(deserializer)?;
+        RangeTree::from_ranges_untaken(data.bounds, &data.untaken)
+            .map_err(|err| de::Error::custom(format_args!("invalid untaken ranges: {:?}", err)))
+    }
+}
@@ -0,0 +1,333 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! A range tree which can be used from multiple threads, see `SharedRangeTree`.
+
+use std::sync::{
+    Mutex,
+    MutexGuard,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::time::Instant;
+use core::mem;
+
+use alloc::vec::Vec;
+
+use types::RType;
+use {
+    RangeTree,
+    RangeTreeError,
+    ReservationId,
+};
+use span;
+
+/// A thread safe range tree, with the bounds split into shards,
+/// each a `RangeTree` behind its own lock.
+///
+/// Taking or releasing a value only locks the shard containing it,
+/// `take_any` starts at a different shard on each call, so threads rarely wait on each other.
+///
+/// Note: unlike `RangeTree::take_any`, values aren't taken in order.
+/// Methods panic if a thread panicked while holding a shard lock.
+pub struct SharedRangeTree<TOrd: RType> {
+    range: [TOrd; 2],
+    shards: Vec<Mutex<RangeTree<TOrd>>>,
+    // number of values in each shard (the last shard holds the remainder).
+    shard_len: u128,
+    // shard to start searching from in `take_any`.
+    shard_next: AtomicUsize,
+}
+
+impl<TOrd: RType> SharedRangeTree<TOrd> {
+    /// Create a tree with all values in `range` untaken, split into `shards` (at least one).
+    pub fn new(
+        range: [TOrd; 2],
+        shards: usize,
+    ) -> SharedRangeTree<TOrd> {
+        debug_assert!(range[0] <= range[1]);
+        let count_all = TOrd::count_range(&range);
+        let shards_len = ::core::cmp::min(::core::cmp::max(shards, 1) as u128, count_all);
+        let shard_len = count_all / shards_len;
+        let shards = (0..shards_len).map(|i| {
+            let value_min = TOrd::count_offset(range[0], i * shard_len);
+            let value_max = if i + 1 == shards_len {
+                range[1]
+            } else {
+                TOrd::count_offset(value_min, shard_len - 1)
+            };
+            Mutex::new(RangeTree::new([value_min, value_max], false))
+        }).collect();
+        SharedRangeTree {
+            range,
+            shards,
+            shard_len,
+            shard_next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Return the [minimum, maximum] values (inclusive).
+    pub fn bounds(
+        &self,
+    ) -> [TOrd; 2] {
+        self.range
+    }
+
+    fn shard_lock(
+        &self,
+        index: usize,
+    ) -> MutexGuard<'_, RangeTree<TOrd>> {
+        self.shards[index].lock().unwrap()
+    }
+
+    // Return the shard containing 'value' which must be in bounds.
+    fn shard_index(
+        &self,
+        value: TOrd,
+    ) -> usize {
+        let offset = TOrd::count_range(&[self.range[0], value]) - 1;
+        ::core::cmp::min(offset / self.shard_len, self.shards.len() as u128 - 1) as usize
+    }
+
+    fn in_bounds(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        value >= self.range[0] && value <= self.range[1]
+    }
+
+    /// Take a value, returning an error when it's out of bounds or already taken.
+    pub fn try_take(
+        &self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        if !self.in_bounds(value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.shard_lock(self.shard_index(value)).try_take(value)
+    }
+
+    /// Release a value, returning an error when it's out of bounds or not taken.
+    pub fn try_release(
+        &self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        if !self.in_bounds(value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.shard_lock(self.shard_index(value)).try_release(value)
+    }
+
+    /// Take a value.
+    ///
+    /// Note: taking a value which is out of bounds or already taken will panic.
+    pub fn take(
+        &self,
+        value: TOrd,
+    ) {
+        self.try_take(value).unwrap();
+    }
+
+    /// Release a value.
+    ///
+    /// Note: releasing a value which is out of bounds or not taken will panic.
+    pub fn release(
+        &self,
+        value: TOrd,
+    ) {
+        self.try_release(value).unwrap();
+    }
+
+    /// Take any value, `None` when all values are taken.
+    pub fn take_any(
+        &self,
+    ) -> Option<TOrd> {
+        let shards_len = self.shards.len();
+        let index_start = self.shard_next.fetch_add(1, Ordering::Relaxed) % shards_len;
+        for i in 0..shards_len {
+            let index = (index_start + i) % shards_len;
+            if let Some(value) = self.shard_lock(index).take_any() {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Reserve a value, see `reserve_range`.
+    pub fn reserve(
+        &self,
+        value: TOrd,
+        deadline: Option<Instant>,
+    ) -> Result<ReservationGuard<'_, TOrd>, RangeTreeError> {
+        self.reserve_range([value, value], deadline)
+    }
+
+    /// Reserve every value in the [minimum, maximum] range (inclusive),
+    /// returning an error when any value is out of bounds or already taken (nothing is reserved).
+    ///
+    /// Each shard holds a reservation for its part of the range (see `RangeTree::reserve_range`),
+    /// so reserved values are excluded from `take_any` and can't be released by `try_release`.
+    /// Until the reservation is committed they're released when it's dropped
+    /// or when `deadline` is reached (checked by `take_any`, `reap_reservations` & `commit`).
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use rangetree::SharedRangeTree;
+    ///
+    /// let ids: SharedRangeTree<u32> = SharedRangeTree::new([0, 99], 4);
+    /// {
+    ///     let _reservation = ids.reserve_range([10, 29], None).unwrap();
+    ///     assert!(!ids.has(20));
+    ///     // dropped without committing.
+    /// }
+    /// assert!(ids.has(20));
+    ///
+    /// let reservation = ids.reserve(5, Some(Instant::now() + Duration::from_secs(30))).unwrap();
+    /// assert_eq!(reservation.commit(), Ok([5, 5]));
+    /// assert!(!ids.has(5));
+    /// ```
+    pub fn reserve_range(
+        &self,
+        range: [TOrd; 2],
+        deadline: Option<Instant>,
+    ) -> Result<ReservationGuard<'_, TOrd>, RangeTreeError> {
+        if range[0] > range[1] {
+            return Err(RangeTreeError::InvalidRange);
+        }
+        if !self.in_bounds(range[0]) || !self.in_bounds(range[1]) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        let mut shards = self.shard_lock_range(range);
+        if !shards.iter().all(|&(_, ref shard, part)| shard.all_untaken_in(part)) {
+            return Err(RangeTreeError::AlreadyTaken);
+        }
+        let mut parts = Vec::with_capacity(shards.len());
+        for &mut (index, ref mut shard, part) in &mut shards {
+            match shard.reserve_range(part, deadline) {
+                Ok(id) => parts.push((index, id)),
+                // checked above, while the shards are locked.
+                Err(_) => unreachable!("reserved range is untaken"),
+            }
+        }
+        Ok(ReservationGuard {
+            tree: self,
+            range,
+            parts,
+        })
+    }
+
+    /// Release the values of reservations whose deadline has been reached,
+    /// returning the number of shard reservations which expired.
+    pub fn reap_reservations(
+        &self,
+    ) -> usize {
+        (0..self.shards.len()).map(|i| self.shard_lock(i).reap_reservations()).sum()
+    }
+
+    // Lock the shards overlapping 'range' (which must be in bounds),
+    // returning each with its index and the part of 'range' it contains.
+    // Shards are locked in ascending order, so threads locking multiple shards don't deadlock.
+    fn shard_lock_range(
+        &self,
+        range: [TOrd; 2],
+    ) -> Vec<(usize, MutexGuard<'_, RangeTree<TOrd>>, [TOrd; 2])> {
+        (self.shard_index(range[0])..=self.shard_index(range[1])).map(|index| {
+            let shard = self.shard_lock(index);
+            let part = span::clamp(&range, &shard.bounds()).unwrap();
+            (index, shard, part)
+        }).collect()
+    }
+
+    /// Check if the value is untaken, values out of bounds return false.
+    pub fn has(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        self.in_bounds(value) && self.shard_lock(self.shard_index(value)).has(value)
+    }
+
+    /// Return the number of untaken values.
+    ///
+    /// Shards are locked one at a time,
+    /// so this may not match any single point in time while other threads are modifying the tree.
+    pub fn count_untaken(
+        &self,
+    ) -> u128 {
+        (0..self.shards.len()).map(|i| self.shard_lock(i).count_untaken()).sum()
+    }
+}
+
+/// Values reserved by `SharedRangeTree::reserve_range`,
+/// released when dropped or expired unless `commit` is called.
+#[must_use = "reserved values are released when the reservation is dropped"]
+pub struct ReservationGuard<'a, TOrd: 'a + RType> {
+    tree: &'a SharedRangeTree<TOrd>,
+    range: [TOrd; 2],
+    // the reservation of each shard overlapping 'range' (in ascending order),
+    // empty once committed or released.
+    parts: Vec<(usize, ReservationId)>,
+}
+
+impl<'a, TOrd: RType> ReservationGuard<'a, TOrd> {
+    /// Return the [minimum, maximum] values (inclusive) of the reservation.
+    pub fn range(
+        &self,
+    ) -> [TOrd; 2] {
+        self.range
+    }
+
+    /// Keep the reserved values taken, returning their range.
+    ///
+    /// Returns `RangeTreeError::Expired` when the deadline was reached,
+    /// the values are released in this case.
+    pub fn commit(
+        mut self,
+    ) -> Result<[TOrd; 2], RangeTreeError> {
+        let parts = mem::take(&mut self.parts);
+        // lock every shard first, so the values are committed or released together.
+        let mut shards: Vec<_> = parts.iter().map(|&(index, id)| (self.tree.shard_lock(index), id)).collect();
+        let mut committed = Vec::with_capacity(shards.len());
+        let mut is_expired = false;
+        for (index, &mut (ref mut shard, id)) in shards.iter_mut().enumerate() {
+            match shard.commit_reservation(id) {
+                Ok(part) => committed.push((index, part)),
+                Err(_) => is_expired = true,
+            }
+        }
+        if !is_expired {
+            return Ok(self.range);
+        }
+        // release the parts which were committed,
+        // values of expired parts may already be taken again.
+        for (index, part) in committed {
+            shards[index].0.release_all_in(part);
+        }
+        Err(RangeTreeError::Expired)
+    }
+
+    /// Release the reserved values (the same as dropping the reservation).
+    pub fn release(
+        self,
+    ) {
+        // released when dropped.
+    }
+}
+
+impl<'a, TOrd: RType> Drop for ReservationGuard<'a, TOrd> {
+    fn drop(
+        &mut self,
+    ) {
+        // Only the shard reservations are cancelled (none when committed or expired),
+        // a poisoned lock is recovered so the values aren't leaked
+        // (a shard is never left invalid by a panic while it's locked).
+        for &(index, id) in &self.parts {
+            let mut shard = match self.tree.shards[index].lock() {
+                Ok(shard) => shard,
+                Err(err) => err.into_inner(),
+            };
+            let _ = shard.cancel_reservation(id);
+        }
+    }
+}
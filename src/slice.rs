@@ -0,0 +1,71 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! A read-only window into a `RangeTree`, see `RangeTree::slice`.
+
+use types::RType;
+use RangeTree;
+use span;
+
+/// Queries of a `RangeTree` restricted to a [minimum, maximum] range (inclusive).
+#[derive(Clone, Copy)]
+pub struct RangeTreeSlice<'a, TOrd: 'a + RType> {
+    tree: &'a RangeTree<TOrd>,
+    range: [TOrd; 2],
+}
+
+impl<'a, TOrd: RType> RangeTreeSlice<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a RangeTree<TOrd>,
+        range: [TOrd; 2],
+    ) -> RangeTreeSlice<'a, TOrd> {
+        // reversed ranges are rejected by `RangeTree::slice`.
+        debug_assert!(range[0] <= range[1]);
+        RangeTreeSlice {
+            tree,
+            range,
+        }
+    }
+
+    /// Return the [minimum, maximum] values (inclusive) of this window.
+    pub fn bounds(
+        &self,
+    ) -> [TOrd; 2] {
+        self.range
+    }
+
+    /// Check if the window has this value (not taken),
+    /// values outside the window return false.
+    pub fn has(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        span::contains(&self.range, &value) && self.tree.has(value)
+    }
+
+    /// Return the number of untaken values in the window.
+    ///
+    /// This uses `RangeTree::rank_untaken`, so spans within the window aren't visited
+    /// (except with `Backend::List`).
+    pub fn count_untaken(
+        &self,
+    ) -> u128 {
+        // the rank of the maximum doesn't include the maximum.
+        let count_max = u128::from(self.tree.has(self.range[1]));
+        self.tree.rank_untaken(self.range[1]).saturating_add(count_max) - self.tree.rank_untaken(self.range[0])
+    }
+
+    /// Return the lowest untaken value in the window.
+    pub fn first_untaken(
+        &self,
+    ) -> Option<TOrd> {
+        self.tree.first_untaken_at_or_after(self.range[0]).filter(|value| *value <= self.range[1])
+    }
+
+    /// Return an iterator over untaken ranges, clipped to the window.
+    pub fn iter_untaken(
+        &self,
+    ) -> impl DoubleEndedIterator<Item=[TOrd; 2]> + 'a {
+        self.tree.iter_untaken_in(self.range)
+    }
+}
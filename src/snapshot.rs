@@ -0,0 +1,168 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Compact binary encoding of a tree, see `RangeTree::to_bytes`.
+//!
+//! Version 1 layout, integers are unsigned LEB128 varints (so the encoding is endian independent):
+//!
+//! - Version (byte).
+//! - Size of the value type in bytes (byte), so a different type isn't decoded by mistake.
+//! - Minimum bound, as its offset from the type minimum.
+//! - Maximum bound, as its offset from the minimum bound.
+//! - Number of untaken spans.
+//! - For each span: the offset of its minimum from the previous span's maximum
+//!   (from the minimum bound for the first span), then the offset of its maximum from its minimum.
+//!
+//! Offsets are differences between values, so they never overflow `u128`.
+
+use core::fmt;
+use core::mem;
+use alloc::vec::Vec;
+
+use types::RType;
+use {
+    Bounded,
+    RangeTree,
+    RangeTreeError,
+    count_offset_between as offset,
+};
+
+const VERSION: u8 = 1;
+
+/// Error returned by `RangeTree::from_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data was written by an unsupported version of the format.
+    UnsupportedVersion,
+    /// The data was written for a value type of a different size.
+    TypeMismatch,
+    /// The data ends before the tree is complete.
+    Truncated,
+    /// A value is outside the type (or tree) bounds or there is data after the tree.
+    InvalidData,
+    /// The untaken spans aren't sorted or overlap.
+    InvalidRange(RangeTreeError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match *self {
+            DecodeError::UnsupportedVersion => f.write_str("unsupported format version"),
+            DecodeError::TypeMismatch => f.write_str("value type size doesn't match"),
+            DecodeError::Truncated => f.write_str("data is truncated"),
+            DecodeError::InvalidData => f.write_str("data is invalid"),
+            DecodeError::InvalidRange(ref err) => write!(f, "untaken spans: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(
+        &self,
+    ) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            DecodeError::InvalidRange(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+fn write_varint(
+    bytes: &mut Vec<u8>,
+    mut value: u128,
+) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(
+    bytes: &mut &[u8],
+) -> Result<u128, DecodeError> {
+    let mut value: u128 = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = bytes.split_first().ok_or(DecodeError::Truncated)?;
+        *bytes = rest;
+        let bits = u128::from(byte & 0x7f);
+        if shift >= 128 || (bits << shift) >> shift != bits {
+            return Err(DecodeError::InvalidData);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+// Read an offset from 'value', which must not step past 'max'.
+fn read_value<TOrd: RType>(
+    bytes: &mut &[u8],
+    value: TOrd,
+    max: TOrd,
+) -> Result<TOrd, DecodeError> {
+    let count = read_varint(bytes)?;
+    if count > offset(value, max) {
+        return Err(DecodeError::InvalidData);
+    }
+    Ok(TOrd::count_offset(value, count))
+}
+
+pub fn encode<TOrd: RType + Bounded>(
+    tree: &RangeTree<TOrd>,
+) -> Vec<u8> {
+    let mut bytes = vec![VERSION, mem::size_of::<TOrd>() as u8];
+    write_varint(&mut bytes, offset(TOrd::min_value(), tree.range[0]));
+    write_varint(&mut bytes, offset(tree.range[0], tree.range[1]));
+    write_varint(&mut bytes, tree.count_ranges_untaken() as u128);
+    let mut value_prev = tree.range[0];
+    for range in tree.iter_untaken() {
+        write_varint(&mut bytes, offset(value_prev, range[0]));
+        write_varint(&mut bytes, offset(range[0], range[1]));
+        value_prev = range[1];
+    }
+    bytes
+}
+
+pub fn decode<TOrd: RType + Bounded>(
+    mut bytes: &[u8],
+) -> Result<RangeTree<TOrd>, DecodeError> {
+    let bytes = &mut bytes;
+    let (&version, rest) = bytes.split_first().ok_or(DecodeError::Truncated)?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion);
+    }
+    let (&size, rest) = rest.split_first().ok_or(DecodeError::Truncated)?;
+    if usize::from(size) != mem::size_of::<TOrd>() {
+        return Err(DecodeError::TypeMismatch);
+    }
+    *bytes = rest;
+
+    let value_max = TOrd::max_value();
+    let range_min = read_value(bytes, TOrd::min_value(), value_max)?;
+    let range = [range_min, read_value(bytes, range_min, value_max)?];
+    let len = read_varint(bytes)?;
+    // each span is at least 2 bytes, don't allocate for a length the data can't contain.
+    if len > (bytes.len() / 2) as u128 {
+        return Err(DecodeError::Truncated);
+    }
+    let mut ranges_untaken = Vec::with_capacity(len as usize);
+    let mut value_prev = range[0];
+    for _ in 0..len {
+        let value = read_value(bytes, value_prev, range[1])?;
+        let range_untaken = [value, read_value(bytes, value, range[1])?];
+        ranges_untaken.push(range_untaken);
+        value_prev = range_untaken[1];
+    }
+    if !bytes.is_empty() {
+        return Err(DecodeError::InvalidData);
+    }
+    RangeTree::from_ranges_untaken(range, &ranges_untaken).map_err(DecodeError::InvalidRange)
+}
@@ -0,0 +1,98 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Helpers for [minimum, maximum] ranges (inclusive),
+//! as used throughout the `RangeTree` API.
+//!
+//! None of these functions overflow, including for ranges touching the type limits.
+
+use types::RType;
+use StepChecked;
+
+/// [minimum, maximum] range (inclusive), the minimum must not be greater than the maximum.
+pub type Span<TOrd> = [TOrd; 2];
+
+/// Number of values in the span.
+#[inline]
+pub fn len<TOrd: RType>(
+    span: &Span<TOrd>,
+) -> u128 {
+    TOrd::count_range(span)
+}
+
+/// Check if the span contains `value`.
+#[inline]
+pub fn contains<TOrd: RType>(
+    span: &Span<TOrd>,
+    value: &TOrd,
+) -> bool {
+    (value >= &span[0]) && (value <= &span[1])
+}
+
+/// Check if the spans share any values.
+#[inline]
+pub fn overlaps<TOrd: RType>(
+    a: &Span<TOrd>,
+    b: &Span<TOrd>,
+) -> bool {
+    (a[0] <= b[1]) && (b[0] <= a[1])
+}
+
+/// Check if the spans don't overlap but have no values between them.
+#[inline]
+pub fn is_adjacent<TOrd: RType>(
+    a: &Span<TOrd>,
+    b: &Span<TOrd>,
+) -> bool {
+    a[1].precedes(b[0]) || b[1].precedes(a[0])
+}
+
+/// Return the part of `span` within `bounds`, `None` when they don't overlap.
+#[inline]
+pub fn clamp<TOrd: RType>(
+    span: &Span<TOrd>,
+    bounds: &Span<TOrd>,
+) -> Option<Span<TOrd>> {
+    if overlaps(span, bounds) {
+        Some([
+            ::core::cmp::max(span[0], bounds[0]),
+            ::core::cmp::min(span[1], bounds[1]),
+        ])
+    } else {
+        None
+    }
+}
+
+/// Split the span into values below `value` and values from `value` upwards,
+/// either side is `None` when it would be empty.
+#[inline]
+pub fn split<TOrd: RType>(
+    span: &Span<TOrd>,
+    value: TOrd,
+) -> (Option<Span<TOrd>>, Option<Span<TOrd>>) {
+    if value > span[1] {
+        (Some(*span), None)
+    } else {
+        match value.pred_checked(span[0]) {
+            Some(value_prev) => (Some([span[0], value_prev]), Some([value, span[1]])),
+            None => (None, Some(*span)),
+        }
+    }
+}
+
+/// Return a single span containing both spans,
+/// `None` when they neither overlap nor are adjacent.
+#[inline]
+pub fn merge<TOrd: RType>(
+    a: &Span<TOrd>,
+    b: &Span<TOrd>,
+) -> Option<Span<TOrd>> {
+    if overlaps(a, b) || is_adjacent(a, b) {
+        Some([
+            ::core::cmp::min(a[0], b[0]),
+            ::core::cmp::max(a[1], b[1]),
+        ])
+    } else {
+        None
+    }
+}
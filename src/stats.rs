@@ -0,0 +1,186 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Statistics of a `RangeTree`, see `RangeTree::stats`.
+
+use core::fmt;
+use core::fmt::Write;
+use alloc::string::{
+    String,
+    ToString,
+};
+use alloc::vec::Vec;
+
+use types::RType;
+use {
+    Count,
+    Growth,
+    RangeTree,
+};
+
+/// Statistics of a tree, for monitoring allocator health.
+///
+/// Field names are stable, they're used as keys by `RangeTree::stats_json`
+/// (and serialization with the `serde` feature).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RangeTreeStats<TOrd: RType> {
+    /// The [minimum, maximum] values (inclusive).
+    pub domain: [TOrd; 2],
+    pub count_taken: u128,
+    pub count_untaken: u128,
+    /// The highest number of values taken at once since the tree was created.
+    pub count_taken_max: u128,
+    /// Number of untaken spans.
+    pub spans: usize,
+    /// Length of the shortest and longest untaken spans, zero when there are none.
+    pub span_len_min: u128,
+    pub span_len_max: u128,
+    /// Number of untaken spans by length,
+    /// where item `i` counts spans of length `[2^i, 2^(i + 1))`,
+    /// trailing empty items are removed.
+    pub span_histogram: Vec<usize>,
+    /// Policy settings.
+    pub growth: Option<Growth<TOrd>>,
+    pub release_deferred: Option<usize>,
+    pub journal: bool,
+    pub rate_history: Option<usize>,
+}
+
+impl<TOrd: RType> RangeTreeStats<TOrd> {
+    pub(crate) fn new(
+        tree: &RangeTree<TOrd>,
+    ) -> RangeTreeStats<TOrd> {
+        let mut span_len_min = 0;
+        let mut span_len_max = 0;
+        let mut span_histogram = vec![];
+        for range in tree.iter_untaken() {
+            let len = TOrd::count_range(&range);
+            if span_len_max == 0 || len < span_len_min {
+                span_len_min = len;
+            }
+            if len > span_len_max {
+                span_len_max = len;
+            }
+            // 'len' is never zero.
+            let bucket = (127 - len.leading_zeros()) as usize;
+            if span_histogram.len() <= bucket {
+                span_histogram.resize(bucket + 1, 0);
+            }
+            span_histogram[bucket] += 1;
+        }
+        let count_taken = tree.count_taken();
+        RangeTreeStats {
+            domain: tree.range,
+            count_taken,
+            count_untaken: tree.count_untaken,
+            count_taken_max: ::core::cmp::max(tree.count_taken_max, count_taken),
+            spans: tree.list.len,
+            span_len_min,
+            span_len_max,
+            span_histogram,
+            growth: tree.growth,
+            release_deferred: tree.release_pending.as_ref().map(|_| tree.release_pending_limit),
+            journal: tree.journal.is_some(),
+            rate_history: tree.rate_history.as_ref().map(|_| tree.rate_history_limit),
+        }
+    }
+}
+
+/// Memory used by a tree, see `RangeTree::memory_usage`.
+///
+/// Nodes are stored in a single vector which is kept until the tree is cleared or shrunk,
+/// so `nodes_free` shows memory retained from earlier fragmentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MemoryUsage {
+    /// Number of nodes memory is allocated for.
+    pub capacity: usize,
+    /// Number of nodes stored (in use or free).
+    pub nodes: usize,
+    /// Number of stored nodes which are free (available for reuse).
+    pub nodes_free: usize,
+    /// Approximate number of bytes used by the tree, including unused capacity.
+    pub bytes: usize,
+}
+
+fn json_option<T: fmt::Display>(
+    value: Option<T>,
+) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "null".to_string(),
+    }
+}
+
+// Write a value as a JSON number for integer types,
+// otherwise as an escaped JSON string (`char` values, as serde writes them).
+fn json_value<T: Count + fmt::Display>(
+    value: &T,
+) -> String {
+    let text = value.to_string();
+    if T::IS_INTEGER {
+        return text;
+    }
+    let mut s = String::with_capacity(text.len() + 2);
+    s.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            '\u{0}'..='\u{1f}' => {
+                let _ = write!(s, "\\u{:04x}", c as u32);
+            }
+            _ => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+impl<TOrd: RType + fmt::Display> RangeTreeStats<TOrd> {
+    /// Return the statistics as a single line JSON object.
+    ///
+    /// Counts are written as JSON numbers, readers should parse them as 128 bit integers.
+    /// Values (the domain and growth limit) are written as numbers for integer types
+    /// and as strings otherwise (see `Count::IS_INTEGER`).
+    pub fn to_json(
+        &self,
+    ) -> String {
+        let mut s = String::new();
+        // Writing to a string can't fail.
+        let _ = write!(
+            s,
+            "{{\"domain\":[{},{}],\"count_taken\":{},\"count_untaken\":{},\"count_taken_max\":{},\
+             \"spans\":{},\"span_len_min\":{},\"span_len_max\":{},\"span_histogram\":[",
+            json_value(&self.domain[0]), json_value(&self.domain[1]),
+            self.count_taken,
+            self.count_untaken,
+            self.count_taken_max,
+            self.spans,
+            self.span_len_min,
+            self.span_len_max,
+        );
+        for (i, n) in self.span_histogram.iter().enumerate() {
+            if i != 0 {
+                s.push(',');
+            }
+            let _ = write!(s, "{}", n);
+        }
+        let growth = self.growth.map(|growth| {
+            format!("{{\"increment\":{},\"limit\":{}}}", growth.increment, json_value(&growth.limit))
+        });
+        let _ = write!(
+            s,
+            "],\"growth\":{},\"release_deferred\":{},\"journal\":{},\"rate_history\":{}}}",
+            json_option(growth),
+            json_option(self.release_deferred),
+            self.journal,
+            json_option(self.rate_history),
+        );
+        s
+    }
+}
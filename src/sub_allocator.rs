@@ -0,0 +1,127 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! A window into a `RangeTree`, see `RangeTree::sub_allocator`.
+
+use core::cmp;
+
+use types::RType;
+use {
+    RangeTree,
+    RangeTreeError,
+};
+use span;
+
+/// Take/release API of a `RangeTree` restricted to a [minimum, maximum] range (inclusive).
+pub struct SubAllocator<'a, TOrd: 'a + RType> {
+    tree: &'a mut RangeTree<TOrd>,
+    range: [TOrd; 2],
+}
+
+impl<'a, TOrd: RType> SubAllocator<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a mut RangeTree<TOrd>,
+        range: [TOrd; 2],
+    ) -> SubAllocator<'a, TOrd> {
+        // reversed ranges are rejected by `RangeTree::sub_allocator`.
+        debug_assert!(range[0] <= range[1]);
+        SubAllocator {
+            tree,
+            range,
+        }
+    }
+
+    /// Return the [minimum, maximum] values (inclusive) of this window.
+    pub fn bounds(
+        &self,
+    ) -> [TOrd; 2] {
+        self.range
+    }
+
+    /// Check if the window has this value (not taken),
+    /// values outside the window return false.
+    pub fn has(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        span::contains(&self.range, &value) && self.tree.has(value)
+    }
+
+    /// Take a value from the window.
+    ///
+    /// Note: taking a value outside the window or which is already taken will panic.
+    pub fn take(
+        &mut self,
+        value: TOrd,
+    ) {
+        assert!(span::contains(&self.range, &value));
+        self.tree.take(value);
+    }
+
+    /// Take a value, returning an error when it's outside the window or already taken.
+    pub fn try_take(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        if !span::contains(&self.range, &value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.tree.try_take(value)
+    }
+
+    /// Take a value which may already be taken,
+    /// returning true if the value was taken by this call.
+    /// Values outside the window return false.
+    pub fn retake(
+        &mut self,
+        value: TOrd,
+    ) -> bool {
+        span::contains(&self.range, &value) && self.tree.retake(value)
+    }
+
+    /// Take the lowest untaken value in the window.
+    pub fn take_any(
+        &mut self,
+    ) -> Option<TOrd> {
+        let node = self.tree.find_node_at_or_after(&self.range[0]);
+        if node.is_null() {
+            return None;
+        }
+        let node_range = self.tree.nodes[node].range;
+        if node_range[0] > self.range[1] {
+            return None;
+        }
+        let value = cmp::max(node_range[0], self.range[0]);
+        self.tree.take_impl(value, node);
+        Some(value)
+    }
+
+    /// Release a value that has been taken.
+    ///
+    /// Note: releasing a value outside the window or which isn't taken will panic.
+    pub fn release(
+        &mut self,
+        value: TOrd,
+    ) {
+        assert!(span::contains(&self.range, &value));
+        self.tree.release(value);
+    }
+
+    /// Release a value, returning an error when it's outside the window or isn't taken.
+    pub fn try_release(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        if !span::contains(&self.range, &value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        self.tree.try_release(value)
+    }
+
+    /// Return an iterator over untaken ranges, clipped to the window.
+    pub fn iter_untaken(
+        &self,
+    ) -> impl DoubleEndedIterator<Item=[TOrd; 2]> + '_ {
+        self.tree.iter_untaken_in(self.range)
+    }
+}
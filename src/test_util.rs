@@ -0,0 +1,86 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Helpers for tests written against this crate (feature `test-util`).
+
+use core::fmt;
+use core::fmt::Write;
+use alloc::string::String;
+
+use types::RType;
+
+/// Compare two sorted lists of [minimum, maximum] ranges,
+/// returning a line-per-range listing when they differ.
+///
+/// Lines are prefixed with `-` for ranges only expected,
+/// `+` for ranges only found in the tree.
+#[doc(hidden)]
+pub fn ranges_diff<TOrd: RType + fmt::Debug>(
+    label: &str,
+    actual: &[[TOrd; 2]],
+    expected: &[[TOrd; 2]],
+) -> Option<String> {
+    if actual == expected {
+        return None;
+    }
+    let mut text = String::new();
+    writeln!(text, "{} ranges differ (-expected, +actual):", label).unwrap();
+    let mut i_actual = 0;
+    let mut i_expected = 0;
+    while i_actual < actual.len() || i_expected < expected.len() {
+        let order = if i_actual == actual.len() {
+            ::core::cmp::Ordering::Greater
+        } else if i_expected == expected.len() {
+            ::core::cmp::Ordering::Less
+        } else {
+            actual[i_actual].cmp(&expected[i_expected])
+        };
+        match order {
+            ::core::cmp::Ordering::Equal => {
+                writeln!(text, "  {:?}", actual[i_actual]).unwrap();
+                i_actual += 1;
+                i_expected += 1;
+            }
+            ::core::cmp::Ordering::Less => {
+                writeln!(text, "+ {:?}", actual[i_actual]).unwrap();
+                i_actual += 1;
+            }
+            ::core::cmp::Ordering::Greater => {
+                writeln!(text, "- {:?}", expected[i_expected]).unwrap();
+                i_expected += 1;
+            }
+        }
+    }
+    Some(text)
+}
+
+/// Assert the taken and/or untaken ranges of a tree,
+/// panicking with a per-range diff on failure.
+///
+/// ```ignore
+/// assert_state!(tree, taken: [[0, 3]], untaken: [[4, 9]]);
+/// ```
+#[macro_export]
+macro_rules! assert_state {
+    ($tree:expr, taken: $taken:expr, untaken: $untaken:expr) => {
+        {
+            let tree = &$tree;
+            $crate::assert_state!(tree, taken: $taken);
+            $crate::assert_state!(tree, untaken: $untaken);
+        }
+    };
+    ($tree:expr, taken: $taken:expr) => {
+        if let Some(text) = $crate::test_util::ranges_diff(
+            "taken", &$tree.ranges_taken_as_vec(), &$taken,
+        ) {
+            panic!("{}", text);
+        }
+    };
+    ($tree:expr, untaken: $untaken:expr) => {
+        if let Some(text) = $crate::test_util::ranges_diff(
+            "untaken", &$tree.ranges_untaken_as_vec(), &$untaken,
+        ) {
+            panic!("{}", text);
+        }
+    };
+}
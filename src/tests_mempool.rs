@@ -1,23 +1,24 @@
 // Apache License, Version 2.0
 // (c) Campbell Barton, 2016
 
-use std::ptr;
+use alloc::vec::Vec;
 use mempool_elem::{
+    ElemId,
     MemPool,
     MemElemUtils,
 };
 
 struct TestElem {
     value: usize,
-    link: *mut TestElem,
+    link: ElemId,
 }
 
 impl MemElemUtils for TestElem {
-    fn free_ptr_get(&self) -> *mut TestElem {
-        return self.link;
+    fn free_id_get(&self) -> ElemId {
+        self.link
     }
-    fn free_ptr_set(&mut self, ptr: *mut TestElem) {
-        self.link = ptr;
+    fn free_id_set(&mut self, id: ElemId) {
+        self.link = id;
     }
 }
 
@@ -25,7 +26,7 @@ impl Default for TestElem {
     fn default() -> TestElem {
         TestElem {
             value: 0,
-            link: ptr::null_mut(),
+            link: ElemId::NULL,
         }
     }
 }
@@ -37,21 +38,136 @@ fn test_mempool() {
     let mut p: MemPool<TestElem> = MemPool::new(chunk_size);
 
     for _ in 0..2 {
-        let mut a = unsafe { &mut *p.alloc_elem_from(Default::default()) };
-        a.value = 0;
+        let mut a = p.alloc_elem_from(Default::default());
+        p[a].value = 0;
         for i in 1..total {
-            let a_next = p.alloc_elem_from(Default::default());
             let a_prev = a;
-            a = unsafe { &mut *a_next };
-            a.value = i;
-            a.link = a_prev;
+            a = p.alloc_elem_from(Default::default());
+            p[a].value = i;
+            p[a].link = a_prev;
         }
 
         for i in (0..total).rev() {
-            assert!(a.value == i);
-            let a_next = unsafe { &mut *a.link };
+            assert!(p[a].value == i);
+            let a_next = p[a].link;
             p.free_elem(a);
             a = a_next;
         }
+        assert!(a.is_null());
+        assert_eq!(p.free_len(), total);
+        assert_eq!(p.elems_len(), total);
+        assert!(p.capacity() >= total);
     }
 }
+
+#[test]
+fn test_mempool_shrink() {
+    let chunk_size = 4;
+    let mut p: MemPool<TestElem> = MemPool::new(chunk_size);
+    let elems: Vec<ElemId> = (0..16).map(|i| {
+        p.alloc_elem_from(TestElem { value: i, link: ElemId::NULL })
+    }).collect();
+    assert_eq!(p.elems_len(), 16);
+
+    // free a range in the middle & everything after the 9th element.
+    for (i, elem) in elems.iter().enumerate() {
+        if (4..8).contains(&i) || i >= 9 {
+            p.free_elem(*elem);
+        }
+    }
+    p.shrink();
+    assert_eq!(p.elems_len(), 9);
+    assert_eq!(p.capacity(), 9);
+    assert_eq!(p.free_len(), 4);
+    // elements in use aren't moved.
+    assert_eq!(p[elems[8]].value, 8);
+
+    // free elements are reused, then new elements added.
+    for _ in 0..5 {
+        p.alloc_elem_from(Default::default());
+    }
+    assert_eq!((p.elems_len(), p.free_len()), (10, 0));
+
+    p.shrink();
+    assert_eq!(p.capacity(), 10);
+}
+
+#[test]
+fn test_mempool_reserve() {
+    let chunk_size = 4;
+    let mut p: MemPool<TestElem> = MemPool::new(chunk_size);
+    p.alloc_elem_from(Default::default());
+    // the initial allocation has room.
+    p.reserve(3);
+    assert_eq!(p.capacity(), 4);
+
+    p.reserve(10);
+    assert!(p.capacity() >= 11);
+    let capacity = p.capacity();
+    for _ in 0..10 {
+        p.alloc_elem_from(Default::default());
+    }
+    assert_eq!(p.capacity(), capacity);
+
+    // freed elements count towards the reserved space.
+    let elem = p.alloc_elem_from(Default::default());
+    p.free_elem(elem);
+    let capacity = p.capacity();
+    p.reserve(capacity - p.elems_len() + 1);
+    assert_eq!(p.capacity(), capacity);
+}
+
+#[test]
+fn test_mempool_stable_ids() {
+    // ids stay valid as the pool grows, is reserved & shrunk.
+    let mut p: MemPool<TestElem> = MemPool::new(3);
+    let mut elems: Vec<ElemId> = vec![];
+    for i in 0..100 {
+        if i == 50 {
+            p.reserve(20);
+        }
+        elems.push(p.alloc_elem_from(TestElem { value: i, link: ElemId::NULL }));
+    }
+    for elem in &elems[10..20] {
+        p.free_elem(*elem);
+    }
+    p.shrink();
+    for (i, elem) in elems.iter().enumerate() {
+        if !(10..20).contains(&i) {
+            assert_eq!(p[*elem].value, i);
+        }
+    }
+}
+
+#[test]
+fn test_mempool_drop() {
+    use alloc::rc::Rc;
+
+    struct DropElem {
+        _value: Rc<()>,
+        link: ElemId,
+    }
+
+    impl MemElemUtils for DropElem {
+        fn free_id_get(&self) -> ElemId {
+            self.link
+        }
+        fn free_id_set(&mut self, id: ElemId) {
+            self.link = id;
+        }
+    }
+
+    let value = Rc::new(());
+    let mut p: MemPool<DropElem> = MemPool::new(4);
+    for _ in 0..10 {
+        p.alloc_elem_from(DropElem { _value: value.clone(), link: ElemId::NULL });
+    }
+    assert_eq!(Rc::strong_count(&value), 11);
+    p.clear();
+    assert_eq!(Rc::strong_count(&value), 1);
+    for _ in 0..10 {
+        p.alloc_elem_from(DropElem { _value: value.clone(), link: ElemId::NULL });
+    }
+    drop(p);
+    assert_eq!(Rc::strong_count(&value), 1);
+}
@@ -0,0 +1,125 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! All-or-nothing modification of a `RangeTree`, see `RangeTree::transaction`.
+
+use alloc::vec::Vec;
+
+use types::RType;
+use {
+    RangeOp,
+    RangeTree,
+    RangeTreeError,
+};
+use vec_util;
+
+/// Takes & releases which are kept by `commit` or undone by `rollback`
+/// (or when dropped without committing).
+///
+/// Changes are made to the tree as they're requested (so `take_any` can return a value),
+/// the tree is borrowed for the lifetime of the transaction so they're only visible once committed.
+///
+/// Releases are never deferred (see `RangeTree::set_release_deferred`),
+/// so they can be undone.
+pub struct Transaction<'a, TOrd: 'a + RType> {
+    tree: &'a mut RangeTree<TOrd>,
+    // the maximum bound before the transaction, growth is undone by rolling back.
+    value_max: TOrd,
+    // changes made so far, undone in reverse order.
+    ops: Vec<RangeOp<TOrd>>,
+}
+
+impl<'a, TOrd: RType> Transaction<'a, TOrd> {
+    pub(crate) fn new(
+        tree: &'a mut RangeTree<TOrd>,
+    ) -> Transaction<'a, TOrd> {
+        let value_max = tree.range[1];
+        Transaction {
+            tree,
+            value_max,
+            ops: vec![],
+        }
+    }
+
+    /// Check if the value is untaken (including changes made by this transaction).
+    pub fn has(
+        &self,
+        value: TOrd,
+    ) -> bool {
+        self.tree.has(value)
+    }
+
+    /// Take a value, returning an error when it's out of bounds or already taken.
+    pub fn try_take(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        self.tree.try_take(value)?;
+        vec_util::push(&mut self.ops, RangeOp::Take(value));
+        Ok(())
+    }
+
+    /// Take any value, see `RangeTree::take_any`.
+    pub fn take_any(
+        &mut self,
+    ) -> Option<TOrd> {
+        let value = self.tree.take_any()?;
+        vec_util::push(&mut self.ops, RangeOp::Take(value));
+        Some(value)
+    }
+
+    /// Release a value, returning an error when it's out of bounds or isn't taken.
+    pub fn try_release(
+        &mut self,
+        value: TOrd,
+    ) -> Result<(), RangeTreeError> {
+        if !self.tree.in_bounds(&value) {
+            return Err(RangeTreeError::OutOfBounds);
+        }
+        if !self.tree.find_node_from_value(&value).is_null() {
+            return Err(RangeTreeError::NotTaken);
+        }
+        self.tree.release_impl(value);
+        vec_util::push(&mut self.ops, RangeOp::Release(value));
+        Ok(())
+    }
+
+    /// Keep all changes made by the transaction.
+    pub fn commit(
+        mut self,
+    ) {
+        self.ops.clear();
+        self.value_max = self.tree.range[1];
+    }
+
+    /// Undo all changes made by the transaction (the same as dropping it).
+    pub fn rollback(
+        self,
+    ) {
+        // undone when dropped.
+    }
+
+    fn rollback_impl(
+        &mut self,
+    ) {
+        while let Some(op) = self.ops.pop() {
+            match op {
+                RangeOp::Take(value) => self.tree.release_impl(value),
+                RangeOp::Release(value) => self.tree.take(value),
+                _ => debug_assert!(false, "unsupported transaction operation"),
+            }
+        }
+        if self.tree.range[1] > self.value_max {
+            // values added by growth are all untaken again.
+            self.tree.shrink_max_impl(self.value_max);
+        }
+    }
+}
+
+impl<'a, TOrd: RType> Drop for Transaction<'a, TOrd> {
+    fn drop(
+        &mut self,
+    ) {
+        self.rollback_impl();
+    }
+}
@@ -0,0 +1,258 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Container growth & access used by the take/release paths.
+//!
+//! With the `no-panic` feature, vectors are grown using the allocator directly,
+//! aborting the process when memory can't be allocated (except for `try_reserve_exact`).
+//! `Vec::push` can panic on capacity overflow and `Vec::try_reserve` isn't known not to unwind,
+//! so neither can be used on paths which must not unwind.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Abort the process.
+///
+/// Unlike calls to `std::process::abort`,
+/// calls to an `extern "C"` function are known not to unwind.
+#[cfg(all(feature = "no-panic", feature = "std"))]
+extern "C" fn abort() -> ! {
+    ::std::process::abort();
+}
+
+/// Without `std`, panicking in a function which can't unwind aborts.
+#[cfg(all(feature = "no-panic", not(feature = "std")))]
+extern "C" fn abort() -> ! {
+    panic!("allocation failed");
+}
+
+#[cfg(feature = "no-panic")]
+mod grow {
+    use alloc::alloc;
+    use alloc::vec::Vec;
+    use core::ptr;
+
+    use super::abort;
+
+    /// Reallocate `v` to hold `capacity` items, returning false when memory can't be allocated.
+    pub fn try_grow_exact<T>(
+        v: &mut Vec<T>,
+        capacity: usize,
+    ) -> bool {
+        debug_assert!(capacity > v.len());
+        let layout = match alloc::Layout::array::<T>(capacity) {
+            Ok(layout) if layout.size() != 0 => layout,
+            _ => return false,
+        };
+        let data = unsafe { alloc::alloc(layout) } as *mut T;
+        if data.is_null() {
+            return false;
+        }
+        let len = v.len();
+        unsafe {
+            ptr::copy_nonoverlapping(v.as_ptr(), data, len);
+            // The items have been moved, only free the old memory.
+            v.set_len(0);
+            *v = Vec::from_raw_parts(data, len, capacity);
+        }
+        true
+    }
+
+    /// Reallocate `v` to hold `capacity` items.
+    pub fn grow_exact<T>(
+        v: &mut Vec<T>,
+        capacity: usize,
+    ) {
+        if !try_grow_exact(v, capacity) {
+            abort();
+        }
+    }
+
+    pub fn grow<T>(
+        v: &mut Vec<T>,
+    ) {
+        let capacity = match v.capacity().checked_mul(2) {
+            Some(capacity) => capacity.max(4),
+            None => abort(),
+        };
+        grow_exact(v, capacity);
+    }
+}
+
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn push<T>(
+    v: &mut Vec<T>,
+    item: T,
+) {
+    if v.len() == v.capacity() {
+        grow::grow(v);
+    }
+    // Checking the capacity lets the optimizer remove the panicking path from `push`.
+    if v.len() < v.capacity() {
+        v.push(item);
+    } else {
+        abort();
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn push<T>(
+    v: &mut Vec<T>,
+    item: T,
+) {
+    v.push(item);
+}
+
+/// Push to a queue which has capacity reserved for the new item,
+/// with the `no-panic` feature the item is ignored when there is no space.
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn push_back_reserved<T>(
+    v: &mut VecDeque<T>,
+    item: T,
+) {
+    debug_assert!(v.len() < v.capacity());
+    if v.len() < v.capacity() {
+        v.push_back(item);
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn push_back_reserved<T>(
+    v: &mut VecDeque<T>,
+    item: T,
+) {
+    v.push_back(item);
+}
+
+/// Create an empty vector with space for `capacity` items.
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn with_capacity<T>(
+    capacity: usize,
+) -> Vec<T> {
+    let mut v = Vec::new();
+    if capacity != 0 {
+        grow::grow_exact(&mut v, capacity);
+    }
+    v
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn with_capacity<T>(
+    capacity: usize,
+) -> Vec<T> {
+    Vec::with_capacity(capacity)
+}
+
+/// Reserve space for at least `additional` more items.
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn reserve_exact<T>(
+    v: &mut Vec<T>,
+    additional: usize,
+) {
+    if v.capacity() - v.len() < additional {
+        match v.len().checked_add(additional) {
+            Some(capacity) => grow::grow_exact(v, capacity),
+            None => abort(),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn reserve_exact<T>(
+    v: &mut Vec<T>,
+    additional: usize,
+) {
+    v.reserve_exact(additional);
+}
+
+/// Reserve space for at least `additional` more items,
+/// returning false when memory can't be allocated (never aborting).
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn try_reserve_exact<T>(
+    v: &mut Vec<T>,
+    additional: usize,
+) -> bool {
+    if v.capacity() - v.len() >= additional {
+        return true;
+    }
+    match v.len().checked_add(additional) {
+        Some(capacity) => grow::try_grow_exact(v, capacity),
+        None => false,
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn try_reserve_exact<T>(
+    v: &mut Vec<T>,
+    additional: usize,
+) -> bool {
+    v.try_reserve_exact(additional).is_ok()
+}
+
+/// Return the item at `index`,
+/// with the `no-panic` feature the process is aborted when out of range.
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn get<T>(
+    v: &[T],
+    index: usize,
+) -> &T {
+    match v.get(index) {
+        Some(item) => item,
+        None => abort(),
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn get<T>(
+    v: &[T],
+    index: usize,
+) -> &T {
+    &v[index]
+}
+
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn get_mut<T>(
+    v: &mut [T],
+    index: usize,
+) -> &mut T {
+    match v.get_mut(index) {
+        Some(item) => item,
+        None => abort(),
+    }
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn get_mut<T>(
+    v: &mut [T],
+    index: usize,
+) -> &mut T {
+    &mut v[index]
+}
+
+/// Called when more items are needed than can be indexed,
+/// with the `no-panic` feature the process is aborted.
+#[cfg(feature = "no-panic")]
+#[inline]
+pub fn capacity_overflow() -> ! {
+    abort();
+}
+
+#[cfg(not(feature = "no-panic"))]
+#[inline]
+pub fn capacity_overflow() -> ! {
+    panic!("capacity overflow");
+}
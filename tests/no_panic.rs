@@ -0,0 +1,118 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Check the core paths can't panic (feature `no-panic`).
+//!
+//! Each call is wrapped in a guard which references an undefined symbol when dropped,
+//! the guard is only dropped when unwinding, so linking fails unless the optimizer
+//! removes every path which may unwind.
+//! Debug builds contain assertions, so this is only checked with optimizations enabled:
+//! `cargo test --release --features no-panic`.
+//!
+//! The `diagnostics` feature records labels in a `BTreeMap` which may panic, so isn't checked.
+
+#![cfg(all(feature = "no-panic", not(feature = "diagnostics"), not(debug_assertions)))]
+
+extern crate rangetree;
+
+use rangetree::{
+    RangeTree,
+    RangeTreeError,
+    TransferError,
+};
+
+struct PanicGuard;
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        extern "C" {
+            #[link_name = "\n\nERROR: a `no-panic` function may panic\n\n"]
+            fn trigger() -> !;
+        }
+        unsafe { trigger() }
+    }
+}
+
+macro_rules! no_panic {
+    ($(fn $name:ident($($arg:ident: $arg_ty:ty),*) -> $ret:ty $body:block)*) => {
+        $(
+            #[inline(never)]
+            fn $name($($arg: $arg_ty),*) -> $ret {
+                let guard = PanicGuard;
+                let result = $body;
+                std::mem::forget(guard);
+                result
+            }
+        )*
+    }
+}
+
+no_panic! {
+    fn has(r: &RangeTree<u32>, value: u32) -> bool { r.has(value) }
+    fn try_take(r: &mut RangeTree<u32>, value: u32) -> Result<(), RangeTreeError> { r.try_take(value) }
+    fn try_release(r: &mut RangeTree<u32>, value: u32) -> Result<(), RangeTreeError> { r.try_release(value) }
+    fn take(r: &mut RangeTree<u32>, value: u32) -> () { r.take(value) }
+    fn take_if_available(r: &mut RangeTree<u32>, value: u32) -> bool { r.take_if_available(value) }
+    fn release(r: &mut RangeTree<u32>, value: u32) -> () { r.release(value) }
+    fn take_any(r: &mut RangeTree<u32>) -> Option<u32> { r.take_any() }
+    fn take_any_max(r: &mut RangeTree<u32>) -> Option<u32> { r.take_any_max() }
+    fn take_any_from(r: &mut RangeTree<u32>, hint: u32) -> Option<u32> { r.take_any_from(hint) }
+    fn release_any(r: &mut RangeTree<u32>) -> Option<u32> { r.release_any() }
+    fn flush(r: &mut RangeTree<u32>) -> () { r.flush() }
+    fn count_untaken(r: &RangeTree<u32>) -> u128 { r.count_untaken() }
+    fn count_taken(r: &RangeTree<u32>) -> u128 { r.count_taken() }
+    fn is_full(r: &RangeTree<u32>) -> bool { r.is_full() }
+    fn transfer(r: &mut RangeTree<u32>, value: u32, dest: &mut RangeTree<u32>) -> Result<(), TransferError> {
+        r.transfer(value, dest)
+    }
+    fn try_reserve(r: &mut RangeTree<u32>, additional: usize) -> Result<(), RangeTreeError> {
+        r.try_reserve(additional)
+    }
+}
+
+#[test]
+fn test_no_panic() {
+    let mut r = RangeTree::new([0, 99], false);
+    r.set_journal(true);
+    r.set_rate_history(Some(8));
+
+    assert_eq!(try_take(&mut r, 100), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(try_reserve(&mut r, 50), Ok(()));
+    assert_eq!(try_reserve(&mut r, usize::MAX), Err(RangeTreeError::OutOfMemory));
+    for i in 0..50 {
+        take(&mut r, i * 2);
+    }
+    // misuse is ignored.
+    take(&mut r, 0);
+    take(&mut r, 100);
+    release(&mut r, 1);
+    release(&mut r, 100);
+    assert_eq!(count_taken(&r), 50);
+    assert_eq!(try_take(&mut r, 10), Err(RangeTreeError::AlreadyTaken));
+    assert_eq!(try_release(&mut r, 11), Err(RangeTreeError::NotTaken));
+    assert!(has(&r, 11));
+    assert!(take_if_available(&mut r, 11));
+    assert!(!take_if_available(&mut r, 11));
+    assert_eq!(try_release(&mut r, 11), Ok(()));
+    assert_eq!(take_any(&mut r), Some(1));
+    assert_eq!(take_any_max(&mut r), Some(99));
+    assert_eq!(take_any_from(&mut r, 50), Some(51));
+    assert_eq!(count_taken(&r), 53);
+    assert_eq!(release_any(&mut r), Some(0));
+    assert_eq!(try_release(&mut r, 2), Ok(()));
+
+    r.set_release_deferred(Some(4));
+    release(&mut r, 4);
+    release(&mut r, 6);
+    assert!(!has(&r, 4));
+    flush(&mut r);
+    assert!(has(&r, 4) && has(&r, 6));
+    assert_eq!(count_untaken(&r), 51);
+
+    let mut dest = RangeTree::new([0, 99], false);
+    assert_eq!(transfer(&mut r, 8, &mut dest), Ok(()));
+    assert!(has(&r, 8) && !has(&dest, 8));
+
+    while take_any(&mut r).is_some() {}
+    assert!(is_full(&r));
+}
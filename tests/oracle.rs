@@ -0,0 +1,224 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+//! Model based tests: random operation sequences are applied to a tree and to a `BTreeSet`
+//! of taken values (the oracle), comparing the results of every operation & query.
+
+extern crate proptest;
+extern crate rangetree;
+
+use std::collections::BTreeSet;
+
+use proptest::prelude::*;
+use rangetree::{
+    RangeTree,
+    RangeTreeError,
+};
+
+#[derive(Debug, Clone)]
+enum Op {
+    TryTake(u8),
+    Retake(u8),
+    TryRelease(u8),
+    TakeAny,
+    TakeAnyMax,
+    TakeAnyFrom(u8),
+    ReleaseAny,
+    TakeN(usize),
+    ReleaseMany(Vec<u8>),
+    Invert,
+}
+
+// Values at (and next to) the type limits are chosen often since they're the values which may overflow.
+fn value() -> impl Strategy<Value=u8> {
+    prop_oneof![
+        Just(0u8),
+        Just(1u8),
+        Just(254u8),
+        Just(255u8),
+        any::<u8>(),
+    ]
+}
+
+fn op() -> impl Strategy<Value=Op> {
+    prop_oneof![
+        4 => value().prop_map(Op::TryTake),
+        2 => value().prop_map(Op::Retake),
+        4 => value().prop_map(Op::TryRelease),
+        2 => Just(Op::TakeAny),
+        2 => Just(Op::TakeAnyMax),
+        2 => value().prop_map(Op::TakeAnyFrom),
+        1 => Just(Op::ReleaseAny),
+        1 => (0..8usize).prop_map(Op::TakeN),
+        1 => proptest::collection::vec(value(), 0..8).prop_map(Op::ReleaseMany),
+        1 => Just(Op::Invert),
+    ]
+}
+
+fn check_queries(
+    r: &RangeTree<u8>,
+    bounds: [u8; 2],
+    taken: &BTreeSet<u8>,
+    probe: u8,
+) {
+    assert_eq!(r.validate(), Ok(()));
+    let untaken: Vec<u8> = (bounds[0]..=bounds[1]).filter(|v| !taken.contains(v)).collect();
+    assert_eq!(r.count_untaken(), untaken.len() as u128);
+    assert_eq!(r.count_taken(), taken.len() as u128);
+    let values: Vec<u8> = r.iter_untaken().flat_map(|range| range[0]..=range[1]).collect();
+    assert_eq!(values, untaken);
+    let values: Vec<u8> = r.iter_taken().flat_map(|range| range[0]..=range[1]).collect();
+    assert_eq!(values, taken.iter().cloned().collect::<Vec<u8>>());
+
+    assert_eq!(r.has(probe), untaken.contains(&probe));
+    assert_eq!(r.first_untaken_at_or_after(probe), untaken.iter().cloned().find(|v| *v >= probe));
+    assert_eq!(r.last_untaken_at_or_before(probe), untaken.iter().cloned().rev().find(|v| *v <= probe));
+    assert_eq!(r.first_taken_at_or_after(probe), taken.range(probe..).next().cloned());
+    assert_eq!(r.kth_untaken(probe as u128), untaken.get(probe as usize).cloned());
+    assert_eq!(r.rank_untaken(probe), untaken.iter().filter(|v| **v < probe).count() as u128);
+    assert_eq!(r.kth_taken(probe as u128), taken.iter().nth(probe as usize).cloned());
+    assert_eq!(r.rank_taken(probe), taken.range(..probe).count() as u128);
+    let range = [probe.saturating_sub(2), probe.saturating_add(2)];
+    assert_eq!(r.any_untaken_in(range), untaken.iter().any(|v| *v >= range[0] && *v <= range[1]));
+    assert_eq!(
+        r.count_untaken_in(range),
+        untaken.iter().filter(|v| **v >= range[0] && **v <= range[1]).count() as u128,
+    );
+    // the lowest of the largest untaken spans.
+    let mut span_max: Option<[u8; 2]> = None;
+    for range in r.iter_untaken() {
+        if span_max.is_none_or(|s| range[1] - range[0] > s[1] - s[0]) {
+            span_max = Some(range);
+        }
+    }
+    assert_eq!(r.max_contiguous_untaken(), span_max);
+    assert_eq!(
+        r.all_untaken_in(range),
+        range[0] >= bounds[0] && range[1] <= bounds[1] && (range[0]..=range[1]).all(|v| !taken.contains(&v)),
+    );
+}
+
+fn apply(
+    r: &mut RangeTree<u8>,
+    bounds: [u8; 2],
+    taken: &mut BTreeSet<u8>,
+    op: Op,
+) {
+    let in_bounds = |v: u8| v >= bounds[0] && v <= bounds[1];
+    let untaken_first = |taken: &BTreeSet<u8>| (bounds[0]..=bounds[1]).find(|v| !taken.contains(v));
+    match op {
+        Op::TryTake(v) => {
+            let expect = if !in_bounds(v) {
+                Err(RangeTreeError::OutOfBounds)
+            } else if taken.contains(&v) {
+                Err(RangeTreeError::AlreadyTaken)
+            } else {
+                Ok(())
+            };
+            assert_eq!(r.try_take(v), expect);
+            if expect.is_ok() {
+                taken.insert(v);
+            }
+        }
+        Op::Retake(v) => {
+            if in_bounds(v) {
+                assert_eq!(r.retake(v), taken.insert(v));
+            }
+        }
+        Op::TryRelease(v) => {
+            let expect = if !in_bounds(v) {
+                Err(RangeTreeError::OutOfBounds)
+            } else if !taken.contains(&v) {
+                Err(RangeTreeError::NotTaken)
+            } else {
+                Ok(())
+            };
+            assert_eq!(r.try_release(v), expect);
+            if expect.is_ok() {
+                taken.remove(&v);
+            }
+        }
+        Op::TakeAny => {
+            let expect = untaken_first(taken);
+            assert_eq!(r.take_any(), expect);
+            taken.extend(expect);
+        }
+        Op::TakeAnyMax => {
+            let expect = (bounds[0]..=bounds[1]).rev().find(|v| !taken.contains(v));
+            assert_eq!(r.take_any_max(), expect);
+            taken.extend(expect);
+        }
+        Op::TakeAnyFrom(v) => {
+            let expect = (v..=bounds[1])
+                .find(|v| in_bounds(*v) && !taken.contains(v))
+                .or_else(|| untaken_first(taken));
+            assert_eq!(r.take_any_from(v), expect);
+            taken.extend(expect);
+        }
+        Op::ReleaseAny => {
+            let expect = taken.iter().next().cloned();
+            assert_eq!(r.release_any(), expect);
+            if let Some(v) = expect {
+                taken.remove(&v);
+            }
+        }
+        Op::TakeN(n) => {
+            let expect: Vec<u8> = (bounds[0]..=bounds[1]).filter(|v| !taken.contains(v)).take(n).collect();
+            assert_eq!(r.take_n(n), expect);
+            taken.extend(expect);
+        }
+        Op::ReleaseMany(values) => {
+            // only values which are taken, each once.
+            let values: Vec<u8> = values.into_iter().filter(|v| taken.contains(v)).collect::<BTreeSet<u8>>()
+                .into_iter().rev().collect();
+            r.release_many(&values);
+            for v in values {
+                taken.remove(&v);
+            }
+        }
+        Op::Invert => {
+            r.invert();
+            *taken = (bounds[0]..=bounds[1]).filter(|v| !taken.contains(v)).collect();
+        }
+    }
+}
+
+fn run(
+    bounds: [u8; 2],
+    full: bool,
+    ops: Vec<Op>,
+    probes: Vec<u8>,
+) {
+    let mut r: RangeTree<u8> = RangeTree::new(bounds, full);
+    let mut taken: BTreeSet<u8> = if full { (bounds[0]..=bounds[1]).collect() } else { BTreeSet::new() };
+    for (op, probe) in ops.into_iter().zip(probes.into_iter().cycle()) {
+        apply(&mut r, bounds, &mut taken, op);
+        check_queries(&r, bounds, &taken, probe);
+    }
+    // rebuilding from the taken ranges gives the same tree.
+    assert_eq!(RangeTree::from_ranges_taken(bounds, &r.ranges_taken_as_vec()).unwrap(), r);
+}
+
+proptest! {
+    // Miri is slow, a few cases still cover the node pool code.
+    #![proptest_config(ProptestConfig::with_cases(if cfg!(miri) { 4 } else { 256 }))]
+
+    #[test]
+    fn oracle_full_domain(
+        full in any::<bool>(),
+        ops in proptest::collection::vec(op(), 1..200),
+        probes in proptest::collection::vec(value(), 1..16),
+    ) {
+        run([0, 255], full, ops, probes);
+    }
+
+    #[test]
+    fn oracle_bounded(
+        bounds in (value(), value()).prop_map(|(a, b)| if a <= b { [a, b] } else { [b, a] }),
+        full in any::<bool>(),
+        ops in proptest::collection::vec(op(), 1..200),
+        probes in proptest::collection::vec(value(), 1..16),
+    ) {
+        run(bounds, full, ops, probes);
+    }
+}
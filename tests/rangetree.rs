@@ -2,6 +2,10 @@
 // (c) Campbell Barton, 2016
 
 extern crate rangetree;
+#[cfg(feature = "rand")]
+extern crate rand_core;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
 use rangetree::RangeTree;
 
@@ -70,6 +74,18 @@ fn test_retake() {
     // println!("{:?}", r.ranges_as_vec());
 }
 
+#[test]
+fn test_take_if_available() {
+    let mut r: RangeTree<u8> = RangeTree::new([1, 10], false);
+    assert!(r.take_if_available(5));
+    assert!(!r.take_if_available(5));
+    assert!(!r.take_if_available(0));
+    assert!(!r.take_if_available(11));
+    assert!(r.take_if_available(10));
+    assert_eq!(r.ranges_taken_as_vec(), [[5, 5], [10, 10]]);
+    assert_eq!(r.validate(), Ok(()));
+}
+
 #[test]
 fn test_complex() {
     let mut r: RangeTree<i32> = RangeTree::new([-10, 11], false);
@@ -113,3 +129,2718 @@ fn test_complex() {
         // r.print();
     }
 }
+
+#[test]
+fn test_validate_incremental() {
+    use rangetree::{
+        ValidatorState,
+        ValidationProgress,
+    };
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..50 {
+        r.take(i * 2);
+    }
+
+    let mut state = ValidatorState::new();
+    let mut calls = 0;
+    loop {
+        calls += 1;
+        match r.validate_incremental(&mut state, 8) {
+            ValidationProgress::Incomplete => {},
+            ValidationProgress::Complete => break,
+            ValidationProgress::Invalid { span, reason } => {
+                panic!("{:?}: {}", span, reason);
+            },
+        }
+        // modify between calls
+        if calls == 2 {
+            r.release(50);
+            r.take(51);
+        }
+    }
+    assert_eq!(calls, 7);
+
+    // single call with a large budget
+    assert_eq!(r.validate_incremental(&mut state, 1000),
+               ValidationProgress::Complete);
+}
+
+#[test]
+fn test_delta_since() {
+    use rangetree::RangeOp;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    let mut replica: RangeTree<i32> = RangeTree::new([0, 99], false);
+    assert_eq!(r.delta_since(0), None);
+
+    r.set_journal(true);
+    let mut seq = r.seq();
+    for _ in 0..10 {
+        r.take_any();
+    }
+    r.release(4);
+    r.take(50);
+
+    let (ops, seq_next) = r.delta_since(seq).unwrap();
+    assert_eq!(ops.len(), 12);
+    assert_eq!(ops[10], RangeOp::Release(4));
+    replica.apply_delta(&ops);
+    assert_eq!(replica.ranges_untaken_as_vec(), r.ranges_untaken_as_vec());
+    seq = seq_next;
+
+    // nothing changed
+    assert_eq!(r.delta_since(seq), Some((vec![], seq)));
+
+    r.clear(false);
+    r.take(3);
+    let (ops, seq_next) = r.delta_since(seq).unwrap();
+    assert_eq!(ops, [RangeOp::Clear(false), RangeOp::Take(3)]);
+    replica.apply_delta(&ops);
+    assert_eq!(replica.ranges_untaken_as_vec(), r.ranges_untaken_as_vec());
+
+    // older operations are no longer available
+    r.journal_discard_before(seq_next);
+    assert_eq!(r.delta_since(seq), None);
+    assert_eq!(r.delta_since(seq_next), Some((vec![], seq_next)));
+}
+
+#[test]
+fn test_take_any_contiguous() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 19], false);
+    for i in &[2, 3, 9] {
+        r.take(*i);
+    }
+    assert_eq!(r.take_any_contiguous(0), None);
+    assert_eq!(r.take_any_contiguous(5), Some(4));
+    assert_eq!(r.take_any_contiguous(2), Some(0));
+    assert_eq!(r.take_any_contiguous(11), None);
+    assert_eq!(r.take_any_contiguous(4), Some(10));
+    assert_eq!(r.ranges_untaken_as_vec(), [[14, 19]]);
+    assert_eq!(r.take_any_contiguous(6), Some(14));
+    assert!(r.is_full());
+}
+
+#[test]
+fn test_singleton() {
+    let mut r: RangeTree<i32> = RangeTree::singleton(-4, false);
+    for _ in 0..2 {
+        assert!(r.is_empty());
+        assert!(!r.is_full());
+        assert!(r.has(-4));
+        assert_eq!(r.ranges_untaken_as_vec(), [[-4, -4]]);
+        assert!(r.ranges_taken_as_vec().is_empty());
+
+        assert_eq!(r.take_any(), Some(-4));
+        assert_eq!(r.take_any(), None);
+        assert!(!r.is_empty());
+        assert!(r.is_full());
+        assert!(!r.has(-4));
+        assert!(!r.retake(-4));
+        assert!(r.ranges_untaken_as_vec().is_empty());
+        assert_eq!(r.ranges_taken_as_vec(), [[-4, -4]]);
+
+        r.release(-4);
+        assert!(r.retake(-4));
+        r.release(-4);
+        assert_eq!(r.take_any_contiguous(2), None);
+        assert_eq!(r.take_any_contiguous(1), Some(-4));
+        r.clear(false);
+    }
+
+    let r: RangeTree<u8> = RangeTree::singleton(255, true);
+    assert!(r.is_full());
+    assert_eq!(r.ranges_taken_as_vec(), [[255, 255]]);
+}
+
+#[test]
+fn test_iter_untaken() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.iter_untaken().collect::<Vec<_>>(), [[0, 9]]);
+    for i in &[0, 3, 4, 8] {
+        r.take(*i);
+    }
+    let mut iter = r.iter_untaken();
+    assert_eq!(iter.next(), Some([1, 2]));
+    assert_eq!(iter.next(), Some([5, 7]));
+    assert_eq!(iter.next(), Some([9, 9]));
+    assert_eq!(iter.next(), None);
+    assert_eq!(r.iter_untaken().find(|range| range[1] - range[0] >= 2), Some([5, 7]));
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    assert_eq!(r.iter_untaken().next(), None);
+    r.release(5);
+    assert_eq!(r.iter_untaken().collect::<Vec<_>>(), [[5, 5]]);
+}
+
+#[test]
+fn test_iter_taken() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.iter_taken().next(), None);
+    for i in &[0, 3, 4, 9] {
+        r.take(*i);
+    }
+    let mut iter = r.iter_taken();
+    assert_eq!(iter.next(), Some([0, 0]));
+    assert_eq!(iter.next(), Some([3, 4]));
+    assert_eq!(iter.next(), Some([9, 9]));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next(), None);
+
+    let mut r: RangeTree<u8> = RangeTree::new([0, 255], true);
+    assert_eq!(r.iter_taken().collect::<Vec<_>>(), [[0, 255]]);
+    r.release(255);
+    r.release(0);
+    assert_eq!(r.iter_taken().collect::<Vec<_>>(), [[1, 254]]);
+}
+
+#[test]
+fn test_iter_untaken_coalesced() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 19], false);
+    for i in &[2, 5, 6, 7, 8, 10, 19] {
+        r.take(*i);
+    }
+    assert_eq!(r.iter_untaken_coalesced(|gap| gap[1] - gap[0] < 2).collect::<Vec<_>>(),
+               [[0, 4], [9, 18]]);
+    assert_eq!(r.iter_untaken_coalesced(|_| false).collect::<Vec<_>>(),
+               r.ranges_untaken_as_vec());
+    assert_eq!(r.iter_untaken_coalesced(|_| true).collect::<Vec<_>>(),
+               [[0, 18]]);
+    assert_eq!(r.iter_untaken_spans_coalesced(|gap| gap[1] - gap[0] < 2).collect::<Vec<_>>(),
+               [[0, 4], [9, 18]]);
+}
+
+#[test]
+fn test_try_take_release() {
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.try_release(3), Err(RangeTreeError::NotTaken));
+    assert_eq!(r.try_take(3), Ok(()));
+    assert_eq!(r.try_take(3), Err(RangeTreeError::AlreadyTaken));
+    assert_eq!(r.ranges_taken_as_vec(), [[3, 3]]);
+    assert_eq!(r.try_release(3), Ok(()));
+    assert_eq!(r.try_release(3), Err(RangeTreeError::NotTaken));
+    assert!(r.is_empty());
+
+    // values pending from deferred releasing aren't taken.
+    r.set_release_deferred(Some(16));
+    r.take(5);
+    assert_eq!(r.try_release(5), Ok(()));
+    assert_eq!(r.try_release(5), Err(RangeTreeError::NotTaken));
+    r.flush();
+    assert!(r.is_empty());
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_find_run() {
+    let mut r: RangeTree<i32> = RangeTree::new([-10, 20], false);
+    for i in &[-8, 3, 9] {
+        r.take(*i);
+    }
+    // free: [-10, -9], [-7, 2], [4, 8], [10, 20]
+    assert_eq!(r.find_run(1, 1), Some(-10));
+    assert_eq!(r.find_run(2, 4), Some(-4));
+    assert_eq!(r.find_run(4, 4), Some(-4));
+    assert_eq!(r.find_run(7, 4), Some(-4));
+    assert_eq!(r.find_run(8, 4), Some(12));
+    assert_eq!(r.find_run(5, 5), Some(-5));
+    assert_eq!(r.find_run(5, 8), Some(16));
+    assert_eq!(r.find_run(11, 1), Some(10));
+    assert_eq!(r.find_run(11, 2), Some(10));
+    assert_eq!(r.find_run(11, 4), None);
+    assert!(r.has_run(10, 10));
+    assert!(!r.has_run(12, 1));
+    assert!(!r.has_run(0, 1));
+    assert!(!r.has_run(1, 0));
+    // read-only
+    assert_eq!(r.ranges_taken_as_vec(), [[-8, -8], [3, 3], [9, 9]]);
+
+    // wide spans with negative values don't overflow.
+    let r: RangeTree<i8> = RangeTree::new([-128, 127], false);
+    assert_eq!(r.find_run(1, 100), Some(-100));
+    assert_eq!(r.find_run(127, 127), Some(-127));
+    assert_eq!(r.find_run(127, 100), Some(-100));
+    assert_eq!(r.find_run(100, 100), Some(-100));
+    let r: RangeTree<u8> = RangeTree::new([0, 255], false);
+    assert_eq!(r.find_run(255, 255), Some(0));
+    assert_eq!(r.find_run(1, 255), Some(0));
+}
+
+#[test]
+fn test_take_aligned() {
+    use rangetree::Backend;
+
+    for backend in &[Backend::RbTree, Backend::List] {
+        let mut r: RangeTree<i32> = RangeTree::new([-10, 20], false);
+        r.set_backend(*backend);
+        for i in &[-8, 3, 9] {
+            r.take(*i);
+        }
+        // free: [-10, -9], [-7, 2], [4, 8], [10, 20]
+        assert_eq!(r.take_aligned(4, 4), Some(-4));
+        assert_eq!(r.take_aligned(4, 4), Some(4));
+        assert_eq!(r.take_aligned(4, 4), Some(12));
+        assert_eq!(r.take_aligned(4, 4), Some(16));
+        assert_eq!(r.take_aligned(4, 4), None);
+        assert_eq!(r.take_aligned(3, 1), Some(-7));
+        assert_eq!(r.take_aligned(0, 1), None);
+        assert_eq!(r.take_aligned(1, 0), None);
+        assert_eq!(r.ranges_taken_as_vec(), [[-8, -1], [3, 7], [9, 9], [12, 19]]);
+        assert_eq!(r.validate(), Ok(()));
+    }
+
+    let mut r: RangeTree<i8> = RangeTree::new([-128, 127], false);
+    assert_eq!(r.take_aligned(127, 100), Some(-100));
+    assert_eq!(r.take_aligned(100, 100), None);
+    assert_eq!(r.take_aligned(27, 100), Some(100));
+    assert_eq!(r.ranges_taken_as_vec(), [[-100, 26], [100, 126]]);
+}
+
+#[test]
+fn test_out_of_bounds() {
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.bounds(), [0, 9]);
+    assert!(!r.has(-1));
+    assert!(!r.has(10));
+    assert!(!r.retake(10));
+    assert_eq!(r.try_take(-1), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.try_take(10), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.try_release(10), Err(RangeTreeError::OutOfBounds));
+    assert!(r.is_empty());
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn test_leak_report() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    assert!(r.leak_report().is_empty());
+    for _ in 0..4 {
+        r.take_any_labeled("mesh");
+    }
+    r.take_labeled(50, "undo");
+    r.take(60);
+    r.release(1);
+    r.release(50);
+    assert_eq!(r.leak_report(), [("mesh", 3)]);
+    r.take_any_labeled("undo");
+    assert_eq!(r.leak_report(), [("mesh", 3), ("undo", 1)]);
+    r.clear(false);
+    assert!(r.leak_report().is_empty());
+}
+
+#[test]
+fn test_growth() {
+    use rangetree::Growth;
+
+    let mut r: RangeTree<u8> = RangeTree::new([0, 3], false);
+    r.set_growth(Some(Growth { increment: 4, limit: 9 }));
+    r.set_journal(true);
+    for i in 0..10 {
+        assert_eq!(r.take_any(), Some(i));
+    }
+    assert_eq!(r.bounds(), [0, 9]);
+    assert_eq!(r.take_any(), None);
+    r.release(4);
+    assert_eq!(r.take_any(), Some(4));
+
+    // growth is replayed by the journal
+    let mut replica: RangeTree<u8> = RangeTree::new([0, 3], false);
+    replica.apply_delta(&r.delta_since(0).unwrap().0);
+    assert_eq!(replica.bounds(), [0, 9]);
+    assert!(replica.is_full());
+
+    r.set_growth(None);
+    r.release(9);
+    r.take_any();
+    assert_eq!(r.take_any(), None);
+
+    // take_any_max grows the same way.
+    let mut r: RangeTree<u8> = RangeTree::new([0, 3], true);
+    r.set_growth(Some(Growth { increment: 4, limit: 9 }));
+    assert_eq!(r.take_any_max(), Some(7));
+    assert_eq!(r.bounds(), [0, 7]);
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_release_any() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    for i in 0..10 {
+        assert_eq!(r.release_any(), Some(i));
+    }
+    assert_eq!(r.release_any(), None);
+    assert!(r.is_empty());
+
+    for i in &[7, 2, 5] {
+        r.take(*i);
+    }
+    assert_eq!(r.release_any(), Some(2));
+    assert_eq!(r.release_any(), Some(5));
+    assert_eq!(r.release_any(), Some(7));
+    assert_eq!(r.release_any(), None);
+
+    // released immediately with deferred releasing.
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    r.set_release_deferred(Some(16));
+    r.release(3);
+    assert_eq!(r.release_any(), Some(0));
+    assert!(r.has(0) && r.has(3));
+    assert_eq!(r.count_untaken(), 2);
+}
+
+#[test]
+fn test_count() {
+    let mut r: RangeTree<i8> = RangeTree::new([-128, 127], false);
+    assert_eq!(r.count_untaken(), 256);
+    assert_eq!(r.count_taken(), 0);
+    for _ in 0..10 {
+        r.take_any();
+    }
+    r.take(0);
+    r.take_any_contiguous(5);
+    assert_eq!(r.count_taken(), 16);
+    assert_eq!(r.count_untaken(), 240);
+    r.release(-128);
+    r.release(0);
+    assert_eq!(r.count_taken(), 14);
+    assert_eq!(r.count_untaken(), 242);
+    r.clear(true);
+    assert_eq!(r.count_taken(), 256);
+    assert_eq!(r.count_untaken(), 0);
+    r.clear(false);
+    assert_eq!(r.count_untaken(), 256);
+
+    let r: RangeTree<u64> = RangeTree::new([0, u64::MAX - 1], false);
+    assert_eq!(r.count_untaken(), u64::MAX as u128);
+}
+
+#[test]
+fn test_sub_allocator() {
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    r.take(12);
+    {
+        let mut sub = r.sub_allocator([10, 19]).unwrap();
+        assert_eq!(sub.bounds(), [10, 19]);
+        assert_eq!(sub.take_any(), Some(10));
+        assert_eq!(sub.take_any(), Some(11));
+        assert_eq!(sub.take_any(), Some(13));
+        assert!(!sub.has(9));
+        assert!(!sub.retake(20));
+        assert_eq!(sub.try_take(20), Err(RangeTreeError::OutOfBounds));
+        assert_eq!(sub.try_take(12), Err(RangeTreeError::AlreadyTaken));
+        sub.take(19);
+        sub.release(11);
+        assert_eq!(sub.iter_untaken().collect::<Vec<_>>(), [[11, 11], [14, 18]]);
+        for _ in 0..6 {
+            assert!(sub.take_any().is_some());
+        }
+        assert_eq!(sub.take_any(), None);
+        assert_eq!(sub.try_release(9), Err(RangeTreeError::OutOfBounds));
+    }
+    assert_eq!(r.ranges_taken_as_vec(), [[10, 19]]);
+}
+
+#[test]
+fn test_count_ranges_untaken() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.count_ranges_untaken(), 1);
+    for i in &[2, 4, 6] {
+        r.take(*i);
+    }
+    assert_eq!(r.count_ranges_untaken(), 4);
+    r.take_any_contiguous(2);
+    assert_eq!(r.count_ranges_untaken(), 3);
+    r.release(4);
+    assert_eq!(r.count_ranges_untaken(), 2);
+    assert_eq!(r.count_ranges_untaken(), r.ranges_untaken_as_vec().len());
+    assert_eq!(r.spans_untaken(), 2);
+    r.clear(true);
+    assert_eq!(r.count_ranges_untaken(), 0);
+}
+
+#[test]
+fn test_release_deferred() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], true);
+    r.set_journal(true);
+    r.set_release_deferred(Some(8));
+    for i in &[5, 3, 4, 10, 4, 20, 21] {
+        r.release(*i);
+    }
+    // still pending
+    assert!(r.is_full());
+    r.flush();
+    assert_eq!(r.ranges_untaken_as_vec(), [[3, 5], [10, 10], [20, 21]]);
+    assert_eq!(r.count_untaken(), 6);
+
+    // reaching the limit flushes
+    for i in 30..38 {
+        r.release(i);
+    }
+    assert_eq!(r.ranges_untaken_as_vec(), [[3, 5], [10, 10], [20, 21], [30, 37]]);
+
+    r.release(50);
+    r.set_release_deferred(None);
+    assert!(r.has(50));
+    r.release(51);
+    assert!(r.has(51));
+
+    let mut replica: RangeTree<i32> = RangeTree::new([0, 99], true);
+    replica.apply_delta(&r.delta_since(0).unwrap().0);
+    assert_eq!(replica.ranges_untaken_as_vec(), r.ranges_untaken_as_vec());
+    assert_eq!(replica.count_untaken(), r.count_untaken());
+}
+
+#[test]
+fn test_cursor() {
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 20], false);
+    for i in &[2, 6, 10] {
+        r.take(*i);
+    }
+    // take the first value of each untaken range while scanning.
+    let mut cursor = r.cursor_untaken();
+    let mut found = vec![];
+    loop {
+        match r.cursor_next(&mut cursor) {
+            Ok(Some(range)) => {
+                found.push(range);
+                r.take(range[0]);
+                assert_eq!(r.cursor_next(&mut cursor), Err(RangeTreeError::StaleCursor));
+                r.cursor_resync(&mut cursor);
+            }
+            Ok(None) => break,
+            Err(_) => unreachable!(),
+        }
+    }
+    assert_eq!(found, [[0, 1], [3, 5], [7, 9], [11, 20]]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[1, 1], [4, 5], [8, 9], [12, 20]]);
+
+    // releasing a value merges it into the range behind the cursor.
+    let mut cursor = r.cursor_untaken();
+    assert_eq!(r.cursor_next(&mut cursor), Ok(Some([1, 1])));
+    r.release(2);
+    r.cursor_resync(&mut cursor);
+    assert_eq!(r.cursor_next(&mut cursor), Ok(Some([2, 2])));
+    assert_eq!(r.cursor_next(&mut cursor), Ok(Some([4, 5])));
+}
+
+#[test]
+fn test_slice() {
+    use rangetree::Backend;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..20 {
+        r.take(i * 5);
+    }
+    for backend in &[Backend::RbTree, Backend::List] {
+        r.set_backend(*backend);
+        let window = r.slice([8, 21]).unwrap();
+        assert_eq!(window.bounds(), [8, 21]);
+        assert_eq!(window.iter_untaken().collect::<Vec<_>>(), [[8, 9], [11, 14], [16, 19], [21, 21]]);
+        assert_eq!(window.iter_untaken().next_back(), Some([21, 21]));
+        assert_eq!(window.count_untaken(), 11);
+        assert_eq!(window.first_untaken(), Some(8));
+        assert!(window.has(8) && !window.has(10) && !window.has(7));
+        // windows starting, ending or entirely on taken values.
+        assert_eq!(r.slice([10, 20]).unwrap().count_untaken(), 8);
+        assert_eq!(r.slice([10, 10]).unwrap().count_untaken(), 0);
+        assert_eq!(r.slice([10, 10]).unwrap().first_untaken(), None);
+        assert_eq!(r.slice([10, 10]).unwrap().iter_untaken().count(), 0);
+        // windows outside the bounds.
+        assert_eq!(r.slice([-10, 3]).unwrap().iter_untaken().collect::<Vec<_>>(), [[1, 3]]);
+        assert_eq!(r.slice([-10, 3]).unwrap().count_untaken(), 3);
+        assert_eq!(r.slice([97, 200]).unwrap().count_untaken(), 3);
+        assert_eq!(r.slice([200, 300]).unwrap().count_untaken(), 0);
+    }
+    // reversed windows.
+    assert!(r.slice([21, 8]).is_none());
+}
+
+#[test]
+fn test_count_untaken_in() {
+    let mut r: RangeTree<u64> = RangeTree::new_full_domain(false);
+    r.take(10);
+    r.take(1 << 40);
+    assert_eq!(r.count_untaken_in([0, 99]), 99);
+    assert_eq!(r.count_untaken_in([10, 10]), 0);
+    assert_eq!(r.count_untaken_in([99, 0]), 0);
+    assert_eq!(r.count_untaken_in([0, 1 << 41]), (1 << 41) - 1);
+    assert_eq!(r.count_untaken_in([0, u64::MAX]), r.count_untaken());
+}
+
+#[test]
+fn test_max_contiguous_untaken() {
+    use rangetree::{Backend, Fit};
+
+    for backend in &[Backend::RbTree, Backend::List] {
+        let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+        r.set_backend(*backend);
+        assert_eq!(r.max_contiguous_untaken(), Some([0, 99]));
+        for i in &[5, 8, 20, 40, 60] {
+            r.take(*i);
+        }
+        // the lowest of [21, 39], [41, 59] & [61, 99].
+        assert_eq!(r.max_contiguous_untaken(), Some([61, 99]));
+        r.take(80);
+        assert_eq!(r.max_contiguous_untaken(), Some([21, 39]));
+        assert_eq!(r.take_any_contiguous_fit(19, Fit::Worst), Some(21));
+        assert_eq!(r.take_any_contiguous_fit(19, Fit::First), Some(41));
+        assert_eq!(r.take_any_contiguous_fit(20, Fit::First), None);
+        assert_eq!(r.max_contiguous_untaken(), Some([61, 79]));
+        assert_eq!(r.validate(), Ok(()));
+
+        r.take_all_in([0, 99]);
+        assert_eq!(r.max_contiguous_untaken(), None);
+    }
+}
+
+#[test]
+fn test_take_all_in() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..20 {
+        r.take(i * 5);
+    }
+    assert_eq!(r.take_all_in([8, 21]), [[8, 9], [11, 14], [16, 19], [21, 21]]);
+    assert_eq!(r.slice([8, 21]).unwrap().count_untaken(), 0);
+    assert_eq!(r.count_untaken(), 80 - 11);
+    // nothing left to take.
+    assert!(r.take_all_in([8, 21]).is_empty());
+    assert!(r.take_all_in([21, 8]).is_empty());
+    // windows outside the bounds.
+    assert_eq!(r.take_all_in([-10, 3]), [[1, 3]]);
+    assert_eq!(r.take_all_in([96, 200]), [[96, 99]]);
+    assert_eq!(r.validate(), Ok(()));
+
+    // pending values are taken.
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    r.set_release_deferred(Some(100));
+    r.release(5);
+    assert_eq!(r.take_all_in([0, 9]), [[5, 5]]);
+    r.flush();
+    assert!(r.is_full());
+}
+
+#[test]
+fn test_take_exact_range_or_suggest() {
+    use rangetree::RangeConflict;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in &[10, 12, 30, 50] {
+        r.take(*i);
+    }
+    assert_eq!(r.take_exact_range_or_suggest([11, 11]), Ok(()));
+    assert_eq!(
+        r.take_exact_range_or_suggest([9, 15]),
+        Err(RangeConflict { conflicts: vec![[10, 12]], suggestion: Some([13, 19]) }),
+    );
+    // equally near, the lower is used.
+    assert_eq!(
+        r.take_exact_range_or_suggest([8, 14]),
+        Err(RangeConflict { conflicts: vec![[10, 12]], suggestion: Some([3, 9]) }),
+    );
+    // the lower span is nearer.
+    assert_eq!(
+        r.take_exact_range_or_suggest([25, 34]),
+        Err(RangeConflict { conflicts: vec![[30, 30]], suggestion: Some([20, 29]) }),
+    );
+    assert_eq!(
+        r.take_exact_range_or_suggest([28, 37]),
+        Err(RangeConflict { conflicts: vec![[30, 30]], suggestion: Some([31, 40]) }),
+    );
+    // values outside the bounds conflict.
+    assert_eq!(
+        r.take_exact_range_or_suggest([95, 104]),
+        Err(RangeConflict { conflicts: vec![[100, 104]], suggestion: Some([90, 99]) }),
+    );
+    assert_eq!(
+        r.take_exact_range_or_suggest([0, 60]),
+        Err(RangeConflict { conflicts: vec![[10, 12], [30, 30], [50, 50]], suggestion: None }),
+    );
+    // reversed ranges are rejected.
+    assert_eq!(
+        r.take_exact_range_or_suggest([20, 15]),
+        Err(RangeConflict { conflicts: vec![], suggestion: None }),
+    );
+    assert_eq!(r.ranges_taken_as_vec(), [[10, 12], [30, 30], [50, 50]]);
+    assert_eq!(r.take_exact_range_or_suggest([51, 99]), Ok(()));
+    assert_eq!(r.ranges_taken_as_vec(), [[10, 12], [30, 30], [50, 99]]);
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_reserve_range() {
+    use std::time::{Duration, Instant};
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 19], false);
+    r.take(5);
+    assert_eq!(r.reserve_range([4, 8], None), Err(RangeTreeError::AlreadyTaken));
+    assert_eq!(r.reserve_range([15, 20], None), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.reserve_range([8, 6], None), Err(RangeTreeError::InvalidRange));
+
+    // reserved values are excluded from take_any and can't be released directly.
+    let a = r.reserve_range([0, 3], None).unwrap();
+    assert!(r.is_reserved(2) && !r.is_reserved(5));
+    assert_eq!(r.take_any(), Some(4));
+    assert_eq!(r.try_release(2), Err(RangeTreeError::Reserved));
+    assert_eq!(r.try_release(4), Ok(()));
+    assert_eq!(r.cancel_reservation(a), Ok([0, 3]));
+    assert_eq!(r.cancel_reservation(a), Err(RangeTreeError::Expired));
+    assert_eq!(r.ranges_taken_as_vec(), [[5, 5]]);
+
+    let b = r.reserve_range([6, 9], Some(Instant::now() + Duration::from_secs(3600))).unwrap();
+    assert_eq!(r.commit_reservation(b), Ok([6, 9]));
+    assert_eq!(r.commit_reservation(b), Err(RangeTreeError::Expired));
+    assert!(!r.is_reserved(6));
+    assert_eq!(r.try_release(6), Ok(()));
+
+    // expired reservations are released by take_any.
+    let c = r.reserve_range([0, 4], Some(Instant::now())).unwrap();
+    // with `no-panic` only `reap_reservations` & `commit_reservation` check expiry.
+    #[cfg(feature = "no-panic")]
+    r.reap_reservations();
+    assert_eq!(r.take_any(), Some(0));
+    assert!(!r.is_reserved(1));
+    assert_eq!(r.commit_reservation(c), Err(RangeTreeError::Expired));
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [5, 5], [7, 9]]);
+    let d = r.reserve_range([10, 19], Some(Instant::now())).unwrap();
+    assert_eq!(r.reap_reservations(), 1);
+    assert_eq!(r.cancel_reservation(d), Err(RangeTreeError::Expired));
+
+    // clearing removes reservations.
+    let e = r.reserve_range([10, 19], None).unwrap();
+    r.clear(false);
+    assert!(!r.is_reserved(10));
+    assert_eq!(r.commit_reservation(e), Err(RangeTreeError::Expired));
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_release_all_in() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], true);
+    for i in 0..20 {
+        r.release(i * 5);
+    }
+    assert_eq!(r.release_all_in([8, 21]), [[8, 9], [11, 14], [16, 19], [21, 21]]);
+    assert_eq!(r.slice([8, 21]).unwrap().count_untaken(), 14);
+    // nothing left to release.
+    assert!(r.release_all_in([8, 21]).is_empty());
+    assert!(r.release_all_in([21, 8]).is_empty());
+    // windows outside the bounds.
+    assert_eq!(r.release_all_in([-10, 3]), [[1, 3]]);
+    assert_eq!(r.release_all_in([96, 200]), [[96, 99]]);
+    assert!(r.release_all_in([200, 300]).is_empty());
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 3], [5, 5], [8, 21], [25, 25]].iter().cloned()
+        .chain((6..19).map(|i| [i * 5, i * 5])).chain(Some([95, 99])).collect::<Vec<_>>());
+    assert_eq!(r.validate(), Ok(()));
+
+    // pending values are released once.
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    r.set_release_deferred(Some(100));
+    r.release(5);
+    assert_eq!(r.release_all_in([0, 9]), [[0, 4], [6, 9]]);
+    assert!(r.is_empty());
+}
+
+#[test]
+fn test_take_any_from() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(r.take_any_from(5), Some(5));
+    assert_eq!(r.take_any_from(5), Some(6));
+    assert_eq!(r.take_any_from(-10), Some(0));
+    r.take(9);
+    r.take(8);
+    assert_eq!(r.take_any_from(8), Some(1));
+    for i in &[2, 3, 4, 7] {
+        assert_eq!(r.take_any_from(2), Some(*i));
+    }
+    assert_eq!(r.take_any_from(100), None);
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn test_take_random() {
+    // xorshift, to avoid depending on an entropy source.
+    struct Rng(u64);
+    impl rand_core::RngCore for Rng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+    }
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut r: RangeTree<i32> = RangeTree::new([-50, 49], false);
+    for _ in 0..100 {
+        let value = r.take_random(&mut rng).unwrap();
+        assert!((-50..=49).contains(&value));
+    }
+    assert!(r.is_full());
+    assert_eq!(r.take_random(&mut rng), None);
+
+    // only untaken values are chosen.
+    r.release(-50);
+    r.release(49);
+    let value = r.take_random(&mut rng).unwrap();
+    assert!(value == -50 || value == 49);
+
+    // the lookup tree's counts choose the same value as checking each span in order.
+    use rangetree::Backend;
+    let mut r: RangeTree<i32> = RangeTree::new([0, 999], false);
+    for i in 0..300 {
+        r.take(i * 3);
+    }
+    let mut r_list: RangeTree<i32> = RangeTree::new([0, 999], false);
+    r_list.set_backend(Backend::List);
+    for i in 0..300 {
+        r_list.take(i * 3);
+    }
+    let mut rng_list = Rng(rng.0);
+    while let Some(value) = r.take_random(&mut rng) {
+        assert_eq!(r_list.take_random(&mut rng_list), Some(value));
+    }
+    assert_eq!(r.validate(), Ok(()));
+    assert!(r_list.is_full());
+}
+
+#[test]
+fn test_kth_untaken() {
+    use rangetree::Backend;
+
+    let mut r: RangeTree<i32> = RangeTree::new([-10, 89], false);
+    for i in 0..25 {
+        r.take(i * 4 - 10);
+    }
+    let untaken: Vec<i32> = r.iter_untaken().flat_map(|range| range[0]..=range[1]).collect();
+    for backend in &[Backend::RbTree, Backend::List] {
+        r.set_backend(*backend);
+        for (k, value) in untaken.iter().enumerate() {
+            assert_eq!(r.kth_untaken(k as u128), Some(*value));
+            assert_eq!(r.rank_untaken(*value), k as u128);
+        }
+        assert_eq!(r.kth_untaken(untaken.len() as u128), None);
+        // taken values & values outside the bounds.
+        assert_eq!(r.rank_untaken(-10), 0);
+        assert_eq!(r.rank_untaken(-6), 3);
+        assert_eq!(r.rank_untaken(-100), 0);
+        assert_eq!(r.rank_untaken(100), untaken.len() as u128);
+    }
+
+    // large ranges.
+    let r: RangeTree<u64> = RangeTree::from_ranges_taken([0, u64::MAX], &[[1 << 40, 1 << 50]]).unwrap();
+    assert_eq!(r.kth_untaken(1 << 40), Some((1 << 50) + 1));
+    assert_eq!(r.rank_untaken(u64::MAX), u128::from(u64::MAX) - (1 << 50) + (1 << 40) - 1);
+}
+
+#[test]
+fn test_kth_taken() {
+    use rangetree::Backend;
+
+    let mut r: RangeTree<i32> = RangeTree::new([-10, 89], false);
+    for i in 0..25 {
+        r.take(i * 4 - 10);
+        r.take(i * 4 - 9);
+    }
+    let taken: Vec<i32> = r.iter_taken().flat_map(|range| range[0]..=range[1]).collect();
+    for backend in &[Backend::RbTree, Backend::List] {
+        r.set_backend(*backend);
+        for (k, value) in taken.iter().enumerate() {
+            assert_eq!(r.kth_taken(k as u128), Some(*value));
+            assert_eq!(r.rank_taken(*value), k as u128);
+        }
+        assert_eq!(r.kth_taken(taken.len() as u128), None);
+        // untaken values & values outside the bounds.
+        assert_eq!(r.rank_taken(-8), 2);
+        assert_eq!(r.rank_taken(-100), 0);
+        assert_eq!(r.rank_taken(100), taken.len() as u128);
+    }
+
+    // taken values at the bounds.
+    let r: RangeTree<u8> = RangeTree::from_ranges_untaken([0, 255], &[[1, 254]]).unwrap();
+    assert_eq!(r.kth_taken(0), Some(0));
+    assert_eq!(r.kth_taken(1), Some(255));
+    assert_eq!(r.rank_taken(255), 1);
+    let r: RangeTree<u64> = RangeTree::new_full_domain(true);
+    assert_eq!(r.kth_taken(u128::from(u64::MAX)), Some(u64::MAX));
+    assert_eq!(r.rank_taken(u64::MAX), u128::from(u64::MAX));
+}
+
+#[test]
+fn test_take_any_max() {
+    let mut r: RangeTree<u8> = RangeTree::new([0, 255], false);
+    for i in (250..=255).rev() {
+        assert_eq!(r.take_any_max(), Some(i));
+    }
+    r.take(249);
+    r.take(247);
+    assert_eq!(r.take_any_max(), Some(248));
+    assert_eq!(r.take_any_max(), Some(246));
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 245]]);
+    r.clear(true);
+    assert_eq!(r.take_any_max(), None);
+}
+
+#[test]
+fn test_take_any_contiguous_fit() {
+    use rangetree::{Backend, Fit};
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 29], false);
+    for i in &[5, 8, 20] {
+        r.take(*i);
+    }
+    // free: [0, 4], [6, 7], [9, 19], [21, 29]
+    assert_eq!(r.take_any_contiguous_fit(2, Fit::First), Some(0));
+    assert_eq!(r.take_any_contiguous_fit(2, Fit::Best), Some(6));
+    assert_eq!(r.take_any_contiguous_fit(2, Fit::Worst), Some(9));
+    assert_eq!(r.take_any_contiguous_fit(3, Fit::Best), Some(2));
+    assert_eq!(r.take_any_contiguous_fit(12, Fit::Worst), None);
+
+    // wide signed spans don't overflow.
+    let mut r: RangeTree<i8> = RangeTree::new([-128, 127], false);
+    assert_eq!(r.take_any_contiguous_fit(127, Fit::Best), Some(-128));
+    assert_eq!(r.take_any_contiguous_fit(127, Fit::Worst), Some(-1));
+    assert_eq!(r.ranges_untaken_as_vec(), [[126, 127]]);
+
+    // the lookup tree finds the same spans as checking every span.
+    let mut a: RangeTree<i32> = RangeTree::new([0, 299], false);
+    let mut value = 0;
+    for i in 0..40 {
+        value += (i * 7) % 9 + 1;
+        a.take(value);
+    }
+    let mut b = RangeTree::from_ranges_untaken([0, 299], &a.ranges_untaken_as_vec()).unwrap();
+    b.set_backend(Backend::List);
+    for n in &[1, 3, 2, 8, 5, 4, 9, 6, 7, 3, 50] {
+        assert_eq!(a.take_any_contiguous_fit(*n, Fit::Best), b.take_any_contiguous_fit(*n, Fit::Best));
+    }
+    assert_eq!(a.ranges_untaken_as_vec(), b.ranges_untaken_as_vec());
+    assert_eq!(a.validate(), Ok(()));
+}
+
+#[test]
+fn test_transfer() {
+    use rangetree::{
+        RangeTreeError,
+        TransferError,
+    };
+
+    let mut a: RangeTree<i32> = RangeTree::new([0, 19], true);
+    let mut b: RangeTree<i32> = RangeTree::new([10, 29], false);
+    assert_eq!(a.transfer(5, &mut b), Err(TransferError::Dest(RangeTreeError::OutOfBounds)));
+    assert_eq!(a.transfer(25, &mut b), Err(TransferError::Source(RangeTreeError::OutOfBounds)));
+    assert_eq!(a.transfer(12, &mut b), Ok(()));
+    assert_eq!(a.transfer(12, &mut b), Err(TransferError::Source(RangeTreeError::NotTaken)));
+    a.take(12);
+    assert_eq!(a.transfer(12, &mut b), Err(TransferError::Dest(RangeTreeError::AlreadyTaken)));
+    assert!(a.is_full());
+
+    assert_eq!(a.transfer_range([10, 14], &mut b),
+               Err(TransferError::Dest(RangeTreeError::AlreadyTaken)));
+    assert_eq!(a.transfer_range([14, 19], &mut b), Ok(()));
+    assert_eq!(a.transfer_range([13, 14], &mut b),
+               Err(TransferError::Source(RangeTreeError::NotTaken)));
+    assert_eq!(a.ranges_untaken_as_vec(), [[14, 19]]);
+    assert_eq!(b.ranges_taken_as_vec(), [[12, 12], [14, 19]]);
+    assert_eq!(a.count_untaken(), 6);
+    assert_eq!(b.count_taken(), 7);
+
+    // reversed ranges.
+    let mut a: RangeTree<i32> = RangeTree::new([0, 19], true);
+    let mut b: RangeTree<i32> = RangeTree::new([0, 19], true);
+    assert_eq!(a.transfer_range([10, 5], &mut b),
+               Err(TransferError::Source(RangeTreeError::InvalidRange)));
+    assert!(a.is_full());
+
+    // pending values aren't taken.
+    let mut b: RangeTree<i32> = RangeTree::new([0, 19], false);
+    a.set_release_deferred(Some(16));
+    a.release(5);
+    assert_eq!(a.transfer_range([5, 5], &mut b),
+               Err(TransferError::Source(RangeTreeError::NotTaken)));
+    a.flush();
+    assert_eq!(a.ranges_untaken_as_vec(), [[5, 5]]);
+    assert!(b.has(5));
+    assert_eq!(a.validate(), Ok(()));
+}
+
+#[test]
+fn test_estimated_time_to_exhaustion() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    assert_eq!(r.estimated_time_to_exhaustion(10), None);
+    r.set_rate_history(Some(16));
+    assert_eq!(r.estimated_time_to_exhaustion(10), None);
+
+    // 3 taken for every release, a net rate of 0.5 per modification.
+    for _ in 0..10 {
+        r.take_any();
+        r.take_any();
+        let value = r.take_any().unwrap();
+        r.release(value);
+    }
+    assert_eq!(r.count_untaken(), 80);
+    assert_eq!(r.estimated_time_to_exhaustion(8), Some(160));
+    // the window is limited to the history.
+    assert_eq!(r.estimated_time_to_exhaustion(1000), Some(160));
+
+    // releasing isn't exhausting.
+    for _ in 0..16 {
+        r.release_any();
+    }
+    assert_eq!(r.estimated_time_to_exhaustion(16), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let mut r: RangeTree<i32> = RangeTree::new([-5, 20], false);
+    for i in &[-5, 0, 1, 7] {
+        r.take(*i);
+    }
+    let text = serde_json::to_string(&r).unwrap();
+    assert_eq!(text, r#"{"bounds":[-5,20],"untaken":[[-4,-1],[2,6],[8,20]]}"#);
+
+    let mut r_copy: RangeTree<i32> = serde_json::from_str(&text).unwrap();
+    assert_eq!(r_copy.ranges_untaken_as_vec(), r.ranges_untaken_as_vec());
+    assert_eq!(r_copy.count_untaken(), r.count_untaken());
+    assert_eq!(r_copy.take_any(), Some(-4));
+    r_copy.release(0);
+    assert!(r_copy.has(0));
+
+    for text in &[
+        r#"{"bounds":[0,9],"untaken":[[5,6],[1,2]]}"#,
+        r#"{"bounds":[0,9],"untaken":[[5,10]]}"#,
+        r#"{"bounds":[0,9],"untaken":[[6,5]]}"#,
+    ] {
+        assert!(serde_json::from_str::<RangeTree<i32>>(text).is_err());
+    }
+}
+
+#[test]
+fn test_bytes() {
+    use rangetree::{
+        DecodeError,
+        RangeTreeError,
+    };
+
+    let mut r: RangeTree<i8> = RangeTree::new([-5, 20], false);
+    for i in &[-5, 0, 1, 7] {
+        r.take(*i);
+    }
+    let bytes = r.to_bytes();
+    // the format is stable: version, type size, bounds & spans (offsets from the previous value).
+    assert_eq!(bytes, [1, 1, 123, 25, 3, 1, 3, 3, 4, 2, 12]);
+    assert_eq!(RangeTree::from_bytes(&bytes), Ok(r));
+
+    // full domains & type limits.
+    let mut r: RangeTree<u128> = RangeTree::new_full_domain(false);
+    r.take(0);
+    r.take(1 << 100);
+    r.take(u128::MAX);
+    assert_eq!(RangeTree::from_bytes(&r.to_bytes()), Ok(r));
+    let r: RangeTree<i64> = RangeTree::new_full_domain(true);
+    assert_eq!(RangeTree::from_bytes(&r.to_bytes()), Ok(r));
+
+    // invalid data.
+    let r: RangeTree<u32> = RangeTree::from_ranges_untaken([10, 1000], &[[20, 30], [500, 1000]]).unwrap();
+    let bytes = r.to_bytes();
+    assert_eq!(RangeTree::<u32>::from_bytes(&[]), Err(DecodeError::Truncated));
+    for len in 0..bytes.len() {
+        assert!(RangeTree::<u32>::from_bytes(&bytes[..len]).is_err());
+    }
+    assert_eq!(RangeTree::<u32>::from_bytes(&[&bytes[..], &[0]].concat()), Err(DecodeError::InvalidData));
+    assert_eq!(RangeTree::<u32>::from_bytes(&[&[2], &bytes[1..]].concat()), Err(DecodeError::UnsupportedVersion));
+    assert_eq!(RangeTree::<u64>::from_bytes(&bytes), Err(DecodeError::TypeMismatch));
+    // a bound past the type maximum.
+    assert_eq!(RangeTree::<u8>::from_bytes(&[1, 1, 0, 0x80, 0x02, 0]), Err(DecodeError::InvalidData));
+    // overlapping spans.
+    assert_eq!(
+        RangeTree::<u8>::from_bytes(&[1, 1, 0, 9, 2, 0, 5, 0, 0]),
+        Err(DecodeError::InvalidRange(RangeTreeError::InvalidRange)),
+    );
+}
+
+#[test]
+fn test_from_ranges() {
+    use rangetree::RangeTreeError;
+
+    let r = RangeTree::from_ranges_untaken([0, 20], &[[1, 3], [4, 6], [10, 20]]).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[1, 6], [10, 20]]);
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [7, 9]]);
+    assert_eq!(r.count_untaken(), 17);
+
+    let r = RangeTree::from_ranges_taken([0, 20], &[[0, 0], [7, 9]]).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[1, 6], [10, 20]]);
+    assert_eq!(r.count_untaken(), 17);
+
+    // Taken ranges at both ends of the full domain.
+    let r: RangeTree<u8> = RangeTree::from_ranges_taken([0, 255], &[[0, 9], [250, 255]]).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[10, 249]]);
+    let r: RangeTree<u8> = RangeTree::from_ranges_taken([0, 255], &[[0, 255]]).unwrap();
+    assert!(r.is_full());
+    let r: RangeTree<u8> = RangeTree::from_ranges_untaken([0, 255], &[]).unwrap();
+    assert!(r.is_full());
+
+    assert_eq!(
+        RangeTree::from_ranges_untaken([0, 20], &[[10, 20], [1, 3]]).err(),
+        Some(RangeTreeError::InvalidRange),
+    );
+    assert_eq!(
+        RangeTree::from_ranges_untaken([0, 20], &[[1, 5], [5, 8]]).err(),
+        Some(RangeTreeError::InvalidRange),
+    );
+    assert_eq!(
+        RangeTree::from_ranges_taken([0, 20], &[[3, 2]]).err(),
+        Some(RangeTreeError::InvalidRange),
+    );
+    assert_eq!(
+        RangeTree::from_ranges_taken([0, 20], &[[15, 21]]).err(),
+        Some(RangeTreeError::OutOfBounds),
+    );
+    assert_eq!(
+        RangeTree::<i32>::from_ranges_untaken([5, 4], &[]).err(),
+        Some(RangeTreeError::InvalidRange),
+    );
+}
+
+#[test]
+fn test_display() {
+    use rangetree::DisplayOptions;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..10 {
+        r.take(i * 2);
+    }
+    assert_eq!(
+        format!("{}", r),
+        "bounds [0, 99], untaken [[1, 1], [3, 3], [5, 5], [7, 7], [9, 9], \
+         [11, 11], [13, 13], [15, 15], [17, 17], [19, 99]]",
+    );
+    let options = DisplayOptions { spans_max: 2, summary: true };
+    assert_eq!(
+        format!("{}", r.display(options)),
+        "bounds [0, 99], untaken [[1, 1], [3, 3], ... (8 more)]\n\
+         taken 10, untaken 90, spans 10 (10.0% occupied)",
+    );
+    let options = DisplayOptions { spans_max: 10, summary: false };
+    assert_eq!(format!("{}", r.display(options)), format!("{}", r));
+    let options = DisplayOptions { spans_max: 0, summary: false };
+    assert_eq!(format!("{}", r.display(options)), "bounds [0, 99], untaken [... (10 more)]");
+
+    // The alternate format is limited to the default number of spans.
+    for i in 10..50 {
+        r.take(i * 2);
+    }
+    let text = format!("{:#}", r);
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.contains("... (34 more)"));
+}
+
+#[test]
+fn test_ascii_map() {
+    fn bar(r: &RangeTree<i32>, width: usize) -> String {
+        r.ascii_map(width).to_string().lines().next().unwrap_or("").to_string()
+    }
+    let mut r: RangeTree<i32> = RangeTree::new([-10, 9], false);
+    assert_eq!(bar(&r, 4), "....");
+    r.take(-10);
+    r.take(9);
+    assert_eq!(bar(&r, 4), "+..+");
+    for i in -5..0 {
+        r.take(i);
+    }
+    assert_eq!(bar(&r, 4), "+#.+");
+    // cells of unequal size, the bar is limited to the number of values.
+    assert_eq!(bar(&r, 3), "+++");
+    assert_eq!(bar(&r, 100).len(), 20);
+    assert_eq!(&bar(&r, 20)[..6], "#....#");
+    assert_eq!(bar(&r, 0), "");
+
+    // the spans follow the bar.
+    assert_eq!(r.ascii_map(4).to_string().lines().skip(1).collect::<Vec<_>>().join("\n"), format!("{:#}", r));
+
+    let r: RangeTree<u128> = RangeTree::new_full_domain(true);
+    assert!(r.ascii_map(8).to_string().starts_with("########\n"));
+}
+
+#[test]
+fn test_dump_dot() {
+    use rangetree::Backend;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    r.take(2);
+    r.take(5);
+    let mut dot = String::new();
+    r.dump_dot(&mut dot).unwrap();
+    assert_eq!(
+        dot,
+        "digraph RangeTree {\n    \
+             graph [ordering=out];\n    \
+             node [shape=box, style=filled, fontcolor=white];\n    \
+             n0 [label=\"[3, 4]\", fillcolor=black];\n    \
+             n1 [label=\"[0, 1]\", fillcolor=black];\n    \
+             n2 [shape=point, label=\"\"];\n    \
+             n1 -> n2;\n    \
+             n3 [shape=point, label=\"\"];\n    \
+             n1 -> n3;\n    \
+             n0 -> n1;\n    \
+             n4 [label=\"[6, 9]\", fillcolor=black];\n    \
+             n5 [shape=point, label=\"\"];\n    \
+             n4 -> n5;\n    \
+             n6 [shape=point, label=\"\"];\n    \
+             n4 -> n6;\n    \
+             n0 -> n4;\n\
+         }\n",
+    );
+
+    // every span is written once.
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..20 {
+        r.take(i * 2);
+    }
+    let mut dot = String::new();
+    r.dump_dot(&mut dot).unwrap();
+    assert_eq!(dot.matches("fillcolor=").count(), r.count_ranges_untaken());
+
+    // no tree to write.
+    r.set_backend(Backend::List);
+    let mut dot = String::new();
+    r.dump_dot(&mut dot).unwrap();
+    assert!(!dot.contains("label"));
+}
+
+#[test]
+fn test_builder_const() {
+    use rangetree::{RangeTreeBuilder, RangeTreeError};
+    use rangetree::span::Span;
+
+    const RESERVED: &[Span<u16>] = &[[0, 9], [100, 100], [0xff00, 0xffff]];
+    const POOL: RangeTreeBuilder<u16> = RangeTreeBuilder::new([0, 0xffff]).reserve_ranges(RESERVED);
+    let r = POOL.build().unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[10, 99], [101, 0xfeff]]);
+
+    const RESERVED_UNSORTED: &[Span<u16>] = &[[100, 100], [0, 9]];
+    assert_eq!(
+        RangeTreeBuilder::new([0, 0xffff]).reserve_ranges(RESERVED_UNSORTED).build().err(),
+        Some(RangeTreeError::InvalidRange),
+    );
+}
+
+#[test]
+fn test_debug() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    r.take(0);
+    r.take(5);
+    assert_eq!(format!("{:?}", r), "RangeTree { bounds: [0, 9], untaken: [[1, 4], [6, 9]] }");
+    assert!(format!("{:#?}", r).contains("depth: 2,"));
+
+    let r_copy = RangeTree::from_ranges_untaken([0, 9], &[[1, 4], [6, 9]]).unwrap();
+    assert_eq!(format!("{:?}", r), format!("{:?}", r_copy));
+}
+
+#[test]
+fn test_stats() {
+    use rangetree::Growth;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    for i in 0..20 {
+        r.take(i);
+    }
+    for i in 0..15 {
+        r.release(i);
+    }
+    r.take(30);
+    r.take(33);
+    r.set_growth(Some(Growth { increment: 10, limit: 200 }));
+    r.set_journal(true);
+
+    let stats = r.stats();
+    assert_eq!(stats.domain, [0, 99]);
+    assert_eq!((stats.count_taken, stats.count_untaken, stats.count_taken_max), (7, 93, 20));
+    // Spans: [0, 14] [20, 29] [31, 32] [34, 99].
+    assert_eq!(stats.spans, 4);
+    assert_eq!((stats.span_len_min, stats.span_len_max), (2, 66));
+    assert_eq!(stats.span_histogram, [0, 1, 0, 2, 0, 0, 1]);
+    assert_eq!(
+        r.stats_json(),
+        "{\"domain\":[0,99],\"count_taken\":7,\"count_untaken\":93,\"count_taken_max\":20,\
+         \"spans\":4,\"span_len_min\":2,\"span_len_max\":66,\"span_histogram\":[0,1,0,2,0,0,1],\
+         \"growth\":{\"increment\":10,\"limit\":200},\"release_deferred\":null,\
+         \"journal\":true,\"rate_history\":null}",
+    );
+    #[cfg(feature = "serde")]
+    assert_eq!(serde_json::to_string(&stats).unwrap(), r.stats_json());
+
+    let r: RangeTree<i32> = RangeTree::new([0, 9], true);
+    let stats = r.stats();
+    assert_eq!((stats.spans, stats.span_len_min, stats.count_taken_max), (0, 0, 10));
+    assert!(stats.span_histogram.is_empty());
+
+    // Non-integer values are written as strings.
+    let mut r: RangeTree<char> = RangeTree::new(['"', '\\'], false);
+    r.set_growth(Some(Growth { increment: 1, limit: '\u{7f}' }));
+    let json: serde_json::Value = serde_json::from_str(&r.stats_json()).unwrap();
+    assert_eq!(json["domain"], serde_json::json!(["\"", "\\"]));
+    assert_eq!(json["growth"]["limit"], "\u{7f}");
+    #[cfg(feature = "serde")]
+    assert_eq!(serde_json::to_string(&r.stats()).unwrap(), r.stats_json());
+    let r: RangeTree<char> = RangeTree::new(['\u{0}', '\n'], false);
+    let json: serde_json::Value = serde_json::from_str(&r.stats_json()).unwrap();
+    assert_eq!(json["domain"], serde_json::json!(["\u{0}", "\n"]));
+}
+
+#[test]
+fn test_eq() {
+    let mut a: RangeTree<i32> = RangeTree::new([0, 9], false);
+    let mut b: RangeTree<i32> = RangeTree::new([0, 9], true);
+    assert!(a != b);
+
+    // Reach the same state with different histories.
+    for i in 0..10 {
+        a.take(i);
+    }
+    for i in (0..10).rev() {
+        a.release(i);
+    }
+    a.take(3);
+    for i in &[9, 0, 4, 6, 1, 2, 5, 8, 7] {
+        b.release(*i);
+    }
+    assert_eq!(a, b);
+
+    b.take(9);
+    assert!(a != b);
+    assert!(RangeTree::new([0, 9], false) != RangeTree::new([0, 10], false));
+}
+
+#[test]
+fn test_send_sync() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RangeTree<u32>>();
+    assert_send_sync::<rangetree::IterUntaken<u32>>();
+    assert_send_sync::<rangetree::IterTaken<u32>>();
+
+    // Move to a worker thread and back.
+    let mut r: RangeTree<u32> = RangeTree::new([0, 99], false);
+    r.take(0);
+    let r = thread::spawn(move || {
+        r.take(1);
+        r
+    }).join().unwrap();
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 1]]);
+
+    // Shared between threads.
+    let r = Arc::new(Mutex::new(r));
+    let threads: Vec<_> = (0..4).map(|_| {
+        let r = r.clone();
+        thread::spawn(move || {
+            for _ in 0..10 {
+                r.lock().unwrap().take_any().unwrap();
+            }
+        })
+    }).collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(r.lock().unwrap().ranges_taken_as_vec(), [[0, 41]]);
+
+    // Read from several threads at once.
+    let r = Arc::new(RangeTree::from_ranges_untaken([0, 99], &[[10, 19], [50, 59]]).unwrap());
+    let threads: Vec<_> = (0..4).map(|_| {
+        let r = r.clone();
+        thread::spawn(move || r.iter_untaken().count())
+    }).collect();
+    for t in threads {
+        assert_eq!(t.join().unwrap(), 2);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_shared() {
+    use std::sync::Arc;
+    use std::thread;
+    use rangetree::{RangeTreeError, SharedRangeTree};
+
+    let r: SharedRangeTree<u8> = SharedRangeTree::new([0, 255], 7);
+    r.take(0);
+    r.take(255);
+    assert_eq!(r.try_take(255), Err(RangeTreeError::AlreadyTaken));
+    assert_eq!(r.try_release(100), Err(RangeTreeError::NotTaken));
+    assert!(!r.has(0) && r.has(1));
+    r.release(0);
+    assert_eq!(r.count_untaken(), 255);
+
+    // More shards than values.
+    let r: SharedRangeTree<i32> = SharedRangeTree::new([-1, 1], 8);
+    assert_eq!((r.take_any(), r.take_any(), r.take_any(), r.take_any()).3, None);
+
+    let r: Arc<SharedRangeTree<u32>> = Arc::new(SharedRangeTree::new([0, 999], 4));
+    let threads: Vec<_> = (0..4).map(|_| {
+        let r = r.clone();
+        thread::spawn(move || {
+            (0..250).map(|_| r.take_any().unwrap()).collect::<Vec<_>>()
+        })
+    }).collect();
+    let mut values: Vec<u32> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+    values.sort();
+    assert_eq!(values, (0..1000).collect::<Vec<_>>());
+    assert_eq!(r.take_any(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_shared_reserve() {
+    use std::time::Instant;
+    use rangetree::{RangeTreeError, SharedRangeTree};
+
+    let r: SharedRangeTree<i32> = SharedRangeTree::new([0, 99], 4);
+    r.take(40);
+    // ranges across shards.
+    assert_eq!(r.reserve_range([20, 60], None).err(), Some(RangeTreeError::AlreadyTaken));
+    assert_eq!(r.reserve_range([90, 100], None).err(), Some(RangeTreeError::OutOfBounds));
+    assert_eq!(r.reserve_range([60, 20], None).err(), Some(RangeTreeError::InvalidRange));
+    assert_eq!(r.count_untaken(), 99);
+    {
+        let reservation = r.reserve_range([10, 39], None).unwrap();
+        assert_eq!(reservation.range(), [10, 39]);
+        assert_eq!(r.count_untaken(), 69);
+        assert_eq!(r.try_take(30), Err(RangeTreeError::AlreadyTaken));
+        assert_eq!(r.try_release(30), Err(RangeTreeError::Reserved));
+        assert_eq!(r.reserve(10, None).err(), Some(RangeTreeError::AlreadyTaken));
+    }
+    assert_eq!(r.count_untaken(), 99);
+
+    let reservation = r.reserve_range([41, 60], None).unwrap();
+    r.reserve(0, None).unwrap().release();
+    assert_eq!(reservation.commit(), Ok([41, 60]));
+    assert_eq!(r.count_untaken(), 79);
+    assert!(r.has(0) && !r.has(50));
+    assert_eq!(r.try_release(50), Ok(()));
+
+    // dropping releases exactly the reserved range (across shards), not its taken neighbors.
+    let r: SharedRangeTree<i32> = SharedRangeTree::new([0, 99], 4);
+    r.take(19);
+    r.take(51);
+    let taken_before: Vec<i32> = (0..100).filter(|v| !r.has(*v)).collect();
+    drop(r.reserve_range([20, 50], None).unwrap());
+    assert_eq!((0..100).filter(|v| !r.has(*v)).collect::<Vec<_>>(), taken_before);
+    assert_eq!(r.count_untaken(), 98);
+
+    // expired reservations are released, values taken afterwards aren't released by the guard.
+    let reservation = r.reserve_range([20, 30], Some(Instant::now())).unwrap();
+    assert_eq!(r.reap_reservations(), 2);
+    assert!(r.has(25));
+    r.take(25);
+    drop(reservation);
+    assert!(!r.has(25));
+    assert_eq!(r.count_untaken(), 97);
+    // expired before committing.
+    let reservation = r.reserve_range([60, 70], Some(Instant::now())).unwrap();
+    assert_eq!(reservation.commit(), Err(RangeTreeError::Expired));
+    assert_eq!(r.count_untaken(), 97);
+}
+
+#[test]
+fn test_value_without_display() {
+    use rangetree::{Count, Step};
+
+    // An ID type which deliberately doesn't implement `Display` (or arithmetic).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Id(u32);
+
+    impl Step for Id {
+        fn succ(self) -> Id { Id(self.0 + 1) }
+        fn pred(self) -> Id { Id(self.0 - 1) }
+    }
+    impl Count for Id {
+        fn count_range(range: &[Id; 2]) -> u128 { u32::count_range(&[range[0].0, range[1].0]) }
+        fn count_offset(value: Id, count: u128) -> Id { Id(u32::count_offset(value.0, count)) }
+    }
+
+    let mut r: RangeTree<Id> = RangeTree::new([Id(1), Id(10)], false);
+    assert_eq!(r.take_any(), Some(Id(1)));
+    r.take(Id(5));
+    assert_eq!(r.ranges_taken_as_vec(), [[Id(1), Id(1)], [Id(5), Id(5)]]);
+    assert_eq!(format!("{:?}", r), "RangeTree { bounds: [Id(1), Id(10)], untaken: [[Id(2), Id(4)], [Id(6), Id(10)]] }");
+}
+
+#[test]
+fn test_128_bit() {
+    let mut r: RangeTree<u128> = RangeTree::new([0, u128::MAX >> 1], false);
+    r.take(0);
+    r.take(u128::MAX >> 1);
+    r.take(u64::MAX as u128);
+    assert_eq!(r.take_any(), Some(1));
+    assert_eq!(r.count_taken(), 4);
+    // Larger than any 'u64' count.
+    assert_eq!(r.count_untaken(), (u128::MAX >> 1) - 3);
+    assert_eq!(
+        r.ranges_untaken_as_vec(),
+        [[2, u64::MAX as u128 - 1], [u64::MAX as u128 + 1, (u128::MAX >> 1) - 1]],
+    );
+    r.release(u64::MAX as u128);
+    assert_eq!(r.count_ranges_untaken(), 1);
+
+    let mut r: RangeTree<i128> = RangeTree::new([i128::MIN, i128::MAX - 1], false);
+    assert_eq!(r.count_untaken(), u128::MAX);
+    assert_eq!(r.take_any(), Some(i128::MIN));
+    r.take(0);
+    r.take(i128::MAX - 1);
+    assert_eq!(r.count_taken(), 3);
+    assert_eq!(r.count_untaken(), u128::MAX - 3);
+    assert_eq!(r.ranges_taken_as_vec(), [[i128::MIN, i128::MIN], [0, 0], [i128::MAX - 1, i128::MAX - 1]]);
+
+    // The count of every 128 bit value saturates.
+    let r: RangeTree<i128> = RangeTree::from_ranges_untaken(
+        [i128::MIN, i128::MAX],
+        &[[i128::MIN, -1], [0, i128::MAX]],
+    ).unwrap();
+    assert_eq!(r.count_untaken(), u128::MAX);
+    assert_eq!(r.count_taken(), 0);
+    let mut r: RangeTree<u128> = RangeTree::new([0, u128::MAX - 1], false);
+    r.take(5);
+    assert_eq!(r.count_taken(), 1);
+    assert_eq!(r.count_untaken(), u128::MAX - 1);
+}
+
+#[test]
+fn test_step_types() {
+    use std::num::NonZeroU32;
+
+    // 'char' steps over the surrogate range.
+    let mut r: RangeTree<char> = RangeTree::new(['\u{d7fe}', '\u{e001}'], false);
+    assert_eq!(r.count_untaken(), 4);
+    r.take('\u{d7ff}');
+    r.take('\u{e000}');
+    assert_eq!(r.ranges_untaken_as_vec(), [['\u{d7fe}', '\u{d7fe}'], ['\u{e001}', '\u{e001}']]);
+    r.release('\u{e000}');
+    assert_eq!(r.ranges_taken_as_vec(), [['\u{d7ff}', '\u{d7ff}']]);
+    r.release('\u{d7ff}');
+    assert_eq!(r.ranges_untaken_as_vec(), [['\u{d7fe}', '\u{e001}']]);
+
+    let mut r: RangeTree<char> = RangeTree::new(['a', 'z'], false);
+    assert_eq!(r.take_any(), Some('a'));
+    r.take('m');
+    assert_eq!(r.ranges_untaken_as_vec(), [['b', 'l'], ['n', 'z']]);
+
+    let nz = |v| NonZeroU32::new(v).unwrap();
+    let mut r: RangeTree<NonZeroU32> = RangeTree::new([nz(1), nz(100)], false);
+    assert_eq!(r.take_any(), Some(nz(1)));
+    r.take(nz(50));
+    assert_eq!(r.count_untaken(), 98);
+    assert_eq!(r.ranges_taken_as_vec(), [[nz(1), nz(1)], [nz(50), nz(50)]]);
+}
+
+#[test]
+fn test_full_domain() {
+    // Compare random operations on a tree over every 'u8' value against a simple table,
+    // the type limits are chosen often since they're the values which may overflow.
+    let mut seed: u32 = 0x2545_f491;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    fn ranges(untaken: &[bool; 256], state: bool) -> Vec<[u8; 2]> {
+        let mut ranges: Vec<[u8; 2]> = vec![];
+        for (i, _) in untaken.iter().enumerate().filter(|(_, s)| **s == state) {
+            match ranges.last_mut() {
+                Some(range) if range[1] as usize + 1 == i => range[1] = i as u8,
+                _ => ranges.push([i as u8, i as u8]),
+            }
+        }
+        ranges
+    }
+
+    for round in 0..20 {
+        let full = round % 2 == 0;
+        let mut r: RangeTree<u8> = RangeTree::new([0, 255], full);
+        let mut untaken = [!full; 256];
+        for _ in 0..200 {
+            let value = match random() % 4 {
+                0 => 0,
+                1 => 255,
+                _ => random() as u8,
+            };
+            let taken = match random() % 5 {
+                0 => Some(value).filter(|_| r.try_take(value).is_ok()),
+                1 => r.take_any(),
+                2 => r.take_any_max(),
+                _ => {
+                    if r.try_release(value).is_ok() {
+                        untaken[value as usize] = true;
+                    }
+                    None
+                }
+            };
+            if let Some(value) = taken {
+                assert!(untaken[value as usize]);
+                untaken[value as usize] = false;
+            }
+            assert_eq!(r.ranges_untaken_as_vec(), ranges(&untaken, true));
+            assert_eq!(r.ranges_taken_as_vec(), ranges(&untaken, false));
+            assert_eq!(r.count_untaken(), untaken.iter().filter(|s| **s).count() as u128);
+            assert_eq!(RangeTree::from_ranges_taken([0, 255], &r.ranges_taken_as_vec()).unwrap(), r);
+            assert_eq!(r.validate(), Ok(()));
+        }
+    }
+
+    let mut r: RangeTree<i8> = RangeTree::new([-128, 127], false);
+    r.take(127);
+    r.take(-128);
+    assert_eq!(r.ranges_taken_as_vec(), [[-128, -128], [127, 127]]);
+    assert_eq!(r.take_any_max(), Some(126));
+    r.release(127);
+    r.release(-128);
+    assert_eq!(r.ranges_untaken_as_vec(), [[-128, 125], [127, 127]]);
+}
+
+#[test]
+fn test_new_full_domain() {
+    let mut r: RangeTree<u32> = RangeTree::new_full_domain(false);
+    assert_eq!(r.bounds(), [0, u32::MAX]);
+    assert_eq!(r.count_untaken(), 1 << 32);
+    assert_eq!(r.take_any(), Some(0));
+    assert_eq!(r.take_any_max(), Some(u32::MAX));
+    r.take(u32::MAX - 1);
+    assert_eq!(r.ranges_untaken_as_vec(), [[1, u32::MAX - 2]]);
+    r.release(0);
+    r.release(u32::MAX);
+    r.release(u32::MAX - 1);
+    assert_eq!(r, RangeTree::new([0, u32::MAX], false));
+
+    let r: RangeTree<i64> = RangeTree::new_full_domain(true);
+    assert_eq!(r.ranges_taken_as_vec(), [[i64::MIN, i64::MAX]]);
+    assert_eq!(r.count_untaken(), 0);
+
+    let r: RangeTree<u128> = RangeTree::new_full_domain(false);
+    assert_eq!(r.count_untaken(), u128::MAX);
+
+    let r: RangeTree<char> = RangeTree::new_full_domain(false);
+    assert_eq!(r.bounds(), ['\0', char::MAX]);
+    assert_eq!(r.count_untaken(), 0x10_ffff + 1 - 0x800);
+}
+
+#[test]
+fn test_capacity() {
+    let mut r: RangeTree<i32> = RangeTree::new([-5, 4], false);
+    assert_eq!(r.capacity(), 10);
+    r.take(0);
+    // taking values doesn't change the capacity.
+    assert_eq!(r.capacity(), 10);
+    r.grow_max(9).unwrap();
+    assert_eq!((r.bounds(), r.capacity()), ([-5, 9], 15));
+    assert_eq!(RangeTree::singleton(3, true).capacity(), 1);
+
+    let r: RangeTree<u64> = RangeTree::new_full_domain(true);
+    assert_eq!(r.capacity(), 1 << 64);
+    let r: RangeTree<char> = RangeTree::new_full_domain(true);
+    assert_eq!(r.capacity(), 0x10_ffff + 1 - 0x800);
+    // saturates.
+    let r: RangeTree<i128> = RangeTree::new_full_domain(false);
+    assert_eq!(r.capacity(), u128::MAX);
+}
+
+#[test]
+fn test_occupancy() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 3], false);
+    assert_eq!(r.occupancy(), 0.0);
+    r.take(1);
+    assert_eq!(r.occupancy(), 0.25);
+    r.take_n(3);
+    assert_eq!(r.occupancy(), 1.0);
+
+    // large ranges.
+    let r: RangeTree<u64> = RangeTree::from_ranges_taken([0, u64::MAX], &[[0, u64::MAX / 2]]).unwrap();
+    assert_eq!(r.occupancy(), 0.5);
+    let r: RangeTree<u128> = RangeTree::new_full_domain(true);
+    assert_eq!(r.occupancy(), 1.0);
+}
+
+#[test]
+fn test_take_n() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    r.take(2);
+    r.take(5);
+    assert!(r.take_n(0).is_empty());
+    assert_eq!(r.take_n(4), [0, 1, 3, 4]);
+    let mut values = [0; 6];
+    assert_eq!(r.take_n_into(&mut values), 4);
+    assert_eq!(values[..4], [6, 7, 8, 9]);
+    assert!(r.is_full());
+    assert!(r.take_n(1).is_empty());
+
+    // Matches repeated `take_any`, including growth.
+    use rangetree::Growth;
+    let mut a: RangeTree<u8> = RangeTree::new([0, 3], false);
+    a.set_growth(Some(Growth { increment: 4, limit: 255 }));
+    let mut b: RangeTree<u8> = RangeTree::new([0, 3], false);
+    b.set_growth(Some(Growth { increment: 4, limit: 255 }));
+    let values = a.take_n(10);
+    assert_eq!(values, (0..10).map(|_| b.take_any().unwrap()).collect::<Vec<_>>());
+    assert_eq!(a, b);
+    assert_eq!(a.take_n(1000).len(), 246);
+}
+
+#[test]
+fn test_take_upto() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 19], false);
+    r.take(2);
+    r.take(5);
+    assert_eq!(r.take_upto(0), (0, vec![]));
+    assert_eq!(r.take_upto(4), (4, vec![[0, 1], [3, 4]]));
+    assert_eq!(r.take_upto(3), (3, vec![[6, 8]]));
+    // fewer values are available.
+    assert_eq!(r.take_upto(100), (11, vec![[9, 19]]));
+    assert!(r.is_full());
+    assert_eq!(r.take_upto(1), (0, vec![]));
+
+    // ranges too large to list values.
+    let mut r: RangeTree<u64> = RangeTree::new_full_domain(false);
+    assert_eq!(r.take_upto(1 << 40), (1 << 40, vec![[0, (1 << 40) - 1]]));
+    assert_eq!(r.take_upto(u128::MAX), ((1 << 64) - (1 << 40), vec![[1 << 40, u64::MAX]]));
+    assert!(r.is_full());
+
+    // growing.
+    use rangetree::Growth;
+    let mut r: RangeTree<u8> = RangeTree::new([0, 3], false);
+    r.set_growth(Some(Growth { increment: 4, limit: 255 }));
+    assert_eq!(r.take_upto(10), (10, vec![[0, 9]]));
+}
+
+#[test]
+fn test_release_many() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], true);
+    r.release_many(&[]);
+    r.release_many(&[50, 3, 4, 99, 2, 10, 0, 51, 5]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 0], [2, 5], [10, 10], [50, 51], [99, 99]]);
+    assert_eq!(r.count_untaken(), 9);
+    r.release_many(&[1, 98, 52]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 5], [10, 10], [50, 52], [98, 99]]);
+
+    let mut values: Vec<i32> = r.iter_taken().flat_map(|range| range[0]..=range[1]).collect();
+    values.reverse();
+    r.release_many(&values);
+    assert_eq!(r, RangeTree::new([0, 99], false));
+
+
+    // untaken, duplicate & out of bounds values are ignored (sorted or not).
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], true);
+    r.release(5);
+    r.release_many(&[4, 5, 6, 6, 100, -1]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[4, 6]]);
+    r.release_many(&[-1, 3, 4, 5, 8, 100]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[3, 6], [8, 8]]);
+    r.release_many(&[9, 7, 9, 2]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[2, 9]]);
+    assert_eq!(r.count_untaken(), 8);
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_set_operations() {
+    use rangetree::RangeTreeError;
+
+    fn tree(untaken: &[[i32; 2]]) -> RangeTree<i32> {
+        RangeTree::from_ranges_untaken([0, 20], untaken).unwrap()
+    }
+    let a = [[0, 4], [8, 10], [15, 15], [18, 20]];
+    let b = [[3, 9], [12, 16], [20, 20]];
+
+    let mut r = tree(&a);
+    r.union_with(&tree(&b)).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 10], [12, 16], [18, 20]]);
+    assert_eq!(r.count_untaken(), 19);
+
+    let mut r = tree(&a);
+    r.intersect_with(&tree(&b)).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[3, 4], [8, 9], [15, 15], [20, 20]]);
+    assert_eq!(r.count_untaken(), 6);
+
+    let mut r = tree(&a);
+    r.subtract(&tree(&b)).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 2], [10, 10], [18, 19]]);
+    assert_eq!(r.count_untaken(), 6);
+
+    // Empty and full trees.
+    let mut r = tree(&a);
+    r.union_with(&tree(&[])).unwrap();
+    r.intersect_with(&tree(&[[0, 20]])).unwrap();
+    assert_eq!(r, tree(&a));
+    r.subtract(&tree(&[[0, 20]])).unwrap();
+    assert!(r.is_full());
+
+    // Values pending from deferred releasing are flushed.
+    let mut r = tree(&a);
+    r.set_release_deferred(Some(100));
+    r.release(6);
+    r.union_with(&tree(&[])).unwrap();
+    assert!(r.has(6));
+    r.release(7);
+    r.intersect_with(&tree(&[[0, 20]])).unwrap();
+    assert!(r.has(7));
+    r.release(11);
+    r.subtract(&tree(&[])).unwrap();
+    assert!(r.has(11));
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 4], [6, 11], [15, 15], [18, 20]]);
+
+    // Different bounds.
+    let other: RangeTree<i32> = RangeTree::new([0, 19], false);
+    assert_eq!(r.union_with(&other), Err(RangeTreeError::InvalidRange));
+    assert_eq!(r.intersect_with(&other), Err(RangeTreeError::InvalidRange));
+    assert_eq!(r.subtract(&other), Err(RangeTreeError::InvalidRange));
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_split_at() {
+    use rangetree::Backend;
+
+    let r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[0, 4], [8, 12], [15, 15], [20, 20]]).unwrap();
+    let (a, b) = r.split_at(10).unwrap();
+    assert_eq!((a.bounds(), a.ranges_untaken_as_vec()), ([0, 10], vec![[0, 4], [8, 10]]));
+    assert_eq!((b.bounds(), b.ranges_untaken_as_vec()), ([11, 20], vec![[11, 12], [15, 15], [20, 20]]));
+    assert_eq!((a.count_untaken(), b.count_untaken()), (8, 4));
+    assert_eq!((a.validate(), b.validate()), (Ok(()), Ok(())));
+
+    // splitting between spans & at the minimum.
+    let (a, b) = b.split_at(13).unwrap();
+    assert_eq!((a.ranges_untaken_as_vec(), b.ranges_untaken_as_vec()), (vec![[11, 12]], vec![[15, 15], [20, 20]]));
+    let (a, b) = b.split_at(14).unwrap();
+    assert_eq!((a.bounds(), a.ranges_untaken_as_vec()), ([14, 14], vec![]));
+    assert_eq!(b.ranges_untaken_as_vec(), [[15, 15], [20, 20]]);
+
+    // settings.
+    let mut r: RangeTree<u8> = RangeTree::new([0, 9], false);
+    r.set_backend(Backend::List);
+    r.set_release_deferred(Some(100));
+    r.take(8);
+    r.release(8);
+    let (a, mut b) = r.split_at(4).unwrap();
+    assert_eq!((a.backend(), b.backend()), (Backend::List, Backend::List));
+    // pending values were released.
+    assert_eq!(b.ranges_untaken_as_vec(), [[5, 9]]);
+    b.take(5);
+    b.release(5);
+    assert!(!b.has(5));
+}
+
+#[test]
+fn test_append() {
+    use rangetree::RangeTreeError;
+
+    let r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[0, 4], [8, 12], [15, 15], [20, 20]]).unwrap();
+    let (mut a, b) = r.split_at(10).unwrap();
+    let (b, c) = b.split_at(14).unwrap();
+    a.append(b).unwrap();
+    a.append(c).unwrap();
+    assert_eq!(a.ranges_untaken_as_vec(), [[0, 4], [8, 12], [15, 15], [20, 20]]);
+    assert_eq!(a.count_untaken(), 12);
+    assert_eq!(a.validate(), Ok(()));
+
+    // taken values at the boundary.
+    let mut a: RangeTree<i32> = RangeTree::new([0, 9], true);
+    a.append(RangeTree::new([10, 19], true)).unwrap();
+    assert!(a.is_full());
+    a.append(RangeTree::from_ranges_untaken([20, 29], &[[25, 29]]).unwrap()).unwrap();
+    assert_eq!(a.ranges_taken_as_vec(), [[0, 24]]);
+
+    // bounds which don't follow.
+    let mut a: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_eq!(a.append(RangeTree::new([11, 19], false)), Err(RangeTreeError::InvalidRange));
+    assert_eq!(a.append(RangeTree::new([5, 19], false)), Err(RangeTreeError::InvalidRange));
+    assert_eq!(a.bounds(), [0, 9]);
+
+    // appending is journaled.
+    let mut a: RangeTree<i32> = RangeTree::new([0, 9], false);
+    let mut replica: RangeTree<i32> = RangeTree::new([0, 9], false);
+    a.set_journal(true);
+    a.append(RangeTree::from_ranges_untaken([10, 19], &[[12, 12]]).unwrap()).unwrap();
+    replica.apply_delta(&a.drain_journal());
+    assert_eq!(replica, a);
+}
+
+#[test]
+fn test_split_at_out_of_bounds() {
+    use rangetree::RangeTreeError;
+
+    for value in &[-1, 9, 10] {
+        let r: RangeTree<i32> = RangeTree::new([0, 9], false);
+        assert_eq!(r.split_at(*value).err(), Some(RangeTreeError::OutOfBounds));
+    }
+}
+
+#[test]
+fn test_diff() {
+    fn tree(untaken: &[[i32; 2]]) -> RangeTree<i32> {
+        RangeTree::from_ranges_untaken([0, 20], untaken).unwrap()
+    }
+    let a = tree(&[[0, 4], [8, 10], [15, 15], [18, 20]]);
+    let b = tree(&[[3, 9], [12, 16], [20, 20]]);
+
+    let (taken, released) = b.diff(&a);
+    assert_eq!(taken, [[0, 2], [10, 10], [18, 19]]);
+    assert_eq!(released, [[5, 7], [12, 14], [16, 16]]);
+
+    // applying the difference gives the same tree.
+    let mut r = tree(&[[0, 4], [8, 10], [15, 15], [18, 20]]);
+    for range in &taken {
+        for value in range[0]..=range[1] {
+            r.take(value);
+        }
+    }
+    for range in &released {
+        for value in range[0]..=range[1] {
+            r.release(value);
+        }
+    }
+    assert_eq!(r, b);
+    assert_eq!(r.diff(&b), (vec![], vec![]));
+
+    // empty and full trees.
+    assert_eq!(tree(&[]).diff(&tree(&[[0, 20]])), (vec![[0, 20]], vec![]));
+    assert_eq!(tree(&[[0, 20]]).diff(&tree(&[])), (vec![], vec![[0, 20]]));
+}
+
+#[test]
+fn test_invert() {
+    let mut r: RangeTree<i32> = RangeTree::from_ranges_taken([0, 20], &[[0, 4], [8, 10], [20, 20]]).unwrap();
+    r.invert();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 4], [8, 10], [20, 20]]);
+    assert_eq!(r.count_untaken(), 9);
+    r.invert();
+    assert_eq!(r.ranges_untaken_as_vec(), [[5, 7], [11, 19]]);
+    assert_eq!(r.count_untaken(), 12);
+
+    let mut r: RangeTree<u8> = RangeTree::new_full_domain(true);
+    r.invert();
+    assert_eq!(r, RangeTree::new_full_domain(false));
+    r.invert();
+    assert!(r.is_full());
+}
+
+#[test]
+fn test_range_map() {
+    use rangetree::{RangeMap, RangeTreeError};
+    let mut m: RangeMap<i32, &str> = RangeMap::new();
+    assert!(m.is_empty());
+    m.insert_range([10, 19], "a").unwrap();
+    m.insert_range([30, 39], "b").unwrap();
+    assert_eq!(m.get(9), None);
+    assert_eq!(m.get(10), Some(&"a"));
+    assert_eq!(m.get_range(35), Some(([30, 39], &"b")));
+    assert_eq!(m.get(25), None);
+
+    // Adjacent ranges with equal values are merged.
+    m.insert_range([20, 24], "a").unwrap();
+    assert_eq!(m.get_range(12), Some(([10, 24], &"a")));
+    m.insert_range([25, 29], "b").unwrap();
+    assert_eq!(m.get_range(39), Some(([25, 39], &"b")));
+    assert_eq!(m.len(), 2);
+
+    // Overlapping ranges are split.
+    m.insert_range([22, 32], "c").unwrap();
+    assert_eq!(m.iter().collect::<Vec<_>>(), [([10, 21], &"a"), ([22, 32], &"c"), ([33, 39], &"b")]);
+    m.insert_range([15, 16], "d").unwrap();
+    m.remove_range([30, 35]).unwrap();
+    assert_eq!(
+        m.iter().collect::<Vec<_>>(),
+        [([10, 14], &"a"), ([15, 16], &"d"), ([17, 21], &"a"), ([22, 29], &"c"), ([36, 39], &"b")],
+    );
+    m.insert_range([0, 100], "e").unwrap();
+    assert_eq!(m.iter().collect::<Vec<_>>(), [([0, 100], &"e")]);
+    // reversed ranges are rejected.
+    assert_eq!(m.insert_range([20, 10], "f"), Err(RangeTreeError::InvalidRange));
+    assert_eq!(m.remove_range([20, 10]), Err(RangeTreeError::InvalidRange));
+    assert_eq!(m.iter().collect::<Vec<_>>(), [([0, 100], &"e")]);
+    m.clear();
+    assert!(m.is_empty());
+
+    // Values are dropped once with their range.
+    use std::rc::Rc;
+    let value = Rc::new(0);
+    let mut m: RangeMap<u8, Rc<i32>> = RangeMap::new();
+    m.insert_range([0, 255], value.clone()).unwrap();
+    m.insert_range([10, 20], Rc::new(1)).unwrap();
+    assert_eq!(Rc::strong_count(&value), 3);
+    m.remove_range([0, 100]).unwrap();
+    assert_eq!(Rc::strong_count(&value), 2);
+    drop(m);
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+fn test_generational_allocator() {
+    use rangetree::{
+        GenerationalAllocator,
+        GenerationalId,
+        RangeTreeError,
+    };
+    let mut a: GenerationalAllocator<u32> = GenerationalAllocator::new([0, 2]);
+    let h0 = a.alloc().unwrap();
+    let h1 = a.alloc().unwrap();
+    assert_eq!(h0, GenerationalId { id: 0, generation: 0 });
+    assert_eq!(h1, GenerationalId { id: 1, generation: 0 });
+    assert!(a.is_valid(h0));
+
+    a.free(h0).unwrap();
+    assert!(!a.is_valid(h0));
+    assert_eq!(a.free(h0), Err(RangeTreeError::NotTaken));
+    let h0_next = a.alloc().unwrap();
+    assert_eq!(h0_next, GenerationalId { id: 0, generation: 1 });
+    assert!(!a.is_valid(h0));
+    assert!(a.is_valid(h0_next));
+
+    assert_eq!(a.alloc().unwrap().id, 2);
+    assert_eq!(a.alloc(), None);
+    assert_eq!(a.tree().count_taken(), 3);
+    assert_eq!(a.generation(1), 0);
+
+    // IDs outside the bounds.
+    let outside = GenerationalId { id: 500, generation: 0 };
+    assert!(!a.is_valid(outside));
+    assert_eq!(a.free(outside), Err(RangeTreeError::OutOfBounds));
+}
+
+#[test]
+fn test_buddy_allocator() {
+    use rangetree::{
+        BuddyAllocator,
+        RangeTreeError,
+    };
+    let mut a: BuddyAllocator<i32> = BuddyAllocator::new([-4, 27]);
+    assert_eq!(a.alloc(0), Some(-4));
+    // aligned to the minimum of the range.
+    assert_eq!(a.alloc(2), Some(0));
+    assert_eq!(a.alloc(1), Some(-2));
+    assert_eq!(a.alloc(3), Some(4));
+    assert_eq!(a.alloc(3), Some(12));
+    assert_eq!(a.alloc(4), None);
+    assert_eq!(a.alloc(0), Some(-3));
+    assert_eq!(a.order(12), Some(3));
+    assert_eq!(a.order(8), None);
+    assert_eq!(a.order(20), None);
+
+    // adjacent blocks of the same order are freed separately.
+    assert_eq!(a.free(8), Err(RangeTreeError::NotTaken));
+    assert_eq!(a.free(4), Ok(3));
+    assert_eq!(a.free(4), Err(RangeTreeError::NotTaken));
+    assert_eq!(a.order(12), Some(3));
+    assert_eq!(a.free(100), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(a.tree().ranges_taken_as_vec(), [[-4, 3], [12, 19]]);
+
+    // freed blocks merge, allowing larger blocks.
+    assert_eq!(a.free(-4), Ok(0));
+    assert_eq!(a.free(-3), Ok(0));
+    assert_eq!(a.free(-2), Ok(1));
+    assert_eq!(a.free(0), Ok(2));
+    assert_eq!(a.alloc(4), Some(-4));
+    assert_eq!(a.alloc(4), None);
+    assert_eq!(a.free(12), Ok(3));
+    assert_eq!(a.alloc(4), Some(12));
+    assert_eq!(a.alloc(0), None);
+    assert_eq!(a.tree().validate(), Ok(()));
+
+    // blocks as large as the type.
+    let mut a: BuddyAllocator<u8> = BuddyAllocator::new([0, 255]);
+    assert_eq!(a.alloc(8), Some(0));
+    assert_eq!(a.alloc(0), None);
+    assert_eq!(a.free(0), Ok(8));
+    assert_eq!(a.alloc(9), None);
+    assert_eq!(a.alloc(128), None);
+}
+
+#[test]
+fn test_range_tree_2d() {
+    use rangetree::RangeTree2d;
+    let mut r = RangeTree2d::new([8, 4]);
+    assert_eq!(r.take_rect(0, 1), None);
+    assert_eq!(r.take_rect(9, 1), None);
+    assert_eq!(r.take_rect(4, 2), Some((0, 0)));
+    assert_eq!(r.take_rect(4, 4), Some((4, 0)));
+    assert_eq!(r.take_rect(3, 2), Some((0, 2)));
+    assert_eq!(r.take_rect(2, 2), None);
+    assert_eq!(r.take_rect(1, 2), Some((3, 2)));
+    assert_eq!(r.count_untaken(), 0);
+    assert!(!r.has(3, 3));
+
+    // The rectangle must fit in every row, not only the first.
+    assert_eq!(r.release_rect(4, 0, 4, 4), Ok(()));
+    assert_eq!(r.release_rect(0, 2, 3, 2), Ok(()));
+    r.take_rect(2, 1);
+    assert_eq!(r.take_rect(3, 3), Some((4, 1)));
+    assert_eq!(r.take_rect(3, 2), Some((0, 2)));
+    assert!(r.has(7, 2));
+    assert!(!r.has(8, 0));
+    assert_eq!(r.count_untaken(), 5);
+}
+
+#[test]
+fn test_range_tree_2d_release_rect() {
+    use rangetree::{RangeTree2d, RangeTreeError};
+    let mut r = RangeTree2d::new([8, 4]);
+    assert_eq!(r.take_rect(4, 2), Some((0, 0)));
+    // partially untaken, nothing is released.
+    assert_eq!(r.release_rect(0, 0, 4, 3), Err(RangeTreeError::NotTaken));
+    assert_eq!(r.release_rect(2, 0, 4, 1), Err(RangeTreeError::NotTaken));
+    assert_eq!(r.count_untaken(), 24);
+    assert_eq!(r.release_rect(6, 0, 4, 1), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.release_rect(usize::MAX, 0, 2, 1), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.release_rect(0, usize::MAX, 1, 2), Err(RangeTreeError::OutOfBounds));
+    assert_eq!(r.release_rect(0, 0, 0, 0), Ok(()));
+    assert_eq!(r.release_rect(1, 1, 2, 1), Ok(()));
+    assert_eq!(r.release_rect(1, 1, 2, 1), Err(RangeTreeError::NotTaken));
+    assert_eq!(r.count_untaken(), 26);
+}
+
+#[test]
+fn test_untaken_in() {
+    let r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[2, 5], [10, 10], [15, 20]]).unwrap();
+    assert!(r.all_untaken_in([2, 5]));
+    assert!(r.all_untaken_in([10, 10]));
+    assert!(r.all_untaken_in([16, 19]));
+    assert!(!r.all_untaken_in([2, 6]));
+    assert!(!r.all_untaken_in([1, 5]));
+    assert!(!r.all_untaken_in([5, 10]));
+    assert!(!r.all_untaken_in([15, 21]));
+    assert!(!r.all_untaken_in([5, 2]));
+
+    assert!(r.any_untaken_in([0, 2]));
+    assert!(r.any_untaken_in([6, 10]));
+    assert!(r.any_untaken_in([-10, 100]));
+    assert!(!r.any_untaken_in([0, 1]));
+    assert!(!r.any_untaken_in([6, 9]));
+    assert!(!r.any_untaken_in([11, 14]));
+    assert!(!r.any_untaken_in([21, 30]));
+    assert!(!r.any_untaken_in([10, 2]));
+}
+
+#[test]
+fn test_untaken_neighbors() {
+    let r: RangeTree<u8> = RangeTree::from_ranges_untaken([0, 255], &[[2, 5], [10, 10], [250, 254]]).unwrap();
+    assert_eq!(r.first_untaken_at_or_after(0), Some(2));
+    assert_eq!(r.first_untaken_at_or_after(4), Some(4));
+    assert_eq!(r.first_untaken_at_or_after(6), Some(10));
+    assert_eq!(r.first_untaken_at_or_after(11), Some(250));
+    assert_eq!(r.first_untaken_at_or_after(255), None);
+
+    assert_eq!(r.last_untaken_at_or_before(255), Some(254));
+    assert_eq!(r.last_untaken_at_or_before(249), Some(10));
+    assert_eq!(r.last_untaken_at_or_before(9), Some(5));
+    assert_eq!(r.last_untaken_at_or_before(3), Some(3));
+    assert_eq!(r.last_untaken_at_or_before(1), None);
+    assert_eq!(r.last_untaken_at_or_before(0), None);
+}
+
+#[test]
+fn test_first_taken_at_or_after() {
+    let r: RangeTree<u8> = RangeTree::from_ranges_untaken([10, 255], &[[10, 12], [15, 15], [250, 255]]).unwrap();
+    assert_eq!(r.first_taken_at_or_after(0), Some(13));
+    assert_eq!(r.first_taken_at_or_after(13), Some(13));
+    assert_eq!(r.first_taken_at_or_after(14), Some(14));
+    assert_eq!(r.first_taken_at_or_after(15), Some(16));
+    assert_eq!(r.first_taken_at_or_after(249), Some(249));
+    assert_eq!(r.first_taken_at_or_after(250), None);
+
+    // Walk taken values in order.
+    let r: RangeTree<i32> = RangeTree::from_ranges_taken([0, 20], &[[2, 3], [7, 7], [20, 20]]).unwrap();
+    let mut values = vec![];
+    let mut value = Some(0);
+    while let Some(v) = value.and_then(|v| r.first_taken_at_or_after(v)) {
+        values.push(v);
+        value = if v < 20 { Some(v + 1) } else { None };
+    }
+    assert_eq!(values, [2, 3, 7, 20]);
+}
+
+#[test]
+fn test_grow_bounds() {
+    use rangetree::RangeTreeError;
+    let mut r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 9], &[[0, 2], [9, 9]]).unwrap();
+    assert_eq!(r.grow_max(5), Err(RangeTreeError::InvalidRange));
+    assert_eq!(r.grow_min(1), Err(RangeTreeError::InvalidRange));
+    r.grow_max(9).unwrap();
+    r.grow_min(0).unwrap();
+    assert_eq!(r.bounds(), [0, 9]);
+
+    // Merged with adjacent untaken ranges.
+    r.grow_max(15).unwrap();
+    r.grow_min(-5).unwrap();
+    assert_eq!(r.bounds(), [-5, 15]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[-5, 2], [9, 15]]);
+    assert_eq!(r.count_untaken(), 15);
+
+    // Not adjacent.
+    r.take(-5);
+    r.take(15);
+    r.grow_max(20).unwrap();
+    r.grow_min(-10).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[-10, -6], [-4, 2], [9, 14], [16, 20]]);
+    assert_eq!(r.count_untaken(), 23);
+
+    let mut r: RangeTree<u8> = RangeTree::new([1, 254], true);
+    r.grow_min(0).unwrap();
+    r.grow_max(255).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 0], [255, 255]]);
+}
+
+#[test]
+fn test_shrink_max() {
+    use rangetree::ShrinkError;
+    let mut r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[0, 5], [8, 12], [15, 20]]).unwrap();
+    assert_eq!(r.try_shrink_max(21), Err(ShrinkError::OutOfBounds));
+    assert_eq!(r.try_shrink_max(-1), Err(ShrinkError::OutOfBounds));
+    assert_eq!(r.try_shrink_max(10), Err(ShrinkError::Taken));
+    assert_eq!(r.bounds(), [0, 20]);
+
+    r.try_shrink_max(20).unwrap();
+    r.try_shrink_max(17).unwrap();
+    assert_eq!(r.bounds(), [0, 17]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 5], [8, 12], [15, 17]]);
+    r.try_shrink_max(14).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 5], [8, 12]]);
+    assert_eq!(r.ranges_taken_as_vec(), [[6, 7], [13, 14]]);
+
+    // Taken values are forgotten.
+    r.force_shrink_max(10).unwrap();
+    assert_eq!(r.bounds(), [0, 10]);
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 5], [8, 10]]);
+    assert_eq!(r.count_untaken(), 9);
+    r.force_shrink_max(7).unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 5]]);
+    assert_eq!(r.count_taken(), 2);
+}
+
+#[test]
+fn test_retain_taken() {
+    let mut r: RangeTree<i32> = RangeTree::from_ranges_taken([0, 20], &[[0, 9], [15, 20]]).unwrap();
+    let mut visited = vec![];
+    r.retain_taken(|value| {
+        visited.push(value);
+        value % 3 == 0 || value == 16
+    });
+    assert_eq!(visited, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 15, 16, 17, 18, 19, 20]);
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [3, 3], [6, 6], [9, 9], [15, 16], [18, 18]]);
+    assert_eq!(r.count_taken(), 7);
+
+    r.retain_taken(|_| true);
+    assert_eq!(r.count_taken(), 7);
+    r.retain_taken(|_| false);
+    assert!(r.is_empty());
+}
+
+#[test]
+fn test_into_ranges_untaken() {
+    let mut r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[0, 5], [8, 12], [15, 20]]).unwrap();
+    r.set_release_deferred(Some(16));
+    r.release(13);
+    let ranges: Vec<[i32; 2]> = r.into_ranges_untaken().collect();
+    assert_eq!(ranges, [[0, 5], [8, 13], [15, 20]]);
+
+    let r: RangeTree<i32> = RangeTree::new([0, 20], true);
+    assert_eq!(r.into_ranges_untaken().next(), None);
+}
+
+#[test]
+fn test_from_values_taken() {
+    use rangetree::RangeTreeError;
+    let r = RangeTree::from_values_taken([0, 20], vec![9, 3, 4, 5, 20, 3, 10]).unwrap();
+    assert_eq!(r.ranges_taken_as_vec(), [[3, 5], [9, 10], [20, 20]]);
+    assert_eq!(RangeTree::from_values_taken([0, 20], vec![21]), Err(RangeTreeError::OutOfBounds));
+
+    let r: RangeTree<u8> = (0..=255u8).filter(|v| v % 4 != 0).collect();
+    assert_eq!(r.bounds(), [0, 255]);
+    assert_eq!(r.count_taken(), 192);
+    assert_eq!(r.ranges_untaken_as_vec().len(), 64);
+    assert_eq!(r.iter_taken().next(), Some([1, 3]));
+
+    let r: RangeTree<u8> = None.into_iter().collect();
+    assert!(r.is_empty());
+}
+
+#[test]
+fn test_iter_untaken_rev() {
+    let r: RangeTree<i32> = RangeTree::from_ranges_untaken([0, 20], &[[0, 2], [5, 5], [8, 12], [20, 20]]).unwrap();
+    assert_eq!(r.iter_untaken().rev().collect::<Vec<_>>(), [[20, 20], [8, 12], [5, 5], [0, 2]]);
+
+    // Both ends meet in the middle.
+    let mut iter = r.iter_untaken();
+    assert_eq!(iter.next_back(), Some([20, 20]));
+    assert_eq!(iter.next(), Some([0, 2]));
+    assert_eq!(iter.next_back(), Some([8, 12]));
+    assert_eq!(iter.next(), Some([5, 5]));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+
+    let mut r = r;
+    let sub = r.sub_allocator([3, 10]).unwrap();
+    assert_eq!(sub.iter_untaken().rev().collect::<Vec<_>>(), [[8, 10], [5, 5]]);
+    let sub = r.sub_allocator([3, 4]).unwrap();
+    assert_eq!(sub.iter_untaken().next_back(), None);
+    assert!(r.sub_allocator([4, 3]).is_none());
+
+    assert_eq!(r.into_ranges_untaken().next_back(), Some([20, 20]));
+}
+
+#[test]
+fn test_overflow_policy() {
+    // Taken ranges and releases touching the type limits, for signed and unsigned types.
+    macro_rules! test_limits {
+        ($t:ty) => {{
+            let bounds = [<$t>::MIN, <$t>::MAX];
+            let mut r: RangeTree<$t> = RangeTree::new(bounds, true);
+            assert_eq!(r.ranges_taken_as_vec(), [bounds]);
+            r.release(<$t>::MAX - 1);
+            r.release(<$t>::MIN + 1);
+            assert_eq!(r.ranges_taken_as_vec(), [
+                [<$t>::MIN, <$t>::MIN],
+                [<$t>::MIN + 2, <$t>::MAX - 2],
+                [<$t>::MAX, <$t>::MAX],
+            ]);
+            r.release(<$t>::MAX);
+            r.release(<$t>::MIN);
+            assert_eq!(r.ranges_untaken_as_vec(), [[<$t>::MIN, <$t>::MIN + 1], [<$t>::MAX - 1, <$t>::MAX]]);
+            assert_eq!(r.first_taken_at_or_after(<$t>::MAX - 1), None);
+            assert_eq!(r.take_any_max(), Some(<$t>::MAX));
+            assert_eq!(r.ranges_taken_as_vec(), [[<$t>::MIN + 2, <$t>::MAX - 2], [<$t>::MAX, <$t>::MAX]]);
+        }}
+    }
+    test_limits!(u8);
+    test_limits!(i8);
+    test_limits!(u64);
+    test_limits!(i64);
+    test_limits!(u128);
+    test_limits!(i128);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_error() {
+    use rangetree::{
+        RangeTreeError,
+        TransferError,
+    };
+    fn take_pair(r: &mut RangeTree<i32>) -> Result<[i32; 2], Box<dyn std::error::Error>> {
+        Ok([r.try_take_any()?, r.try_take_any()?])
+    }
+    let mut r: RangeTree<i32> = RangeTree::new([0, 2], false);
+    assert_eq!(take_pair(&mut r).unwrap(), [0, 1]);
+    let err = take_pair(&mut r).unwrap_err();
+    assert_eq!(err.to_string(), "no untaken values remain");
+    assert_eq!(err.downcast_ref::<RangeTreeError>(), Some(&RangeTreeError::Exhausted));
+
+    assert_eq!(r.try_take(3).unwrap_err().to_string(), "value is outside the tree bounds");
+    let err = TransferError::Dest(RangeTreeError::AlreadyTaken);
+    assert_eq!(err.to_string(), "destination tree: value is already taken");
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn test_validate() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 999], false);
+    assert_eq!(r.validate(), Ok(()));
+    for i in 0..500 {
+        r.take((i * 7) % 1000);
+        assert_eq!(r.validate(), Ok(()));
+    }
+    r.release_many(&[0, 7, 14]);
+    r.invert();
+    r.grow_max(1100).unwrap();
+    r.try_shrink_max(1050).unwrap();
+    r.retain_taken(|value| value % 2 == 0);
+    assert_eq!(r.validate(), Ok(()));
+    r.clear(true);
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_large_tree() {
+    // many ranges (every other value taken), inserted & removed in both directions.
+    let n = if cfg!(miri) { 200 } else { 2000 };
+    let mut r: RangeTree<i64> = RangeTree::new([0, n * 2], false);
+    for i in 0..n {
+        r.take(i * 2);
+    }
+    assert_eq!(r.validate(), Ok(()));
+    for i in (0..n).rev() {
+        if i % 3 != 0 {
+            r.release(i * 2);
+        }
+    }
+    assert_eq!(r.validate(), Ok(()));
+    for i in 0..n {
+        if i % 3 == 0 {
+            r.release(i * 2);
+        }
+    }
+    assert_eq!(r.validate(), Ok(()));
+    assert_eq!(r.count_untaken(), (n * 2 + 1) as u128);
+}
+
+#[test]
+fn test_backend() {
+    use rangetree::{Backend, RangeTreeBuilder};
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99], false);
+    assert_eq!(r.backend(), Backend::RbTree);
+    r.set_backend(Backend::List);
+    for i in 0..50 {
+        r.take(i * 2);
+    }
+    assert_eq!(r.validate(), Ok(()));
+    assert!(!r.has(10) && r.has(11));
+    assert_eq!(r.take_any_from(51), Some(51));
+    r.release(10);
+
+    // switching rebuilds the lookup tree.
+    r.set_backend(Backend::RbTree);
+    assert_eq!(r.validate(), Ok(()));
+    assert!(r.has(10));
+    assert_eq!(r.take_any_from(51), Some(53));
+
+    let r: RangeTree<i32> = RangeTreeBuilder::new([0, 9]).backend(Backend::List).build().unwrap();
+    assert_eq!(r.backend(), Backend::List);
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 9]]);
+}
+
+#[test]
+fn test_memory_usage() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9999], false);
+    let usage = r.memory_usage();
+    assert_eq!((usage.capacity, usage.nodes, usage.nodes_free), (1024, 1, 0));
+
+    // fragment, then join all spans again.
+    for i in 0..2000 {
+        r.take(i * 2);
+    }
+    let usage = r.memory_usage();
+    assert_eq!((usage.capacity, usage.nodes, usage.nodes_free), (2048, 2000, 0));
+    for i in 0..2000 {
+        r.release(i * 2);
+    }
+    let usage_free = r.memory_usage();
+    assert_eq!((usage_free.capacity, usage_free.nodes, usage_free.nodes_free), (2048, 2000, 1999));
+    assert_eq!(usage_free.bytes, usage.bytes);
+
+    r.clear(false);
+    let usage_clear = r.memory_usage();
+    assert_eq!((usage_clear.capacity, usage_clear.nodes, usage_clear.nodes_free), (1024, 1, 0));
+    assert!(usage_clear.bytes < usage.bytes);
+}
+
+#[test]
+fn test_shrink_to_fit() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 99999], false);
+    for i in 0..10000 {
+        r.take(i * 2);
+    }
+    let usage = r.memory_usage();
+    assert_eq!(usage.nodes, 10000);
+
+    // join all spans except the first and last.
+    for i in 1..9999 {
+        r.release(i * 2);
+    }
+    let mut cursor = r.cursor_untaken();
+    r.shrink_to_fit();
+    let usage_shrink = r.memory_usage();
+    assert!(usage_shrink.capacity < usage.capacity);
+    assert!(usage_shrink.bytes < usage.bytes);
+    assert_eq!(r.validate(), Ok(()));
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [19998, 19998]]);
+    // the tree isn't modified.
+    assert_eq!(r.cursor_next(&mut cursor), Ok(Some([1, 19997])));
+
+    r.take(5);
+    r.release(5);
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_builder() {
+    use rangetree::{Backend, RangeTreeError};
+
+    let r: RangeTree<u8> = RangeTree::builder().build().unwrap();
+    assert_eq!(r.bounds(), [0, 255]);
+    assert_eq!(r.count_untaken(), 256);
+
+    let mut r: RangeTree<i32> = RangeTree::builder()
+        .bounds([-10, 10])
+        .chunk_size(2)
+        .reserve_ranges(&[[-10, -1]])
+        .build()
+        .unwrap();
+    assert_eq!(r.ranges_untaken_as_vec(), [[0, 10]]);
+    for i in 0..5 {
+        r.take(i * 2);
+    }
+    assert_eq!(r.memory_usage().capacity, 8);
+    assert_eq!(r.validate(), Ok(()));
+
+    let r: RangeTree<i32> = RangeTree::builder().bounds([0, 9]).full(true).backend(Backend::List).build().unwrap();
+    assert_eq!(r.count_untaken(), 0);
+    assert_eq!(r.backend(), Backend::List);
+
+    assert_eq!(RangeTree::<i32>::builder().bounds([9, 0]).build().err(), Some(RangeTreeError::InvalidRange));
+    assert!(RangeTree::<i32>::builder().bounds([0, 9]).chunk_size(0).build().is_ok());
+}
+
+#[test]
+fn test_reserve() {
+    use rangetree::RangeTreeError;
+
+    let mut r: RangeTree<i32> = RangeTree::builder().bounds([0, 99999]).chunk_size(16).build().unwrap();
+    r.reserve(5000);
+    let usage = r.memory_usage();
+    assert!(usage.capacity >= 5001);
+    // every other value taken adds a node for each.
+    for i in 0..5000 {
+        r.take(i * 2);
+    }
+    assert_eq!(r.memory_usage().capacity, usage.capacity);
+    assert_eq!(r.memory_usage().bytes, usage.bytes);
+    assert_eq!(r.validate(), Ok(()));
+
+    // fallible reserving.
+    assert_eq!(r.try_reserve(usize::MAX), Err(RangeTreeError::OutOfMemory));
+    assert_eq!(r.memory_usage().capacity, usage.capacity);
+    assert_eq!(r.try_reserve(10000), Ok(()));
+    let usage = r.memory_usage();
+    assert!(usage.capacity >= usage.nodes + 10000);
+}
+
+#[test]
+fn test_transaction() {
+    use rangetree::{Growth, RangeTreeError};
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    r.take(3);
+    let seq = r.seq();
+    {
+        let mut t = r.transaction();
+        assert_eq!(t.take_any(), Some(0));
+        assert_eq!(t.try_take(5), Ok(()));
+        assert_eq!(t.try_take(5), Err(RangeTreeError::AlreadyTaken));
+        assert_eq!(t.try_release(3), Ok(()));
+        assert_eq!(t.try_release(4), Err(RangeTreeError::NotTaken));
+        assert!(t.has(3) && !t.has(5));
+    }
+    assert_eq!(r.ranges_taken_as_vec(), [[3, 3]]);
+    assert_eq!(r.validate(), Ok(()));
+    assert!(r.seq() > seq);
+
+    let mut t = r.transaction();
+    t.try_take(5).unwrap();
+    t.try_release(3).unwrap();
+    t.commit();
+    assert_eq!(r.ranges_taken_as_vec(), [[5, 5]]);
+
+    // growth is undone.
+    let mut r: RangeTree<i32> = RangeTree::new([0, 1], true);
+    r.set_growth(Some(Growth { increment: 4, limit: 20 }));
+    let mut t = r.transaction();
+    assert_eq!(t.take_any(), Some(2));
+    t.rollback();
+    assert_eq!(r.bounds(), [0, 1]);
+    assert!(r.is_full());
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_take_scoped() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 2], false);
+    r.take(0);
+    {
+        let mut value = r.take_scoped().unwrap();
+        assert_eq!(value.value(), 1);
+        assert_eq!(value.tree().take_any(), Some(2));
+        assert_eq!(value.tree().take_scoped().map(|v| *v), None);
+    }
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [2, 2]]);
+
+    // an early return releases the value.
+    fn take_even(r: &mut RangeTree<i32>) -> Option<i32> {
+        let value = r.take_scoped()?;
+        if *value % 2 != 0 {
+            return None;
+        }
+        Some(value.keep())
+    }
+    r.release(0);
+    assert_eq!(take_even(&mut r), Some(0));
+    assert_eq!(take_even(&mut r), None);
+    assert!(r.has(1));
+
+    // released through the tree before the guard is dropped.
+    {
+        let mut value = r.take_scoped().unwrap();
+        let v = *value;
+        value.tree().release(v);
+    }
+    assert!(r.has(1));
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[cfg(feature = "handles")]
+#[test]
+fn test_take_handle() {
+    use std::thread;
+
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    let handles: Vec<_> = (0..4).map(|_| r.take_handle().unwrap()).collect();
+    assert_eq!(handles.iter().map(|h| h.value()).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    let mut handles = handles.into_iter();
+    assert_eq!(handles.next().unwrap().keep(), 0);
+    // dropped on another thread.
+    thread::spawn(move || drop(handles)).join().unwrap();
+    assert_eq!(r.count_taken(), 4);
+    assert_eq!(r.take_any(), Some(1));
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 1]]);
+
+    // released directly before the handle is dropped.
+    let handle = r.take_handle().unwrap();
+    r.release(*handle);
+    drop(handle);
+    r.flush();
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 1]]);
+    assert_eq!(r.validate(), Ok(()));
+
+    // handles outliving a clear don't release values taken again.
+    let handle = r.take_handle().unwrap();
+    assert_eq!(*handle, 2);
+    r.clear(false);
+    assert_eq!(r.take_handle().map(|h| h.keep()), Some(0));
+    r.take(1);
+    r.take(2);
+    drop(handle);
+    r.flush();
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 2]]);
+
+    // the same for invert.
+    let handle = r.take_handle().unwrap();
+    r.invert();
+    r.take(*handle);
+    drop(handle);
+    r.flush();
+    assert_eq!(r.ranges_taken_as_vec(), [[3, 9]]);
+    assert_eq!(r.validate(), Ok(()));
+
+    // any mutation releases dropped handles first.
+    let handle = r.take_handle().unwrap();
+    assert_eq!(*handle, 0);
+    drop(handle);
+    r.take(0);
+    let handle = r.take_handle().unwrap();
+    assert_eq!(*handle, 1);
+    let handle_max = r.take_handle().unwrap();
+    assert_eq!(*handle_max, 2);
+    drop(handle_max);
+    assert_eq!(r.take_any_max(), Some(2));
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 9]]);
+    drop(handle);
+    assert_eq!(r.take_any_max(), Some(1));
+    assert_eq!(r.validate(), Ok(()));
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    // nothing is recorded until enabled.
+    r.take(9);
+    assert_eq!(r.undo(1), 0);
+
+    r.set_undo(true);
+    r.set_journal(true);
+    assert_eq!(r.take_any(), Some(0));
+    r.take(5);
+    r.release(9);
+    r.release_many(&[0, 5]);
+    r.invert();
+    assert!(r.is_full());
+
+    // the invert & release of 5.
+    assert_eq!(r.undo(2), 2);
+    assert_eq!(r.ranges_taken_as_vec(), [[5, 5]]);
+    assert_eq!(r.undo(10), 4);
+    assert_eq!(r.ranges_taken_as_vec(), [[9, 9]]);
+    assert_eq!(r.redo(2), 2);
+    assert_eq!(r.ranges_taken_as_vec(), [[0, 0], [5, 5], [9, 9]]);
+    assert_eq!(r.validate(), Ok(()));
+
+    // undoing is journaled, so a replica stays in sync.
+    let mut replica: RangeTree<i32> = RangeTree::new([0, 9], false);
+    replica.take(9);
+    replica.apply_delta(&r.drain_journal());
+    assert_eq!(replica, r);
+    assert!(r.drain_journal().is_empty());
+
+    // a new modification discards the operations to redo.
+    r.take(1);
+    assert_eq!(r.redo(1), 0);
+    // clearing can't be undone.
+    r.clear(false);
+    assert_eq!(r.undo(1), 0);
+
+    r.set_undo(false);
+    r.take(1);
+    assert_eq!(r.undo(1), 0);
+}
+
+#[test]
+fn test_observer() {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+    use rangetree::SpanEvent;
+
+    let mut r: RangeTree<u32> = RangeTree::new([0, 9], false);
+    let events = Arc::new(Mutex::new(vec![]));
+    let events_observer = events.clone();
+    r.set_observer(move |event| events_observer.lock().unwrap().push(event));
+    let events_take = || events.lock().unwrap().drain(..).collect::<Vec<SpanEvent<u32>>>();
+
+    // split.
+    r.take(4);
+    assert_eq!(events_take(), [
+        SpanEvent::Resized { old: [0, 9], new: [0, 3] },
+        SpanEvent::Added([5, 9]),
+    ]);
+    // merge.
+    r.release(4);
+    assert_eq!(events_take(), [
+        SpanEvent::Resized { old: [0, 3], new: [0, 9] },
+        SpanEvent::Removed([5, 9]),
+    ]);
+    r.take_any_contiguous(10);
+    assert_eq!(events_take(), [SpanEvent::Removed([0, 9])]);
+    r.release(9);
+    assert_eq!(events_take(), [SpanEvent::Added([9, 9])]);
+
+    r.clear(false);
+    assert_eq!(events_take(), [SpanEvent::Cleared, SpanEvent::Added([0, 9])]);
+
+    r.remove_observer();
+    r.take(0);
+    assert!(events_take().is_empty());
+}
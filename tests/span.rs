@@ -0,0 +1,40 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+extern crate rangetree;
+
+use rangetree::span;
+
+#[test]
+fn test_span_overlap() {
+    assert!(span::overlaps(&[0, 4], &[4, 8]));
+    assert!(span::overlaps(&[0, 9], &[4, 5]));
+    assert!(!span::overlaps(&[0, 3], &[4, 8]));
+
+    assert!(span::is_adjacent(&[0, 3], &[4, 8]));
+    assert!(span::is_adjacent(&[4, 8], &[0, 3]));
+    assert!(!span::is_adjacent(&[0, 4], &[4, 8]));
+    assert!(!span::is_adjacent(&[0, 2], &[4, 8]));
+    assert!(span::is_adjacent(&[0_u8, 254], &[255, 255]));
+    assert!(!span::is_adjacent(&[255_u8, 255], &[255, 255]));
+
+    assert!(span::contains(&[-2, 2], &-2));
+    assert!(!span::contains(&[-2, 2], &3));
+    assert_eq!(span::len(&[i8::MIN, i8::MAX]), 256);
+}
+
+#[test]
+fn test_span_clamp_split_merge() {
+    assert_eq!(span::clamp(&[0, 9], &[5, 20]), Some([5, 9]));
+    assert_eq!(span::clamp(&[0, 4], &[5, 20]), None);
+
+    assert_eq!(span::split(&[0, 9], 5), (Some([0, 4]), Some([5, 9])));
+    assert_eq!(span::split(&[0, 9], 0), (None, Some([0, 9])));
+    assert_eq!(span::split(&[0, 9], 10), (Some([0, 9]), None));
+    assert_eq!(span::split(&[0_u8, 255], 0), (None, Some([0, 255])));
+    assert_eq!(span::split(&[0_u8, 255], 255), (Some([0, 254]), Some([255, 255])));
+
+    assert_eq!(span::merge(&[0, 3], &[4, 9]), Some([0, 9]));
+    assert_eq!(span::merge(&[5, 9], &[0, 6]), Some([0, 9]));
+    assert_eq!(span::merge(&[0, 2], &[4, 9]), None);
+}
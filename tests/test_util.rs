@@ -0,0 +1,38 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2016
+
+#![cfg(feature = "test-util")]
+
+#[macro_use]
+extern crate rangetree;
+
+use rangetree::RangeTree;
+
+#[test]
+fn test_assert_state() {
+    let mut r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_state!(r, taken: [], untaken: [[0, 9]]);
+    r.take(0);
+    r.take(5);
+    assert_state!(r, taken: [[0, 0], [5, 5]], untaken: [[1, 4], [6, 9]]);
+    assert_state!(r, untaken: [[1, 4], [6, 9]]);
+}
+
+#[test]
+fn test_assert_state_diff() {
+    let text = rangetree::test_util::ranges_diff(
+        "taken", &[[0, 0], [5, 6]], &[[0, 0], [5, 5], [8, 9]],
+    ).unwrap();
+    assert_eq!(text, "taken ranges differ (-expected, +actual):\n  \
+                      [0, 0]\n\
+                      - [5, 5]\n\
+                      + [5, 6]\n\
+                      - [8, 9]\n");
+}
+
+#[test]
+#[should_panic(expected = "+ [0, 9]")]
+fn test_assert_state_fail() {
+    let r: RangeTree<i32> = RangeTree::new([0, 9], false);
+    assert_state!(r, untaken: [[0, 8]]);
+}